@@ -0,0 +1,25 @@
+#!/bin/sh
+# Counts the write syscalls city makes per frame on a pseudo terminal, the same count
+# `strace -c -e trace=write,writev` gives, read from /proc/<pid>/io so it runs without strace
+# (Linux only). Arguments go to city, like --write-chunk 4096:
+#
+#   cargo build --release && scripts/count-writes.sh --write-chunk 4096
+set -e
+city=${CITY:-target/release/city}
+secs=${SECS:-4}
+log=$(mktemp)
+trap 'rm -f "$log"' EXIT
+
+script -qfc "stty cols 200 rows 50; exec $city --seed 3 --fps 30 $*" "$log" >/dev/null &
+sleep 1
+pid=$(pgrep -P $! -n)
+frames() { grep -ao 'real fps' "$log" | wc -l; }
+writes() { awk '/^syscw/ { print $2 }' "/proc/$pid/io"; }
+
+f0=$(frames) w0=$(writes)
+sleep "$secs"
+f1=$(frames) w1=$(writes)
+kill -INT "$pid"
+wait
+
+echo "$((f1 - f0)) frames, $((w1 - w0)) writes, $(echo "$w1 $w0 $f1 $f0" | awk '{ printf "%.2f", ($1 - $2) / ($3 - $4) }') per frame"
@@ -0,0 +1,126 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::city::Tick;
+use crate::paths;
+
+const FILE_NAME: &str = "bookmarks.txt";
+
+/// Everything needed to relaunch the city at a bookmarked frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub seed: u64,
+    pub tick: Tick,
+    pub width: usize,
+    pub height: usize,
+    pub step: Tick,
+    pub layer_seeds: bool,
+    pub legacy_spawn: bool,
+}
+
+impl fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seed={} tick={} size={}x{} step={} layer-seeds={} legacy-spawn={}",
+               self.seed, self.tick, self.width, self.height, self.step, self.layer_seeds, self.legacy_spawn)
+    }
+}
+
+impl FromStr for Bookmark {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn value<T: FromStr>(v: &str, key: &str) -> Result<T, String> {
+            v.parse().map_err(|_| format!("bad {} value '{}'", key, v))
+        }
+
+        let (mut seed, mut tick, mut size, mut step, mut layer_seeds) = (None, None, None, None, false);
+        // bookmarks without the key were saved before spawning went by density
+        let mut legacy_spawn = true;
+
+        for pair in s.split_whitespace() {
+            let (key, v) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => return Err(format!("expected key=value, got '{}'", pair)),
+            };
+
+            match key {
+                "seed" => seed = Some(value(v, key)?),
+                "tick" => tick = Some(value(v, key)?),
+                "step" => step = Some(value(v, key)?),
+                "layer-seeds" => layer_seeds = value(v, key)?,
+                "legacy-spawn" => legacy_spawn = value(v, key)?,
+                "size" => {
+                    let mut wh = v.splitn(2, 'x');
+                    let w = value(wh.next().unwrap_or(""), key)?;
+                    let h = value(wh.next().unwrap_or(""), key)?;
+                    size = Some((w, h));
+                }
+                _ => {} // unknown keys are kept for newer versions
+            }
+        }
+
+        match (seed, tick, size, step) {
+            (Some(seed), Some(tick), Some((width, height)), Some(step)) =>
+                Ok(Bookmark { seed, tick, width, height, step, layer_seeds, legacy_spawn }),
+            _ => Err("missing seed, tick, size or step".to_string()),
+        }
+    }
+}
+
+pub fn path() -> io::Result<PathBuf> {
+    paths::data_dir()
+        .map(|d| d.join(FILE_NAME))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "neither XDG_DATA_HOME nor HOME is set"))
+}
+
+/// Appends bookmark and returns its number as shown by `list`
+pub fn append(b: &Bookmark) -> io::Result<usize> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let count = load()?.len();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", b)?;
+    Ok(count + 1)
+}
+
+/// Bookmarks in file order, numbered from 1. A missing file means no bookmarks
+pub fn load() -> io::Result<Vec<Bookmark>> {
+    let path = path()?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .enumerate()
+        .map(|(i, l)| l.parse().map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), i + 1, e))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for legacy_spawn in [false, true] {
+            let b = Bookmark { seed: 7, tick: 1234, width: 80, height: 24, step: 2, layer_seeds: true, legacy_spawn };
+            assert_eq!(b.to_string().parse(), Ok(b));
+        }
+    }
+
+    #[test]
+    fn old_bookmarks_spawn_the_legacy_way() {
+        let b: Bookmark = "seed=7 tick=1234 size=80x24 step=2 layer-seeds=false".parse().unwrap();
+        assert!(b.legacy_spawn);
+    }
+}
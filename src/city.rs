@@ -1,17 +1,46 @@
+use crate::effect::{Effect, EffectCanvas, EffectCtx, EffectPhase, StarsEffect, SHOOTING_SALT};
+use crate::profile::{PhaseClock, TickTimes};
+use crate::stamp::{Stamp, StampCell};
 use crate::vec2d::Vec2D;
-use std::collections::VecDeque;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use fastrand::Rng;
 use arrayvec::ArrayVec;
+use std::fmt;
 use std::mem;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub type WHSize = (usize, usize);
 pub type PaletteColor = usize;
 
+pub const FG_DEFAULT: PaletteColor = 39;
+const RGB_COLOR: PaletteColor = 1 << 24; // flag of colors given as RGB instead of an SGR code
+
 pub type Tick = u32;
+pub type BuildingId = u64;
 pub const TICK_WRAP: Tick = Tick::MAX / 4;
 pub const PROBABILITY_CURVE: f32 = 2.5;
+// xterm's default colors 30..37 and 90..97, and the levels of each channel in its color cube
+const XTERM_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255),
+    (255, 255, 255),
+];
+const XTERM_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Narrowest canvas with full size buildings, narrower ones scale them down
+pub const GEOMETRY_FULL_W: usize = 50;
 
 const COLLISION_GAP: usize = 2;
+const SPAWN_CAP_DEPTH: usize = 3;
+const TARGET_GAIN: f32 = 0.02; // spawn chance change per spawn opportunity, at a count off by the whole target
+const BUILDING_MIN_W: usize = 6;
+const BUILDING_MAX_W: usize = 25;
+const LOOP_SETTLE_ROUNDS: usize = 16;
+const OUTRO_LIGHTS_END: f32 = 0.6; // outro progress by which every window is dark
+const OUTRO_FADE_START: f32 = 0.4; // ^ at which buildings start fading out
+const MINIMAP_BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
 const ROOF_GAP_X: usize = 2;
 const ROOF_GAP_Y: usize = 1;
 const WINDOW_X: usize = 2;
@@ -22,6 +51,164 @@ const WINDOW_PAD_L: usize = 3;
 const WINDOW_PAD_R: usize = 4;
 const WINDOW_SPC_Y: usize = 1;
 const WINDOW_SPC_X: usize = 2;
+const RISE_GROW_TICKS: Tick = 30;
+const RISE_STAND_TICKS: (Tick, Tick) = (100, 300);
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+const FACE_GRADIENT_STEPS: f32 = 8.0; // shades from the base color to white or black, keeps sixel palettes small
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: PaletteColor,
+    pub bg: PaletteColor,
+}
+
+impl Cell {
+    /// Empty cell showing only the background color
+    #[inline]
+    pub const fn blank(bg: PaletteColor) -> Cell {
+        Cell { glyph: ' ', fg: FG_DEFAULT, bg }
+    }
+
+    /// Foreground SGR code matching a background one (e.g. 47 -> 37, 101 -> 91)
+    #[inline]
+    pub const fn fg_of(bg: PaletteColor) -> PaletteColor {
+        bg - 10
+    }
+
+    /// Background color given as RGB, shown as the nearest one on terminals without 24-bit color
+    pub const fn rgb(r: u8, g: u8, b: u8) -> PaletteColor {
+        RGB_COLOR | (r as usize) << 16 | (g as usize) << 8 | b as usize
+    }
+
+    /// SGR parameters selecting a color in the encoding of `depth`: `48;2;r;g;b` for ones
+    /// from `rgb` on truecolor terminals, the nearest `48;5;n` or background code on others
+    pub fn sgr(color: PaletteColor, depth: ColorDepth) -> impl fmt::Display {
+        struct Sgr(PaletteColor, ColorDepth);
+        impl fmt::Display for Sgr {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if self.0 & RGB_COLOR == 0 {
+                    return write!(f, "{}", self.0);
+                }
+                let (r, g, b) = Cell::rgb_of(self.0);
+                match self.1 {
+                    ColorDepth::TrueColor => write!(f, "48;2;{};{};{}", r, g, b),
+                    depth => write!(f, "{}{}", if depth == ColorDepth::Ansi256 { "48;5;" } else { "" },
+                                    nearest_cached(self.0, depth)),
+                }
+            }
+        }
+        Sgr(color, depth)
+    }
+
+    /// RGB of a foreground or background SGR code in xterm's default palette,
+    /// default colors are taken as light gray on black
+    pub const fn rgb_of(color: PaletteColor) -> (u8, u8, u8) {
+        if color & RGB_COLOR != 0 {
+            return ((color >> 16) as u8, (color >> 8) as u8, color as u8);
+        }
+        let fg = match color {
+            49 => return (0, 0, 0),
+            40..=47 | 100..=107 => color - 10,
+            c => c,
+        };
+        match fg {
+            30..=37 => XTERM_16[fg - 30],
+            39 => XTERM_16[7],
+            90..=97 => XTERM_16[fg - 90 + 8],
+            _ => (0, 0, 0),
+        }
+    }
+}
+
+/// Colors a terminal can show, picked once at startup (see `console::term_color_depth`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "16" => Ok(ColorDepth::Ansi16),
+            "256" => Ok(ColorDepth::Ansi256),
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            _ => Err(format!("unknown colors '{}', expected 16, 256 or truecolor", s)),
+        }
+    }
+}
+
+impl fmt::Display for ColorDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorDepth::Ansi16 => "16",
+            ColorDepth::Ansi256 => "256",
+            ColorDepth::TrueColor => "truecolor",
+        })
+    }
+}
+
+/// Weighted squared distance between colors, close to how different they look
+/// ("redmean", weights of red and blue shift with how red they are)
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let red_mean = (r1 as i32 + r2 as i32) / 2;
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+    ((((512 + red_mean) * d(r1, r2)) >> 8) + 4 * d(g1, g2) + (((767 - red_mean) * d(b1, b2)) >> 8)) as u32
+}
+
+/// RGB of a color of xterm's 256 color palette: the 16 basic colors, a 6x6x6 cube and a ramp of
+/// grays. Colors of RGB terminals are matched to the same ones, see `nearest_256` and `nearest_16`
+pub const fn xterm_256_rgb(i: u8) -> (u8, u8, u8) {
+    match i {
+        0..=15 => XTERM_16[i as usize],
+        16..=231 => {
+            let i = (i - 16) as usize;
+            (XTERM_LEVELS[i / 36], XTERM_LEVELS[i / 6 % 6], XTERM_LEVELS[i % 6])
+        }
+        _ => {
+            let gray = 8 + 10 * (i - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Index of the color of xterm's 256 color palette nearest to `rgb`, from its cube or gray ramp.
+/// The first 16 are left out, terminals change them
+pub fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |v: u8| (0..6).min_by_key(|&l| (XTERM_LEVELS[l] as i32 - v as i32).abs()).unwrap() as u8;
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+
+    // grays go from 8 to 238 in steps of 10
+    let avg = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray = 232 + (avg.saturating_sub(3) / 10).min(23) as u8;
+    match distance(rgb, xterm_256_rgb(gray)) < distance(rgb, xterm_256_rgb(cube)) {
+        true => gray,
+        false => cube,
+    }
+}
+
+/// Background code of the 16 colors nearest to `rgb` in xterm's default palette
+pub fn nearest_16(rgb: (u8, u8, u8)) -> PaletteColor {
+    (40..=47).chain(100..=107).min_by_key(|&c| distance(rgb, Cell::rgb_of(c))).unwrap()
+}
+
+/// `nearest_256` or `nearest_16` of an RGB color for terminals with `depth` colors. Each color
+/// is matched once, frames only have a few (shades of walls)
+fn nearest_cached(color: PaletteColor, depth: ColorDepth) -> PaletteColor {
+    thread_local! {
+        static NEAREST: RefCell<HashMap<(PaletteColor, ColorDepth), PaletteColor>> = RefCell::default();
+    }
+    NEAREST.with(|nearest| *nearest.borrow_mut().entry((color, depth)).or_insert_with(|| {
+        match depth {
+            ColorDepth::Ansi256 => nearest_256(Cell::rgb_of(color)) as PaletteColor,
+            _ => nearest_16(Cell::rgb_of(color)),
+        }
+    }))
+}
 
 #[derive(Debug)]
 pub struct City<'a> {
@@ -29,267 +216,3253 @@ pub struct City<'a> {
     size: WHSize,
     step: Tick,
     tick: Tick,
+    drawn_tick: Tick, // the tick the canvas shows
     background: PaletteColor,
     layers_desc: &'a [LayerDesc],
     layers: Vec<Layer>,
-    canvas: Vec2D<PaletteColor>,
+    canvas: Vec2D<Cell>,
+    skyline: Vec<usize>,
+    dirty: DirtyRows,
+    back: Option<(Vec2D<Cell>, DirtyRows)>, // previous frame's canvas if double buffered
+    looping: Option<LoopSpawn>,
+    world_w: Option<usize>, // buildings spawn and leave at the world edges, screen width if None
+    view_x: Option<usize>, // world column at the left screen edge, right end of the world if None
+    shard: Option<(Shard, u64)>, // with the seed spawns are drawn from, overrides world_w and view_x
+    palette_shift: (usize, bool), // steps window colors are rotated by and whether unlit ones stay put
+    mirror: bool, // only the left half is simulated, the right half shows it reversed
+    rising: bool, // buildings rise and sink in place instead of scrolling
+    horizon: Option<Horizon>,
+    grid: Option<Grid>, // debug rulers drawn over the buildings
+    sky: Option<Sky>, // gradient drawn instead of the background
+    stars: Option<Stars>, // the stars effect was set from, it's set again on reseed
+    effects: Vec<EffectSlot>, // in drawing order
+    motion: MotionProfile,
+    motion_blur: f32, // share of the previous frame in changed cells, 0 for none
+    dither: bool, // face gradients mix neighboring shades, and skies set by colors dither
+    outro: f32, // progress of the shutdown animation, 0 while running normally
+    legacy_spawn: bool, // density is the raw chance of each spawn opportunity, see set_legacy_spawn
+    despawn_margin: usize, // cells past the left world edge buildings are kept for
+    next_id: BuildingId,
+    geometry: Geometry, // follows the canvas width
+    visible: Vec<VisibleBuilding>, // buildings drawn in the last frame, in drawing order
+    tick_times: Option<TickTimes>, // of drawn ticks since they were last taken, while profiling
+}
+
+/// Building drawn on the canvas, the part of it inside the canvas (the left half if mirrored)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VisibleBuilding {
+    pub id: BuildingId, // unique in the city and kept until the building leaves, counting up in spawn order
+    pub layer: usize,
+    pub pos: (usize, usize),
+    pub size: WHSize,
+    pub offset: (usize, usize), // cells of the building left of and above the drawn part
+}
+
+/// What the inspector shows of a building
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildingInfo {
+    pub id: BuildingId,
+    pub layer: usize,
+    pub size: WHSize, // whole building, including rows above the canvas
+    pub spawn_tick: Tick,
+    pub color: PaletteColor,
+    pub seed: u64,
+}
+
+/// Column span painted in each canvas row since its last wipe, empty when start >= end
+type DirtyRows = Vec<(usize, usize)>;
+
+#[derive(Debug, Clone, Copy)]
+struct LoopSpawn {
+    period: Tick,
+    seed: u64,
+}
+
+impl LoopSpawn {
+    /// Random stream for spawn at `tick % period` on a layer
+    fn rng_at(&self, layer_i: usize, tick: Tick) -> Rng {
+        tick_rng(self.seed, layer_i, tick % self.period)
+    }
+
+    /// Spawn decisions for each tick of the period. Collision depends on previous spawns,
+    /// so the period is replayed until it's consistent with its own tail
+    fn schedule(&self, d: &LayerDesc, layer_i: usize, step: Tick, legacy: bool, geometry: &Geometry) -> Vec<bool> {
+        let period = self.period as usize;
+        let speed = d.speed as usize;
+        let mut schedule = vec![false; period];
+        let mut recent: VecDeque<(usize, usize)> = VecDeque::new(); // spawn tick, reach
+        let (free_chance, blocked_chance) = spawn_chances(d, step, legacy, geometry);
+        let (min_w, max_w) = geometry.building_w;
+
+        for round in 0..LOOP_SETTLE_ROUNDS {
+            let mut changed = false;
+
+            for i in (0..period).step_by(speed) {
+                let t = round * period + i;
+                let travelled = |s: usize| (t - s) * step as usize / speed;
+
+                // building's right edge is beyond the screen edge while its reach is ahead
+                recent.retain(|&(s, reach)| reach > travelled(s));
+                let chance = if recent.is_empty() { free_chance } else { blocked_chance };
+
+                let rng = self.rng_at(layer_i, i as Tick);
+                let spawn = rng.f32() < chance;
+                if spawn {
+                    // same draw order as in advance
+                    if d.wall_color.len() > 1 {
+                        pick_weighted(&rng, &d.wall_weights, d.wall_color.len());
+                    }
+                    let size_x = rng.usize(min_w..=max_w);
+                    recent.push_back((t, size_x + COLLISION_GAP));
+                }
+
+                changed |= schedule[i] != spawn;
+                schedule[i] = spawn;
+            }
+
+            if round > 0 && !changed {
+                break;
+            }
+        }
+
+        schedule
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct LayerDesc {
-    pub density: f32, // 0.0 (min) .. 1.0 (max)
-    pub collision: f32, // ^ same
+    pub density: f32, // 0.0 (empty) .. 1.0 (buildings side by side), share of a screen width taken by buildings
+    pub collision: f32, // 0.0 (min) .. 1.0 (max), chance to spawn over a building still entering
     pub speed: Tick, // move each N ticks: 1 (faster) .. inf (slower)
     pub wall_color: ArrayVec<[PaletteColor; 32]>,
+    pub wall_weights: ArrayVec<[u32; 32]>, // relative chance of each wall color, empty for uniform
     pub draw_windows: bool,
-    pub window_colors: ArrayVec<[PaletteColor; 32]>,
+    pub window_colors: ArrayVec<[WindowColor; 32]>,
+    pub window_weights: ArrayVec<[u32; 32]>, // ^ same for window colors
+    pub window_glyph: Option<char>, // draw windows as this glyph colored by window color over the wall
+    pub age_fade: f32, // 0.0 (off) .. 1.0 (all windows unlit at the left edge)
+    pub spawn_jitter: usize, // spawn up to N cells beyond the right edge, 0 spawns exactly at it
+    pub grow_ticks: Tick, // ticks new buildings take to rise to full height, 0 for no growing
+    pub face_gradient: f32, // -1.0 .. 1.0, shade walls lighter at the top and darker at the base, negative flips it, 0 is flat
+    pub allow_clip: bool, // let buildings be a bit taller than the screen, false keeps them fully visible
+    pub stamps: Vec<Stamp>, // fixed shapes to spawn instead of random rectangles
+    pub stamp_chance: f32, // 0.0 (never) .. 1.0 (always when stamps are set)
+    pub sink: usize, // stand buildings this many rows below the bottom, so only their tops show
+    pub target_count: Option<usize>, // spawn to keep about this many buildings, density only sets the start
 }
 
-#[derive(Debug, Clone, Default)]
-struct Layer {
-    ring: VecDeque<Building>,
-    rightmost_building_rcx: usize,
+/// Color of a window, fixed or a shade of the wall of the building it's in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowColor {
+    Fixed(PaletteColor),
+    Wall,
+    WallLight,
+    WallDark,
 }
 
-#[derive(Debug, Clone)]
-struct Building {
-    size_x: usize,
-    size_y: usize,
-    spawn_tick: Tick,
-    color: PaletteColor,
-    seed: u64,
-}
+const WINDOW_COLOR_NAMES: [(&str, WindowColor); 3] = [
+    ("wall", WindowColor::Wall),
+    ("wall-light", WindowColor::WallLight),
+    ("wall-dark", WindowColor::WallDark),
+];
 
-impl<'a> City<'a> {
-    pub fn new(
-        width: usize,
-        height: usize,
-        step: Tick,
-        rng: &'a Rng,
-        bg_color: PaletteColor,
-        layers: &'a [LayerDesc],
-    ) -> City<'a> {
-        City {
-            rng, step,
-            size: (width, height),
-            tick: 1,
-            background: bg_color,
-            canvas: Vec2D::new(width, height, || bg_color),
-            layers: vec![Layer::default(); layers.len()],
-            layers_desc: layers,
+impl WindowColor {
+    /// Color code in a building with this wall color
+    pub fn resolve(self, wall: PaletteColor) -> PaletteColor {
+        match self {
+            WindowColor::Fixed(color) => color,
+            WindowColor::Wall => wall,
+            WindowColor::WallLight => match wall {
+                40 => 100,
+                41..=46 => wall + 60,
+                47 | 101..=107 => 107,
+                100 => 47,
+                _ => wall,
+            },
+            WindowColor::WallDark => match wall {
+                40..=46 => 40,
+                47 => 100,
+                100 => 40,
+                101..=106 => wall - 60,
+                107 => 47,
+                _ => wall,
+            },
         }
     }
+}
 
-    #[inline]
-    pub fn get_size(&self) -> WHSize {
-        self.size
+impl FromStr for WindowColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match WINDOW_COLOR_NAMES.iter().find(|(name, _)| *name == s.trim()) {
+            Some(&(_, color)) => Ok(color),
+            None => parse_color(s).map(WindowColor::Fixed),
+        }
     }
+}
 
-    #[inline]
-    pub fn get_tick(&self) -> Tick {
-        self.tick
+impl fmt::Display for WindowColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowColor::Fixed(color) => write!(f, "{}", color),
+            _ => f.write_str(WINDOW_COLOR_NAMES.iter().find(|(_, c)| c == self).map_or("", |(name, _)| name)),
+        }
     }
+}
 
-    #[inline]
-    pub fn get_canvas(&self) -> &Vec2D<PaletteColor> {
-        &self.canvas
+/// Wall color given as an option, only a color code. Shades of the wall are window colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WallColor(pub PaletteColor);
+
+impl FromStr for WallColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match WINDOW_COLOR_NAMES.iter().find(|(name, _)| *name == s.trim()) {
+            Some((name, _)) => Err(format!("walls can't be colored {}, only windows can", name)),
+            None => parse_color(s).map(WallColor),
+        }
     }
+}
 
-    pub fn set_wh(&mut self, w: usize, h: usize) {
-        self.size = (w, h);
-        self.canvas = Vec2D::new(w, h, || self.background);
+impl fmt::Display for WallColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    pub fn next_tick(&mut self) {
-        let City { rng, size, tick: tick_ref, background, layers_desc, layers, canvas, step } = self;
-        let (sx, sy) = *size;
-        let mut tick = *tick_ref;
-        let step = *step;
+/// Colors to replace a layer's wall or window colors with, parsed from `layer=color,color,..`.
+/// Colors are codes or, for windows, wall shades named like `wall-light`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerColors<C = WindowColor> {
+    pub layer: usize, // index into layers, back to front
+    pub colors: ArrayVec<[C; 32]>,
+}
 
-        let bsz_minmax_w = (6, 25);
-        let bsz_minmax_h = (10, sy + 2);
+impl<C: FromStr<Err = String>> FromStr for LayerColors<C> {
+    type Err = String;
 
-        // wipe canvas
-        canvas.fill_with(*background);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (layer, list) = s.split_once('=').ok_or_else(|| format!("expected layer=colors, got '{}'", s))?;
+        let layer = layer.trim().parse().map_err(|e| format!("layer '{}': {}", layer, e))?;
 
-        for (d, l) in layers_desc.iter().zip(layers.iter_mut()) {
-            // spawn a new building on this layer
-            // don't spawn if not moving on this tick && spawn decision
-            let threshold =
-                if l.rightmost_building_rcx > sx { d.collision } else { d.density };
-
-            if tick % d.speed == 0 && rng.f32() < threshold.powf(PROBABILITY_CURVE) {
-                let colors_len = d.wall_color.len();
-                let color_i = if colors_len > 1 { rng.usize(..colors_len) } else { 0 };
-
-                let b = Building {
-                    size_x: rng.usize(bsz_minmax_w.0..=bsz_minmax_w.1),
-                    size_y: rng.usize(bsz_minmax_h.0..=bsz_minmax_h.1),
-                    spawn_tick: tick,
-                    color: d.wall_color[color_i],
-                    seed: rng.u64(..),
-                };
-                l.ring.push_back(b);
-            }
+        let mut colors = ArrayVec::new();
+        for v in list.split(',') {
+            colors.try_push(v.parse()?).map_err(|_| format!("at most {} colors", colors.capacity()))?;
+        }
+        Ok(LayerColors { layer, colors })
+    }
+}
 
-            // draw buildings on canvas
-            let mut rightmost_rc = 0;
-            let b_count = l.ring.len();
-            for _ in 0..b_count {
-                let b = match l.ring.pop_front() {
-                    Some(b) => b,
-                    None => break,
-                };
+impl<C: fmt::Display> fmt::Display for LayerColors<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list: Vec<String> = self.colors.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}={}", self.layer, list.join(","))
+    }
+}
 
-                let mut wrap_tick = tick;
-                if b.spawn_tick > wrap_tick {
-                    wrap_tick += TICK_WRAP;
-                }
+/// Number set for one layer, like its target count or sink, parsed from `layer=n`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerNumber {
+    pub layer: usize,
+    pub n: usize,
+}
 
-                let (bsz_x, bsz_y) = (b.size_x, b.size_y);
-                let x = sx as i32 - ((wrap_tick - b.spawn_tick) * step / d.speed) as i32;
-                let (offset_x, x) = if x < 0 { (x.abs() as usize, 0) } else { (0, x as usize) };
-                let (offset_y, y) = if bsz_y > sy { (bsz_y - sy, 0) } else { (0, sy - bsz_y) };
+impl FromStr for LayerNumber {
+    type Err = String;
 
-                if offset_x > bsz_x {
-                    continue; // don't requeue buildings that can't be seen anymore
-                }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (layer, n) = s.split_once('=').ok_or_else(|| format!("expected layer=n, got '{}'", s))?;
+        let layer = layer.trim().parse().map_err(|e| format!("layer '{}': {}", layer, e))?;
+        let n = n.trim().parse().map_err(|e| format!("'{}': {}", n, e))?;
+        Ok(LayerNumber { layer, n })
+    }
+}
 
-                let (w, h) = (bsz_x - offset_x, bsz_y - offset_y);
-                let (w, h) = (w.min(sx - x), h.min(sy));
+impl fmt::Display for LayerNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.layer, self.n)
+    }
+}
 
-                rightmost_rc =
-                    rightmost_rc.max(x + bsz_x + COLLISION_GAP);
+/// Row of color behind the buildings, parsed from `row,color` with rows counted from the bottom
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Horizon {
+    pub row: usize,
+    pub color: PaletteColor,
+}
 
-                draw_building(canvas, &b, d, (x, y), (offset_x, offset_y), (w, h));
-                l.ring.push_back(b);
-            }
+impl FromStr for Horizon {
+    type Err = String;
 
-            l.rightmost_building_rcx = rightmost_rc;
-        }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (row, color) = s.split_once(',').ok_or_else(|| format!("expected row,color, got '{}'", s))?;
+        let row = row.trim().parse().map_err(|e| format!("row '{}': {}", row, e))?;
+        Ok(Horizon { row, color: parse_color(color)? })
+    }
+}
 
-        // next tick
-        tick += 1;
-        if tick > TICK_WRAP {
-            tick = 1;
-        }
-        *tick_ref = tick;
+impl fmt::Display for Horizon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.row, self.color)
     }
 }
 
-struct Hash(u32);
+/// Part of a city spread over `count` terminals of the same size side by side, `index`
+/// counting from 1 at the left. Parsed from `index/count`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
 
-impl Hash {
-    // one_at_a_time from https://en.wikipedia.org/wiki/Jenkins_hash_function
+impl FromStr for Shard {
+    type Err = String;
 
-    #[inline]
-    pub fn new() -> Hash {
-        Hash(0)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s.split_once('/').ok_or_else(|| format!("expected index/count, got '{}'", s))?;
+        let index = index.trim().parse().map_err(|e| format!("index '{}': {}", index, e))?;
+        let count = count.trim().parse().map_err(|e| format!("count '{}': {}", count, e))?;
+        if index < 1 || index > count {
+            return Err(format!("index {} isn't within 1..{}", index, count));
+        }
+        Ok(Shard { index, count })
     }
+}
 
-    #[inline]
-    pub fn reset_final(&mut self) -> u32 {
-        let mut hash = mem::replace(&mut self.0, 0);
-        hash += hash << 3;
-        hash ^= hash >> 11;
-        hash += hash << 15;
-        hash
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
     }
+}
 
-    #[inline]
-    pub fn inc_seed_u32(&mut self, seed: u32) {
-        let mut hash = self.0;
-        for i in 0..4 {
-            hash += seed >> (8 * i) & 0xff;
-            hash += hash << 10;
-            hash ^= hash >> 6;
-        }
-        self.0 = hash;
+/// Debug rulers every `spacing` columns and rows, labeled with their canvas column and
+/// row from the bottom. Parsed from `spacing,color`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub spacing: usize,
+    pub color: PaletteColor,
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Grid { spacing: 10, color: 100 }
     }
 }
 
+impl FromStr for Grid {
+    type Err = String;
 
-fn draw_building(canvas: &mut Vec2D<PaletteColor>, b: &Building, layer: &LayerDesc,
-                 pos_xy: (usize, usize), offset_xy: (usize, usize), limits_xy: (usize, usize)) {
-    let ((ox, oy), (lw, lh)) = (offset_xy, limits_xy);
-    let (cx, cy) = pos_xy;
-    let (sw, sh) = (b.size_x, b.size_y);
-    let (iw, ih) = (sw.min(lw), sh.min(lh));
-    if lw == 0 || lh == 0 || sw < ROOF_GAP_X * 2 || sw < WINDOW_PAD_L + WINDOW_PAD_R + WINDOW_X {
-        return; // skip on too small buildings and views
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spacing, color) = s.split_once(',').ok_or_else(|| format!("expected spacing,color, got '{}'", s))?;
+        let spacing = match spacing.trim().parse() {
+            Ok(0) => return Err("spacing must be at least 1".to_string()),
+            Ok(spacing) => spacing,
+            Err(e) => return Err(format!("spacing '{}': {}", spacing, e)),
+        };
+        Ok(Grid { spacing, color: parse_color(color)? })
     }
+}
 
-    let rng = Rng::with_seed(b.seed);
-    let seed_fill = rng.u32(..) as u64;
-    let mut hash = Hash::new();
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.spacing, self.color)
+    }
+}
 
-    let right_gap_x = sw - ROOF_GAP_X;
-    let wnd_unix_x = WINDOW_X + WINDOW_SPC_X;
-    let wnd_unit_y = WINDOW_Y + WINDOW_SPC_Y;
-    let wnd_fst_xy = (WINDOW_PAD_L, ROOF_GAP_Y + WINDOW_PAD_T);
-    let wnd_lim_xy = (sw - WINDOW_PAD_R, sh - WINDOW_PAD_B);
+/// Star field drawn on the sky behind the buildings, parsed from `count` or
+/// `count,chance` with the chance of a shooting star starting at any tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stars {
+    pub count: usize,
+    pub shooting_chance: f32,
+}
 
-    let wnd_colors = &layer.window_colors;
-    let wnd_colors_len = wnd_colors.len();
-    let wnd_draw = layer.draw_windows && wnd_colors_len > 0;
-    let wall_color = b.color;
+impl FromStr for Stars {
+    type Err = String;
 
-    let row_x = move || ox..ox+iw;
-    let row_i = move |x| cx + (x - ox);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, chance) = s.split_once(',').unwrap_or((s, "0.001"));
+        let count = count.trim().parse().map_err(|e| format!("count '{}': {}", count, e))?;
+        let shooting_chance = match chance.trim().parse() {
+            Ok(chance) if (0.0..=1.0).contains(&chance) => chance,
+            Ok(_) => return Err(format!("chance '{}' isn't between 0 and 1", chance)),
+            Err(e) => return Err(format!("chance '{}': {}", chance, e)),
+        };
+        Ok(Stars { count, shooting_chance })
+    }
+}
 
-    for y in oy..oy+ih {
-        let r = canvas.get_row_mut(cy + (y - oy));
+impl fmt::Display for Stars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.count, self.shooting_chance)
+    }
+}
 
-        if y < ROOF_GAP_Y {
-            // draw upper corners
+/// How much the picture may move and flash, one setting every moving or flashing effect checks
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MotionProfile {
+    #[default]
+    Full,
+    Calm, // scrolling capped at a cell per second, nothing flashes, window lights change at a crawl
+}
 
-            for x in row_x() {
-                if x >= ROOF_GAP_X && x < right_gap_x {
-                    r[row_i(x)] = wall_color;
-                }
-            }
-        } else {
-            // draw normal walls and windows
+impl MotionProfile {
+    /// Whether effects may flash, like twinkling and shooting stars
+    pub fn flashing(self) -> bool {
+        self == MotionProfile::Full
+    }
 
-            let mut wnd_drawn_y = false;
+    /// Most cells per second any layer may scroll by, None for no cap
+    pub fn max_cells_per_sec(self) -> Option<f32> {
+        match self {
+            MotionProfile::Full => None,
+            MotionProfile::Calm => Some(1.0),
+        }
+    }
 
-            if wnd_draw && y >= wnd_fst_xy.1 && y < wnd_lim_xy.1 {
-                let cwnd_pos_y  = (y - wnd_fst_xy.1) % wnd_unit_y;
+    /// Factor the time between window light changes is stretched by
+    pub fn window_change_slowdown(self) -> f32 {
+        match self {
+            MotionProfile::Full => 1.0,
+            MotionProfile::Calm => 20.0,
+        }
+    }
+}
 
-                if cwnd_pos_y < WINDOW_Y {
-                    wnd_drawn_y = true;
-                    let mut wnd_clr = wall_color;
+/// Effect set on a city, with the seed of its random stream
+#[derive(Debug)]
+struct EffectSlot {
+    key: &'static str,
+    phase: EffectPhase,
+    seed: u64,
+    effect: Box<dyn Effect>,
+    ctx: EffectCtx, // of the last tick, `draw` gets it after `tick`
+}
 
-                    for x in row_x() {
-                        let mut clr = wall_color;
+/// Vertical gradient of sky colors, parsed from a comma list of bands from the top down
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sky {
+    pub colors: Vec<PaletteColor>,
+    pub drift: i32, // ticks per row the bands move down by, negative moves them up, 0 keeps them still
+    pub dither: bool, // mix neighboring bands cell by cell where they meet instead of changing at once
+}
 
-                        if x >= WINDOW_PAD_L && x < wnd_lim_xy.0 {
-                            let cwnd_pos_x = (x - wnd_fst_xy.0) % wnd_unix_x;
+impl Sky {
+    /// Color of row `y` of a canvas `h` rows high at `tick`
+    fn row_color(&self, y: usize, h: usize, tick: Tick) -> PaletteColor {
+        self.colors[self.gradient_pos(y, h, tick) * self.colors.len() / h]
+    }
 
-                            if cwnd_pos_x == 0 {
-                                hash.inc_seed_u32(0xdeadbeef);
-                                hash.inc_seed_u32(x as u32);
-                                hash.inc_seed_u32(y as u32);
-                                rng.seed(seed_fill << 32 | hash.reset_final() as u64);
-                                let i = rng.usize(..wnd_colors_len);
-                                wnd_clr = wnd_colors[i];
-                            }
+    /// Color of cell (x, y) when dithered: the two bands around the cell's place in
+    /// the gradient are picked by an ordered dither, weighted by how close each band is
+    fn cell_color(&self, (x, y): (usize, usize), h: usize, tick: Tick) -> PaletteColor {
+        let n = self.colors.len();
+        // band centers at whole numbers
+        let at = (self.gradient_pos(y, h, tick) as f32 + 0.5) * n as f32 / h as f32 - 0.5;
+        let band = at.max(0.0) as usize;
+        let mix = at - band as f32;
+        let threshold = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0;
+        match mix > threshold {
+            true => self.colors[(band + 1).min(n - 1)],
+            false => self.colors[band.min(n - 1)],
+        }
+    }
 
-                            if cwnd_pos_x < WINDOW_X {
-                                clr = wnd_clr;
-                            }
-                        }
+    /// Row of the still gradient shown at row `y` at `tick`. Drifting bands run through
+    /// the gradient and back in reverse, so they wrap around without a seam
+    fn gradient_pos(&self, y: usize, h: usize, tick: Tick) -> usize {
+        let cycle = 2 * h;
+        let shift = match self.drift {
+            0 => 0,
+            d => (tick / d.unsigned_abs()) as usize % cycle,
+        };
+        let p = match self.drift > 0 {
+            true => (y + cycle - shift) % cycle,
+            false => (y + shift) % cycle,
+        };
+        if p < h { p } else { cycle - 1 - p }
+    }
+}
 
-                        r[row_i(x)] = clr;
-                    }
-                }
+impl FromStr for Sky {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s.split(',').map(parse_color).collect::<Result<Vec<_>, _>>()?;
+        Ok(Sky { colors, drift: 0, dither: false })
+    }
+}
+
+/// Colors as parsed by FromStr, drift and dither are set separately
+impl fmt::Display for Sky {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list: Vec<String> = self.colors.iter().map(|c| c.to_string()).collect();
+        f.write_str(&list.join(","))
+    }
+}
+
+/// Background color code as shown by --list-colors
+pub fn parse_color(v: &str) -> Result<PaletteColor, String> {
+    let v = v.trim();
+    match v.parse::<PaletteColor>() {
+        Ok(color) if (40..=47).contains(&color) || (100..=107).contains(&color) => Ok(color),
+        Ok(color) => Err(format!("{} isn't a color code, see --list-colors", color)),
+        Err(e) => Err(format!("{}: {}", v, e)),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Layer {
+    ring: VecDeque<Building>,
+    rightmost_building_rcx: usize,
+    rng: Option<Rng>, // own random stream, shared city rng is used if None
+    loop_schedule: Vec<bool>,
+    plots: Vec<Plot>, // buildings of a rising city in spawn order
+    hidden: bool, // still simulated, so it comes back where it would be
+    live: usize, // buildings that haven't left the world yet, as of the last tick
+    target_chance: Option<f32>, // spawn chance steered toward the target count, None until first steered
+}
+
+/// Building of a rising city, standing in place at a canvas column
+#[derive(Debug, Clone, PartialEq)]
+struct Plot {
+    x: usize,
+    stand_ticks: Tick, // layer ticks between fully risen and sinking
+    b: Building,
+}
+
+impl Plot {
+    /// Rows risen from the ground and 0.0..1.0 through its life after `age` layer ticks,
+    /// None once it's gone. Rises over `grow` ticks, stands, then sinks over as many
+    fn stage(&self, age: Tick, grow: Tick) -> Option<(usize, f32)> {
+        let life = 2 * grow + self.stand_ticks;
+        let risen = |t: Tick| (self.b.size_y as u64 * t as u64 / grow as u64) as usize;
+        let rows = match age {
+            _ if age >= life => return None,
+            _ if age < grow => risen(age),
+            _ if age < grow + self.stand_ticks => self.b.size_y,
+            _ => risen(life - age),
+        };
+        Some((rows, age as f32 / life as f32))
+    }
+
+    fn overlaps(&self, x: usize, w: usize) -> bool {
+        x < self.x + self.b.size_x + COLLISION_GAP && self.x < x + w + COLLISION_GAP
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Building {
+    id: BuildingId,
+    size_x: usize,
+    size_y: usize,
+    spawn_tick: Tick,
+    color: PaletteColor,
+    seed: u64, // the only source of randomness for the building's look, see draw_building
+    jitter_x: usize, // cells beyond the right edge at spawn
+    stamp: Option<usize>, // index into layer's stamps
+    windows: Vec<Window>, // window grid by rows, see window_grid_size
+}
+
+impl Building {
+    fn info(&self, layer: usize) -> BuildingInfo {
+        BuildingInfo { id: self.id, layer, size: (self.size_x, self.size_y), spawn_tick: self.spawn_tick,
+                       color: self.color, seed: self.seed }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Window {
+    color_i: u8, // index into layer's window colors
+    fade_roll: f32, // window goes dark when age fade exceeds it
+}
+
+/// Horizontal building and window sizes, scaled down on canvases narrower than `GEOMETRY_FULL_W`.
+/// Vertical sizes stay, canvases are never lower than they were made for
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Geometry {
+    building_w: (usize, usize), // min, max
+    roof_gap_x: usize,
+    window_x: usize,
+    window_spc_x: usize,
+    window_pad_l: usize,
+    window_pad_r: usize,
+}
+
+impl Geometry {
+    const FULL: Geometry = Geometry {
+        building_w: (BUILDING_MIN_W, BUILDING_MAX_W),
+        roof_gap_x: ROOF_GAP_X,
+        window_x: WINDOW_X,
+        window_spc_x: WINDOW_SPC_X,
+        window_pad_l: WINDOW_PAD_L,
+        window_pad_r: WINDOW_PAD_R,
+    };
+
+    /// Sizes scaled by `width / GEOMETRY_FULL_W` and rounded, so they change one at a time
+    /// on the way down and reach the full ones just below it. The least building width is kept
+    /// at what its roof and a window column need, so no more buildings are skipped as too small
+    /// than in the full layout, whose narrowest ones are
+    fn for_width(width: usize) -> Geometry {
+        if width >= GEOMETRY_FULL_W {
+            return Geometry::FULL;
+        }
+
+        let scale = |v: usize| ((v * width + GEOMETRY_FULL_W / 2) / GEOMETRY_FULL_W).max(1);
+        let (roof_gap_x, window_x, window_spc_x) = (scale(ROOF_GAP_X), scale(WINDOW_X), scale(WINDOW_SPC_X));
+        let (window_pad_l, window_pad_r) = (scale(WINDOW_PAD_L), scale(WINDOW_PAD_R));
+        let drawable_w = (roof_gap_x * 2).max(window_pad_l + window_pad_r + window_x);
+        let min_w = scale(BUILDING_MIN_W).max(drawable_w.min(BUILDING_MIN_W));
+        Geometry {
+            building_w: (min_w, scale(BUILDING_MAX_W).max(min_w)),
+            roof_gap_x, window_x, window_spc_x, window_pad_l, window_pad_r,
+        }
+    }
+}
+
+impl<'a> City<'a> {
+    /// Panics on a zero step or layer speed, which would never move. Options reject a zero
+    /// step before it gets here, layers are built by code
+    pub fn new(
+        width: usize,
+        height: usize,
+        step: Tick,
+        rng: &'a Rng,
+        bg_color: PaletteColor,
+        layers: &'a [LayerDesc],
+    ) -> City<'a> {
+        assert!(step > 0, "Step must be at least 1");
+        assert_valid_layers(layers);
+
+        City {
+            rng, step,
+            size: (width, height),
+            tick: 1,
+            drawn_tick: 1,
+            background: bg_color,
+            canvas: Vec2D::new(width, height, || Cell::blank(bg_color)),
+            skyline: vec![height; width],
+            dirty: clean_rows(width, height),
+            back: None,
+            layers: vec![Layer::default(); layers.len()],
+            layers_desc: layers,
+            looping: None,
+            world_w: None,
+            view_x: None,
+            shard: None,
+            palette_shift: (0, false),
+            mirror: false,
+            rising: false,
+            horizon: None,
+            grid: None,
+            sky: None,
+            stars: None,
+            effects: Vec::new(),
+            motion: MotionProfile::Full,
+            motion_blur: 0.0,
+            dither: false,
+            outro: 0.0,
+            legacy_spawn: false,
+            despawn_margin: 0,
+            next_id: 0,
+            geometry: Geometry::for_width(width),
+            visible: Vec::new(),
+            tick_times: None,
+        }
+    }
+
+    #[inline]
+    pub fn get_size(&self) -> WHSize {
+        self.size
+    }
+
+    #[inline]
+    pub fn get_tick(&self) -> Tick {
+        self.tick
+    }
+
+    /// Tick of the frame on the canvas, the one before `get_tick` unless the city was
+    /// moved on without drawing since
+    #[inline]
+    pub fn drawn_tick(&self) -> Tick {
+        self.drawn_tick
+    }
+
+    /// Frame drawn before the current one, None unless double buffered
+    #[inline]
+    pub fn get_prev_canvas(&self) -> Option<&Vec2D<Cell>> {
+        self.back.as_ref().map(|(canvas, _)| canvas)
+    }
+
+    #[inline]
+    pub fn get_background(&self) -> PaletteColor {
+        self.background
+    }
+
+    #[inline]
+    pub fn get_canvas(&self) -> &Vec2D<Cell> {
+        &self.canvas
+    }
+
+    /// Canvas columns buildings are drawn on, the left half including the middle column if mirrored
+    #[inline]
+    pub fn sim_width(&self) -> usize {
+        match self.mirror {
+            true => self.size.0.div_ceil(2),
+            false => self.size.0,
+        }
+    }
+
+    /// Width the buildings travel across, never less than the simulated width
+    #[inline]
+    pub fn world_width(&self) -> usize {
+        let sim_w = self.sim_width();
+        if let Some((shard, _)) = self.shard {
+            return shard.count * sim_w;
+        }
+        self.world_w.map_or(sim_w, |w| w.max(sim_w))
+    }
+
+    /// World column shown at the left screen edge
+    #[inline]
+    pub fn view_x(&self) -> usize {
+        if let Some((shard, _)) = self.shard {
+            return (shard.index - 1) * self.sim_width();
+        }
+        let max_x = self.world_width() - self.sim_width();
+        self.view_x.map_or(max_x, |x| x.min(max_x))
+    }
+
+    /// Buildings on every layer that haven't left the world yet
+    pub fn building_count(&self) -> usize {
+        self.layers.iter().map(|l| l.ring.len() + l.plots.len()).sum()
+    }
+
+    /// Buildings in the last drawn frame by id and where they are, front layer last
+    pub fn visible_buildings(&self) -> &[VisibleBuilding] {
+        &self.visible
+    }
+
+    /// Frontmost building drawn over a canvas cell in the last drawn frame, None for the sky
+    pub fn building_at(&self, (x, y): (usize, usize)) -> Option<BuildingInfo> {
+        let (sx, sy) = self.size;
+        if x >= sx || y >= sy {
+            return None;
+        }
+        let x = if self.mirror && x >= sx - sx / 2 { sx - 1 - x } else { x };
+
+        self.visible.iter().rev()
+            .filter(|v| (v.pos.0..v.pos.0 + v.size.0).contains(&x) && (v.pos.1..v.pos.1 + v.size.1).contains(&y))
+            .find_map(|v| {
+                let l = &self.layers[v.layer];
+                let b = l.ring.iter().chain(l.plots.iter().map(|p| &p.b)).find(|b| b.id == v.id)?;
+                let cell = (v.offset.0 + x - v.pos.0, v.offset.1 + y - v.pos.1);
+                covers(b, &self.layers_desc[v.layer], &self.geometry, cell).then(|| b.info(v.layer))
+            })
+    }
+
+    /// Most buildings the layers can hold at once, spawns beyond it are refused
+    pub fn building_cap(&self) -> usize {
+        let span = self.world_width() + self.despawn_margin;
+        self.layers_desc.iter().map(|d| spawn_cap(d, span, &self.geometry)).sum()
+    }
+
+    /// Row of the highest building cell in each column of the last drawn frame,
+    /// canvas height for empty sky. Kept up to date while drawing, so it's free to query
+    #[inline]
+    pub fn skyline(&self) -> &[usize] {
+        &self.skyline
+    }
+
+    /// Canvas cells in row-major order with (width, height), for copying the whole frame at once
+    #[inline]
+    pub fn canvas_raw(&self) -> (&[Cell], WHSize) {
+        self.canvas.as_flat_slice()
+    }
+
+    /// Color of the tallest building in each of `width` slices of the last drawn frame,
+    /// the background for slices of empty sky
+    pub fn skyline_colors(&self, width: usize) -> Vec<PaletteColor> {
+        let (sx, sy) = self.size;
+        (0..width)
+            .map(|i| {
+                let (from, to) = (i * sx / width, ((i + 1) * sx / width).max(i * sx / width + 1));
+                match (from..to.min(sx)).min_by_key(|&x| self.skyline[x]) {
+                    Some(x) if self.skyline[x] < sy => self.canvas.get_row(self.skyline[x])[x].bg,
+                    _ => self.background,
+                }
+            })
+            .collect()
+    }
+
+    /// Downsampled skyline silhouette: each column is the tallest building in its slice
+    /// of the canvas drawn as a bar, rows are separated by newlines
+    pub fn minimap(&self, width: usize, height: usize) -> String {
+        let (sx, sy) = self.size;
+        let heights: Vec<usize> = self.skyline().iter().map(|y| sy - y).collect();
+
+        // bar levels in eighths of a minimap row
+        let levels: Vec<usize> = (0..width)
+            .map(|i| {
+                let (from, to) = (i * sx / width, ((i + 1) * sx / width).max(i * sx / width + 1));
+                let h = heights[from..to.min(sx)].iter().copied().max().unwrap_or(0);
+                h * height * 8 / sy
+            })
+            .collect();
+
+        let mut map = String::with_capacity((width * 3 + 1) * height);
+        for row in 0..height {
+            let base = (height - 1 - row) * 8;
+            map.extend(levels.iter().map(|&l| MINIMAP_BARS[l.saturating_sub(base).min(8)]));
+            map.push('\n');
+        }
+        map
+    }
+
+    pub fn set_tick(&mut self, tick: Tick) {
+        assert!((1..=TICK_WRAP).contains(&tick), "Tick out of range");
+        self.tick = tick;
+    }
+
+    /// Hides a shown layer or shows a hidden one, returns whether it's hidden now.
+    /// None if there's no such layer
+    pub fn toggle_layer(&mut self, layer: usize) -> Option<bool> {
+        let l = self.layers.get_mut(layer)?;
+        l.hidden = !l.hidden;
+        Some(l.hidden)
+    }
+
+    /// Gives each layer its own random stream derived from seed and layer index,
+    /// so changing one layer's parameters doesn't affect buildings on other layers
+    pub fn seed_layers(&mut self, seed: u64) {
+        for (i, l) in self.layers.iter_mut().enumerate() {
+            l.rng = Some(Rng::with_seed(mix_seed(seed, i as u64)));
+        }
+    }
+
+    /// Starts the city over from tick 1 with another seed for the shared random stream,
+    /// and for layer streams and the loop schedule if they're used. Call `warmup` after
+    pub fn reseed(&mut self, seed: u64) {
+        let layer_seeds = self.layers.iter().any(|l| l.rng.is_some());
+        self.rng.seed(seed);
+        self.set_layers(self.layers_desc);
+        if layer_seeds {
+            self.seed_layers(seed);
+        }
+        if let Some(looping) = self.looping {
+            self.set_loop_period(looping.period, seed);
+        }
+        if let Some(stars) = self.stars {
+            self.set_stars(Some(stars), seed);
+        }
+    }
+
+    /// Makes spawns a pure function of seed, layer and `tick % period` instead of the shared
+    /// random stream, so after warmup the city repeats exactly every `period` ticks.
+    /// Buildings differ from the normal mode for the same seed.
+    /// Period has to be a multiple of every layer speed
+    pub fn set_loop_period(&mut self, period: Tick, seed: u64) {
+        assert!(self.layers_desc.iter().all(|d| period.is_multiple_of(d.speed)),
+                "Loop period must be a multiple of every layer speed");
+
+        let looping = LoopSpawn { period, seed };
+        for (i, (d, l)) in self.layers_desc.iter().zip(self.layers.iter_mut()).enumerate() {
+            l.loop_schedule = looping.schedule(d, i, self.step, self.legacy_spawn, &self.geometry);
+        }
+        self.looping = Some(looping);
+    }
+
+    /// Lets buildings spawn and leave at the edges of a world wider than the screen,
+    /// the screen shows a window into it (see `set_view_x`). Widths below the screen
+    /// width act as the screen width, also after `set_wh`
+    pub fn set_world_width(&mut self, width: usize) {
+        self.world_w = Some(width);
+    }
+
+    /// Shows the part `shard` of a world `shard.count` screens wide, also after `set_wh`.
+    /// Spawns are drawn from `seed` and the tick like with `set_loop_period`, but without
+    /// repeating, so cities of the same seed, size and step started at the same tick stay
+    /// the same in separate processes. Doesn't apply to mirrored or rising cities
+    pub fn set_shard(&mut self, shard: Option<Shard>, seed: u64) {
+        if shard.is_some() {
+            assert!(!self.mirror && !self.rising, "Sharding doesn't apply to a mirrored or rising city");
+        }
+        self.shard = shard.map(|shard| (shard, seed));
+    }
+
+    /// Moves the screen to show the world from column `x`, None keeps it at the right end
+    /// of the world where buildings spawn. Clamped so the screen stays inside the world
+    pub fn set_view_x(&mut self, x: Option<usize>) {
+        self.view_x = x;
+    }
+
+    /// Rotates window colors through their layer's window colors by `shift` steps, so every
+    /// window keeps its place in the cycle and all of them change in lockstep. With `lit_only`
+    /// the colors also used for walls or the background stay put. 0 draws the colors as picked
+    pub fn set_palette_shift(&mut self, shift: usize, lit_only: bool) {
+        self.palette_shift = (shift, lit_only);
+    }
+
+    /// Simulates just the left half of the canvas and mirrors it onto the right half, buildings
+    /// then enter in the middle. With an odd width the middle column belongs to the left half.
+    /// Buildings already in the city keep going, so `warmup` from a new city is needed
+    /// for a screen that looks mirrored from the start
+    pub fn set_mirror(&mut self, mirror: bool) {
+        self.mirror = mirror;
+    }
+
+    /// Changes the sky color from the next drawn frame, the city and canvas are kept as they are
+    pub fn set_background(&mut self, color: PaletteColor) {
+        self.background = color;
+        // cells never painted since still show the old color, so both canvases are wiped whole once
+        let w = self.size.0;
+        self.dirty.fill((0, w));
+        if let Some((_, dirty)) = self.back.as_mut() {
+            dirty.fill((0, w));
+        }
+    }
+
+    /// Changes the colors of the sky gradient from the next drawn frame, keeping how it drifts
+    /// and dithers. Without a gradient one is set that stays still, dithered as `set_dither` says
+    pub fn set_background_gradient(&mut self, colors: Vec<PaletteColor>) {
+        assert!(!colors.is_empty(), "Sky needs at least one color");
+        let (drift, dither) = self.sky.as_ref().map_or((0, self.dither), |sky| (sky.drift, sky.dither));
+        self.sky = Some(Sky { colors, drift, dither });
+    }
+
+    /// Fills the sky with a vertical gradient instead of the background color from the next drawn frame
+    pub fn set_sky(&mut self, sky: Option<Sky>) {
+        self.sky = sky;
+    }
+
+    /// Paints a row of the sky in the horizon color behind the buildings from the next drawn frame.
+    /// The row is counted from the bottom, so it stays at the same height above the ground on resize
+    pub fn set_horizon(&mut self, horizon: Option<Horizon>) {
+        self.horizon = horizon;
+    }
+
+    /// Draws stars on the sky from the next drawn frame, placed by `seed`. They aren't part of the skyline
+    pub fn set_stars(&mut self, stars: Option<Stars>, seed: u64) {
+        self.stars = stars;
+        let effect = stars.map(|stars| Box::new(StarsEffect::new(stars, seed)) as Box<dyn Effect>);
+        self.set_effect("stars", EffectPhase::Behind, seed ^ SHOOTING_SALT, effect);
+    }
+
+    /// Sets the effect under `key` from the next tick, in place of the one set under it before
+    /// if there's one, else after every other effect. None removes it. Its random stream is
+    /// drawn from `seed`, see `Effect` for what effects may depend on
+    pub fn set_effect(&mut self, key: &'static str, phase: EffectPhase, seed: u64,
+                      mut effect: Option<Box<dyn Effect>>) {
+        if let Some(effect) = effect.as_mut() {
+            for (layer_i, l) in self.layers.iter().enumerate() {
+                l.ring.iter().chain(l.plots.iter().map(|p| &p.b)).for_each(|b| effect.spawned(&b.info(layer_i)));
+            }
+        }
+        let at = self.effects.iter().position(|slot| slot.key == key);
+        let ctx = EffectCtx { tick: self.tick, size: self.size, motion: self.motion, rng: Rng::with_seed(seed) };
+        match (at, effect) {
+            (Some(i), Some(effect)) => self.effects[i] = EffectSlot { key, phase, seed, effect, ctx },
+            (None, Some(effect)) => self.effects.push(EffectSlot { key, phase, seed, effect, ctx }),
+            (Some(i), None) => {
+                self.effects.remove(i);
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Times the phases of drawn ticks from the next one, see `take_tick_times`
+    pub fn set_profiling(&mut self, on: bool) {
+        self.tick_times = if on { Some(TickTimes::default()) } else { None };
+    }
+
+    /// Phase times of the ticks drawn since the last call, None if not profiling
+    pub fn take_tick_times(&mut self) -> Option<TickTimes> {
+        let layers = self.layers.len();
+        self.tick_times.as_mut().map(|times| mem::replace(times, TickTimes {
+            layers: vec![Duration::ZERO; layers],
+            ..TickTimes::default()
+        }))
+    }
+
+    /// Calm motion keeps stars from twinkling and shooting, scrolling speed is up to the caller
+    pub fn set_motion(&mut self, motion: MotionProfile) {
+        self.motion = motion;
+    }
+
+    /// Blends cells that changed since the previous frame with it, `weight` being the share of
+    /// the previous color (0 turns it off). Moving edges leave a trail fading out over a few
+    /// frames. Needs the previous frame, so this turns on double buffering
+    pub fn set_motion_blur(&mut self, weight: f32) {
+        assert!((0.0..1.0).contains(&weight), "Motion blur must be at least 0 and below 1");
+        self.motion_blur = weight;
+        if weight > 0.0 && self.back.is_none() {
+            self.enable_double_buffer();
+        }
+    }
+
+    /// Mixes the neighboring shades of face gradients (see `LayerDesc::face_gradient`) cell by cell
+    /// with an ordered dither where they meet, from the next drawn frame. Skies set with
+    /// `set_sky` dither by `Sky::dither`, ones set by `set_background_gradient` by this
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    /// Winds the city down from the next drawn frame for a shutdown, `progress` going from 0
+    /// (as usual) to 1 (only the sky left). Windows go dark column by column from the left,
+    /// then buildings fade into what's behind them
+    pub fn set_outro(&mut self, progress: f32) {
+        self.outro = progress.clamp(0.0, 1.0);
+    }
+
+    /// Draws rulers over the buildings from the next drawn frame, to line positions on the
+    /// canvas up with the ones in the simulation. They aren't part of the skyline
+    pub fn set_grid(&mut self, grid: Option<Grid>) {
+        self.grid = grid;
+    }
+
+    /// Lets buildings rise from the ground at random free columns, stand for a while and sink
+    /// again instead of scrolling. Layer speed slows down their life, `grow_ticks` sets how
+    /// long rising takes. World width, view and loop period don't apply.
+    /// Buildings already in the city stay as they are, so this is meant for a new city
+    pub fn set_rising(&mut self, rising: bool) {
+        self.rising = rising;
+    }
+
+    /// Spawns with density ^ PROBABILITY_CURVE as the chance of every spawn opportunity, like
+    /// before density meant a share of the screen width. Layers then thin out as the step grows,
+    /// but seeds and bookmarks from older versions show the same city again
+    pub fn set_legacy_spawn(&mut self, legacy: bool) {
+        self.legacy_spawn = legacy;
+        if let Some(looping) = self.looping {
+            self.set_loop_period(looping.period, looping.seed);
+        }
+    }
+
+    /// Keeps buildings until they're this many cells past the left world edge instead of
+    /// dropping them once they leave it, for drawing that overhangs them
+    pub fn set_despawn_margin(&mut self, cells: usize) {
+        self.despawn_margin = cells;
+    }
+
+    /// Replaces the layers and starts over with an empty city at tick 1, `warmup` fills
+    /// the screen again. Streams from `seed_layers` are dropped, a loop period is kept
+    pub fn set_layers(&mut self, layers: &'a [LayerDesc]) {
+        assert_valid_layers(layers);
+        for l in &self.layers {
+            for b in l.ring.iter().chain(l.plots.iter().map(|p| &p.b)) {
+                self.effects.iter_mut().for_each(|slot| slot.effect.despawned(b.id));
+            }
+        }
+        self.layers_desc = layers;
+        self.layers = vec![Layer::default(); layers.len()];
+        self.tick = 1;
+        if let Some(looping) = self.looping {
+            self.set_loop_period(looping.period, looping.seed);
+        }
+    }
+
+    /// Keeps a second canvas, so the previous frame stays readable through `get_prev_canvas`
+    /// while `next_tick` draws into the other one. Doubles canvas memory
+    pub fn enable_double_buffer(&mut self) {
+        let (w, h) = self.size;
+        self.back = Some((Vec2D::new(w, h, || Cell::blank(self.background)), clean_rows(w, h)));
+    }
+
+    /// Registers cells painted on the canvas outside of buildings, so the next wipe of
+    /// this canvas clears them. Position and size are clipped to the canvas
+    pub fn mark_dirty(&mut self, pos_xy: (usize, usize), size_xy: (usize, usize)) {
+        let ((x, y), (w, h)) = (pos_xy, size_xy);
+        let (sx, sy) = self.size;
+        let (x, y) = (x.min(sx), y.min(sy));
+        mark_dirty(&mut self.dirty, (x, y), (w.min(sx - x), h.min(sy - y)));
+    }
+
+    /// Resizes the canvas, buildings already in the city are kept. Below `GEOMETRY_FULL_W`
+    /// columns new buildings and the window layout of all of them get narrower
+    pub fn set_wh(&mut self, w: usize, h: usize) {
+        self.size = (w, h);
+        self.canvas = Vec2D::new(w, h, || Cell::blank(self.background));
+        self.skyline = vec![h; w];
+        self.dirty = clean_rows(w, h);
+        if self.back.is_some() {
+            self.enable_double_buffer();
+        }
+
+        let geometry = Geometry::for_width(w);
+        if geometry != self.geometry {
+            self.geometry = geometry;
+            // windows are keyed on their column and row, so the ones left keep their colors in the new layout
+            for (layer_i, (d, l)) in self.layers_desc.iter().zip(self.layers.iter_mut()).enumerate() {
+                for b in l.ring.iter_mut().chain(l.plots.iter_mut().map(|p| &mut p.b)) {
+                    b.windows = window_grid(b, layer_i, d, &geometry);
+                }
+            }
+            if let Some(looping) = self.looping {
+                self.set_loop_period(looping.period, looping.seed);
+            }
+        }
+    }
+
+    pub fn next_tick(&mut self) {
+        if let Some((canvas, dirty)) = self.back.as_mut() {
+            mem::swap(canvas, &mut self.canvas);
+            mem::swap(dirty, &mut self.dirty);
+        }
+        self.advance(true);
+        if let Some((prev, _)) = self.back.as_ref().filter(|_| self.motion_blur > 0.0) {
+            blur_changes(&mut self.canvas, prev, &mut self.dirty, self.motion_blur);
+        }
+    }
+
+    /// Runs `n` ticks for a frame standing for several, only the last one is drawn. The previous
+    /// canvas stays the frame drawn before them, the one on screen that the new frame is diffed
+    /// and blurred against
+    pub fn run_ticks(&mut self, n: u64) {
+        for _ in 1..n {
+            self.advance(false);
+        }
+        if n > 0 {
+            self.next_tick();
+        }
+    }
+
+    /// Ticks after which buildings spawned at the right edge have crossed the whole world
+    /// on every layer: slowest layer speed * world width
+    pub fn warmup_ticks(&self) -> Tick {
+        if self.rising {
+            return self.rise_life_ticks();
+        }
+        self.layers_desc.iter().map(|d| d.speed).max().unwrap_or(0) * self.world_width() as Tick
+    }
+
+    /// Ticks for the slowest layer to scroll one screen width, after which every layer
+    /// has replaced all of its visible content
+    pub fn cycle_ticks(&self) -> Tick {
+        if self.rising {
+            return self.rise_life_ticks();
+        }
+        let slowest = self.layers_desc.iter().map(|d| d.speed).max().unwrap_or(0);
+        (slowest * self.sim_width() as Tick).div_ceil(self.step)
+    }
+
+    /// Ticks the longest living buildings of a rising city stay on the slowest layer
+    fn rise_life_ticks(&self) -> Tick {
+        self.layers_desc.iter().map(|d| d.speed * (2 * rise_grow_ticks(d) + RISE_STAND_TICKS.1)).max().unwrap_or(0)
+    }
+
+    /// Fills the screen by advancing `warmup_ticks()` ticks from construction without drawing,
+    /// the next `next_tick` draws the first full frame. Does nothing if the city is already past that
+    pub fn warmup(&mut self) {
+        let filled_tick = 1 + self.warmup_ticks();
+        if self.tick < filled_tick {
+            self.seek(filled_tick);
+        }
+    }
+
+    /// Advances without drawing until `tick` is the next tick to be drawn.
+    /// Ticks behind the current one are reached only after a wrap
+    pub fn seek(&mut self, tick: Tick) {
+        assert!((1..=TICK_WRAP).contains(&tick), "Tick out of range");
+        while self.tick != tick {
+            self.advance(false);
+        }
+    }
+
+    fn advance(&mut self, draw: bool) {
+        let (world_w, view_x, sim_w) = (self.world_width(), self.view_x(), self.sim_width());
+        let (palette_shift, mirror, rising, horizon) = (self.palette_shift, self.mirror, self.rising, self.horizon);
+        let dither = self.dither;
+        let (grid, motion, outro) = (self.grid, self.motion, self.outro);
+        let (legacy_spawn, despawn_margin) = (self.legacy_spawn, self.despawn_margin);
+        let spawn_seed = self.shard.map(|(_, seed)| seed);
+        let City { rng, size, tick: tick_ref, drawn_tick, background, layers_desc, layers, canvas, skyline, dirty, step,
+                   looping, sky, effects, next_id, visible, geometry, tick_times, .. } = self;
+        let (sx, sy) = *size;
+        let mut tick = *tick_ref;
+        if draw {
+            *drawn_tick = tick;
+        }
+        let step = *step;
+        let lights_off_x = (outro / OUTRO_LIGHTS_END * sx as f32).ceil() as usize;
+        let mut clock = PhaseClock::start(tick_times.as_mut().filter(|_| draw), layers.len());
+
+        // wipe canvas, only spans painted since its last wipe aren't background
+        if draw {
+            let (cells, _) = canvas.as_flat_slice_mut();
+            for (y, span) in dirty.iter_mut().enumerate() {
+                match sky {
+                    // gradient rows are repainted whole, so there's nothing to wipe
+                    Some(sky) => {
+                        let row = &mut cells[y * sx..(y + 1) * sx];
+                        match sky.dither {
+                            true => row.iter_mut().enumerate()
+                                .for_each(|(x, cell)| *cell = Cell::blank(sky.cell_color((x, y), sy, tick))),
+                            false => row.fill(Cell::blank(sky.row_color(y, sy, tick))),
+                        }
+                        *span = (0, sx);
+                    }
+                    None => {
+                        if span.0 < span.1 {
+                            cells[y * sx + span.0..y * sx + span.1].fill(Cell::blank(*background));
+                        }
+                        *span = (sx, 0);
+                    }
+                }
+            }
+            skyline.fill(sy);
+            visible.clear();
+
+            // repainted like a building, so the wipe clears it when it's moved or turned off
+            if let Some(h) = horizon.filter(|h| h.row < sy) {
+                let y = sy - 1 - h.row;
+                canvas.get_row_mut(y).fill(Cell::blank(h.color));
+                mark_dirty(dirty, (0, y), (sx, 1));
+            }
+        }
+
+        clock.lap(|t| &mut t.wipe);
+
+        // effects move on while seeking too, so they don't depend on what was drawn
+        for slot in effects.iter_mut() {
+            let ctx = &mut slot.ctx;
+            (ctx.tick, ctx.size, ctx.motion) = (tick, *size, motion);
+            ctx.rng.seed(mix_seed(slot.seed, tick as u64));
+            slot.effect.tick(ctx);
+        }
+        let draw_effects = |phase, effects: &[EffectSlot], canvas: &mut Vec2D<Cell>, dirty: &mut DirtyRows| {
+            let mut canvas = EffectCanvas::new(canvas, dirty);
+            for slot in effects.iter().filter(|slot| slot.phase == phase) {
+                slot.effect.draw(&mut canvas, &slot.ctx);
+            }
+        };
+        if draw {
+            draw_effects(EffectPhase::Behind, effects, canvas, dirty);
+        }
+        clock.lap(|t| &mut t.effects);
+
+        for (layer_i, (d, l)) in layers_desc.iter().zip(layers.iter_mut()).enumerate() {
+            let bsz_minmax_h = (10, if d.allow_clip { sy + 2 } else { sy });
+            let draw = draw && !l.hidden;
+            let layer_key = layer_key(layer_i, d);
+            let loop_rng;
+            let rng = match looping {
+                Some(looping) => {
+                    loop_rng = looping.rng_at(layer_i, tick);
+                    &loop_rng
+                }
+                None => match spawn_seed {
+                    Some(seed) => {
+                        loop_rng = tick_rng(seed, layer_i, tick);
+                        &loop_rng
+                    }
+                    None => l.rng.as_ref().unwrap_or(rng),
+                },
+            };
+
+            let cycled;
+            let window_colors = match palette_shift {
+                (0, _) => &d.window_colors,
+                (shift, lit_only) => {
+                    cycled = cycle_window_colors(d, *background, shift, lit_only);
+                    &cycled
+                }
+            };
+
+            if rising {
+                // spawn where the building and its gaps are free of others on this layer
+                let chance = match d.target_count {
+                    Some(target) if tick % d.speed == 0 => {
+                        let start = d.density.powf(PROBABILITY_CURVE);
+                        steer_spawn_chance(&mut l.target_chance, start, l.plots.len(), target)
+                    }
+                    _ => d.density.powf(PROBABILITY_CURVE),
+                };
+                if tick % d.speed == 0 && rng.f32() < chance {
+                    let mut b = new_building(d, layer_i, rng, tick, geometry, bsz_minmax_h);
+                    let stand_ticks = rng.u32(RISE_STAND_TICKS.0..=RISE_STAND_TICKS.1);
+                    if b.size_x <= sim_w {
+                        let x = rng.usize(..=sim_w - b.size_x);
+                        if !l.plots.iter().any(|p| p.overlaps(x, b.size_x)) {
+                            b.id = take_id(next_id);
+                            effects.iter_mut().for_each(|slot| slot.effect.spawned(&b.info(layer_i)));
+                            l.plots.push(Plot { x, stand_ticks, b });
+                        }
+                    }
+                }
+                clock.lap(|t| &mut t.spawn);
+
+                let grow = rise_grow_ticks(d);
+                l.plots.retain(|p| {
+                    let wrap_tick = if p.b.spawn_tick > tick { tick + TICK_WRAP } else { tick };
+                    let (rows, age) = match p.stage((wrap_tick - p.b.spawn_tick) / d.speed, grow) {
+                        Some(stage) => stage,
+                        None => {
+                            effects.iter_mut().for_each(|slot| slot.effect.despawned(p.b.id));
+                            return false;
+                        }
+                    };
+
+                    // plots past the edge of a shrunk canvas are kept until they sink
+                    let (offset_y, y, h) = vertical_span(rows, sy, d.sink);
+                    if draw && p.x < sim_w && h > 0 {
+                        let w = p.b.size_x.min(sim_w - p.x);
+                        let look = Look { age, layer_key, window_colors, geometry, lights_off_x, dither };
+                        draw_building(canvas, &p.b, d, &look, (p.x, y), (0, offset_y), (w, h));
+                        raise_skyline(skyline, &p.b, d, geometry, (p.x, y), (0, offset_y), (w, h));
+                        mark_dirty(dirty, (p.x, y), (w, h));
+                        visible.push(VisibleBuilding { id: p.b.id, layer: layer_i, pos: (p.x, y), size: (w, h),
+                                                       offset: (0, offset_y) });
+                    }
+                    true
+                });
+                clock.lap(|t| &mut t.layers[layer_i]);
+                continue;
+            }
+
+            // spawn a new building on this layer
+            // don't spawn if not moving on this tick && spawn decision,
+            // a building still entering the world leaves only the collision chance
+            let (mut free_chance, mut blocked_chance) = spawn_chances(d, step, legacy_spawn, geometry);
+            if let Some(target) = d.target_count.filter(|_| tick % d.speed == 0 && looping.is_none()) {
+                free_chance = steer_spawn_chance(&mut l.target_chance, free_chance, l.live, target);
+                blocked_chance = blocked_chance.min(free_chance);
+            }
+            let chance = if l.rightmost_building_rcx > world_w { blocked_chance } else { free_chance };
+
+            let spawn = tick % d.speed == 0 && match looping {
+                Some(looping) => {
+                    rng.f32(); // keep draw order the schedule was made with
+                    l.loop_schedule[(tick % looping.period) as usize]
+                }
+                None => rng.f32() < chance,
+            };
+
+            if spawn {
+                let mut b = new_building(d, layer_i, rng, tick, geometry, bsz_minmax_h);
+                // built anyway, so the random sequence doesn't depend on the cap
+                if l.ring.len() < spawn_cap(d, world_w + despawn_margin, geometry) {
+                    b.id = take_id(next_id);
+                    effects.iter_mut().for_each(|slot| slot.effect.spawned(&b.info(layer_i)));
+                    l.ring.push_back(b);
+                }
+            }
+            clock.lap(|t| &mut t.spawn);
+
+            // draw buildings on canvas
+            let mut rightmost_rc = 0;
+            let mut live = 0;
+            let b_count = l.ring.len();
+            for _ in 0..b_count {
+                let b = match l.ring.pop_front() {
+                    Some(b) => b,
+                    None => break,
+                };
+
+                let mut wrap_tick = tick;
+                if b.spawn_tick > wrap_tick {
+                    wrap_tick += TICK_WRAP;
+                }
+
+                let (bsz_x, bsz_y) = (b.size_x, b.size_y);
+                let travelled = ((wrap_tick - b.spawn_tick) * step / d.speed) as usize;
+                let world_x = (world_w + b.jitter_x) as i32 - travelled as i32;
+
+                if world_x + ((bsz_x + despawn_margin) as i32) < 0 {
+                    // don't requeue buildings that left the world and its margin
+                    effects.iter_mut().for_each(|slot| slot.effect.despawned(b.id));
+                    continue;
+                }
+
+                rightmost_rc =
+                    rightmost_rc.max(world_x.max(0) as usize + bsz_x + COLLISION_GAP);
+                if world_x + bsz_x as i32 > 0 {
+                    live += 1;
+                }
+
+                let x = world_x - view_x as i32;
+                let (offset_x, x) = if x < 0 { (x.unsigned_abs() as usize, 0) } else { (0, x as usize) };
+
+                // growing buildings rise from the ground, showing their top rows first
+                let grown_y = match d.grow_ticks {
+                    0 => bsz_y,
+                    g => (bsz_y as u64 * (wrap_tick - b.spawn_tick) as u64 / g as u64).min(bsz_y as u64) as usize,
+                };
+                let (offset_y, y, h) = vertical_span(grown_y, sy, d.sink);
+
+                // jittered buildings may not have entered the screen yet, or already left it
+                if draw && x < sim_w && offset_x < bsz_x && h > 0 {
+                    let w = (bsz_x - offset_x).min(sim_w - x);
+
+                    let age = travelled as f32 / (world_w + b.jitter_x + bsz_x) as f32;
+                    let look = Look { age, layer_key, window_colors, geometry, lights_off_x, dither };
+                    draw_building(canvas, &b, d, &look, (x, y), (offset_x, offset_y), (w, h));
+                    raise_skyline(skyline, &b, d, geometry, (x, y), (offset_x, offset_y), (w, h));
+                    mark_dirty(dirty, (x, y), (w, h));
+                    visible.push(VisibleBuilding { id: b.id, layer: layer_i, pos: (x, y), size: (w, h),
+                                                   offset: (offset_x, offset_y) });
+                }
+                l.ring.push_back(b);
+            }
+
+            l.rightmost_building_rcx = rightmost_rc;
+            l.live = live;
+            clock.lap(|t| &mut t.layers[layer_i]);
+        }
+
+        if draw && mirror {
+            mirror_halves(canvas, skyline, dirty);
+        }
+        if draw {
+            draw_effects(EffectPhase::InFront, effects, canvas, dirty);
+        }
+        if draw && outro > OUTRO_FADE_START {
+            let weight = ((outro - OUTRO_FADE_START) / (1.0 - OUTRO_FADE_START) * 256.0) as i32;
+            let (sky, background) = (sky.as_ref(), *background);
+            let behind = |(x, y)| match sky {
+                Some(sky) if sky.dither => sky.cell_color((x, y), sy, tick),
+                Some(sky) => sky.row_color(y, sy, tick),
+                None => background,
+            };
+            fade_buildings(canvas, skyline, weight, behind);
+        }
+        if let Some(grid) = grid.filter(|_| draw) {
+            draw_grid(canvas, dirty, grid);
+        }
+        clock.lap(|t| &mut t.effects);
+
+        // next tick
+        tick += 1;
+        if tick > TICK_WRAP {
+            tick = 1;
+        }
+        *tick_ref = tick;
+    }
+}
+
+fn take_id(next_id: &mut BuildingId) -> BuildingId {
+    let id = *next_id;
+    *next_id += 1;
+    id
+}
+
+/// Random stream of a layer at a tick, the same wherever and whenever it's asked for
+fn tick_rng(seed: u64, layer_i: usize, tick: Tick) -> Rng {
+    Rng::with_seed(mix_seed(mix_seed(seed, layer_i as u64), tick as u64))
+}
+
+/// What tells the buildings of a layer from those of others spawned with the same seed: its
+/// index, speed and wall colors. Density isn't part of it, so the spawn mode and target counts
+/// change where buildings stand but not how they look
+fn layer_key(layer_i: usize, d: &LayerDesc) -> u64 {
+    let key = mix_seed(layer_i as u64, d.speed as u64);
+    d.wall_color.iter().fold(key, |key, &color| mix_seed(key, color as u64))
+}
+
+// splitmix64 finalizer
+pub fn mix_seed(seed: u64, i: u64) -> u64 {
+    let mut z = seed.wrapping_add(i.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+struct Hash(u32);
+
+impl Hash {
+    // one_at_a_time from https://en.wikipedia.org/wiki/Jenkins_hash_function
+
+    #[inline]
+    pub fn new() -> Hash {
+        Hash(0)
+    }
+
+    #[inline]
+    pub fn reset_final(&mut self) -> u32 {
+        let mut hash = mem::replace(&mut self.0, 0);
+        hash = hash.wrapping_add(hash << 3);
+        hash ^= hash >> 11;
+        hash = hash.wrapping_add(hash << 15);
+        hash
+    }
+
+    #[inline]
+    pub fn inc_seed_u32(&mut self, seed: u32) {
+        let mut hash = self.0;
+        for i in 0..4 {
+            hash = hash.wrapping_add(seed >> (8 * i) & 0xff);
+            hash = hash.wrapping_add(hash << 10);
+            hash ^= hash >> 6;
+        }
+        self.0 = hash;
+    }
+}
+
+/// Hash of a string extended to 64 bits by a second pass seeded with the first.
+/// Seeds derived from it are shared between runs, so it must never change
+pub fn hash_str(s: &str) -> u64 {
+    let pass = |init: u32| {
+        let mut hash = Hash::new();
+        hash.inc_seed_u32(init);
+        for chunk in s.as_bytes().chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            hash.inc_seed_u32(u32::from_le_bytes(word));
+        }
+        hash.reset_final()
+    };
+    let lo = pass(0);
+    (pass(lo) as u64) << 32 | lo as u64
+}
+
+fn assert_valid_layers(layers: &[LayerDesc]) {
+    // a city without layers would only ever show the sky
+    assert!(!layers.is_empty(), "At least one layer is needed");
+    assert!(layers.iter().all(|d| d.speed > 0), "Layer speed must be at least 1");
+    assert!(layers.iter().all(|d| valid_weights(&d.wall_weights, d.wall_color.len())
+                                && valid_weights(&d.window_weights, d.window_colors.len())),
+            "Color weights must match colors and not be all zero");
+}
+
+fn valid_weights(weights: &[u32], colors_len: usize) -> bool {
+    weights.is_empty() || (weights.len() == colors_len && weights.iter().any(|&w| w > 0))
+}
+
+/// Index of a color, uniform when weights are empty or all equal.
+/// Uniform picks draw the same random value as an unweighted pick
+fn pick_weighted(rng: &Rng, weights: &[u32], len: usize) -> usize {
+    if weights.iter().all(|&w| w == weights[0]) {
+        return rng.usize(..len);
+    }
+
+    let total: u64 = weights.iter().map(|&w| w as u64).sum();
+    let mut r = rng.u64(..total);
+    weights.iter()
+        .position(|&w| match r.checked_sub(w as u64) {
+            Some(rest) => { r = rest; false }
+            None => true,
+        })
+        .unwrap_or(len - 1)
+}
+
+fn clean_rows(w: usize, h: usize) -> DirtyRows {
+    vec![(w, 0); h]
+}
+
+pub fn mark_dirty(dirty: &mut [(usize, usize)], (x, y): (usize, usize), (w, h): (usize, usize)) {
+    for span in &mut dirty[y..y+h] {
+        *span = (span.0.min(x), span.1.max(x + w));
+    }
+}
+
+/// Moves background colors of cells differing from `prev` toward it. Blended cells are marked
+/// dirty, they're left behind by what moved and the wipe wouldn't clear them otherwise
+fn blur_changes(canvas: &mut Vec2D<Cell>, prev: &Vec2D<Cell>, dirty: &mut [(usize, usize)], weight: f32) {
+    let weight = (weight * 256.0) as i32;
+    for (y, span) in dirty.iter_mut().enumerate() {
+        for (x, (cell, old)) in canvas.get_row_mut(y).iter_mut().zip(prev.get_row(y)).enumerate() {
+            if cell.bg == old.bg {
+                continue;
+            }
+            let bg = blend(cell.bg, old.bg, weight);
+            if bg != cell.bg {
+                cell.bg = bg;
+                *span = (span.0.min(x), span.1.max(x + 1));
+            }
+        }
+    }
+}
+
+/// Blends cells below the skyline toward the colors `behind` them by `weight` / 256.
+/// Window glyphs are gone past half of it, their colors can't be blended
+fn fade_buildings(canvas: &mut Vec2D<Cell>, skyline: &[usize], weight: i32,
+                  behind: impl Fn((usize, usize)) -> PaletteColor) {
+    for (x, &top) in skyline.iter().enumerate() {
+        for (y, cell) in canvas.col_iter_mut(x).enumerate().skip(top) {
+            let bg = blend(cell.bg, behind((x, y)), weight);
+            *cell = match weight >= 128 {
+                true => Cell::blank(bg),
+                false => Cell { bg, ..*cell },
+            };
+        }
+    }
+}
+
+/// `color` moved toward `to` by `weight` / 256 of the way. Rounds toward `color`, so a trail
+/// blended again and again ends on it. Colors of the same RGB are left as they are
+fn blend(color: PaletteColor, to: PaletteColor, weight: i32) -> PaletteColor {
+    let ((r, g, b), (tr, tg, tb)) = (Cell::rgb_of(color), Cell::rgb_of(to));
+    let mix = |c: u8, t: u8| (c as i32 + (t as i32 - c as i32) * weight / 256) as u8;
+    match (mix(r, tr), mix(g, tg), mix(b, tb)) {
+        rgb if rgb == (r, g, b) => color,
+        (r, g, b) => Cell::rgb(r, g, b),
+    }
+}
+
+/// Window columns and rows of a building
+fn window_grid_size(sw: usize, sh: usize, g: &Geometry) -> (usize, usize) {
+    let (unit_x, unit_y) = (g.window_x + g.window_spc_x, WINDOW_Y + WINDOW_SPC_Y);
+    let span_x = sw.saturating_sub(g.window_pad_r).saturating_sub(g.window_pad_l);
+    let span_y = sh.saturating_sub(WINDOW_PAD_B).saturating_sub(ROOF_GAP_Y + WINDOW_PAD_T);
+    (span_x.div_ceil(unit_x), span_y / unit_y * WINDOW_Y + (span_y % unit_y).min(WINDOW_Y))
+}
+
+/// Colors of every window, derived from building seed and window column and row,
+/// so draw_building only has to look them up
+fn window_grid(b: &Building, layer_i: usize, layer: &LayerDesc, g: &Geometry) -> Vec<Window> {
+    if !layer.draw_windows || layer.window_colors.is_empty() || b.stamp.is_some() || too_small(b, g) {
+        return Vec::new();
+    }
+
+    let rng = Rng::with_seed(b.seed);
+    let seed_fill = rng.u32(..) as u64;
+    let key = layer_key(layer_i, layer);
+    let mut hash = Hash::new();
+    let unit_y = WINDOW_Y + WINDOW_SPC_Y;
+    let (cols, rows) = window_grid_size(b.size_x, b.size_y, g);
+
+    let (fst_y, lim_y) = (ROOF_GAP_Y + WINDOW_PAD_T, b.size_y.saturating_sub(WINDOW_PAD_B));
+
+    let mut windows = Vec::with_capacity(cols * rows);
+    for y in (fst_y..lim_y).filter(|y| (y - fst_y) % unit_y < WINDOW_Y) {
+        for col in 0..cols {
+            // hashed as the column's x in the full layout, so narrower ones keep its colors
+            let full_x = WINDOW_PAD_L + col * (WINDOW_X + WINDOW_SPC_X);
+            hash.inc_seed_u32(0xdeadbeef);
+            hash.inc_seed_u32(key as u32);
+            hash.inc_seed_u32((key >> 32) as u32);
+            hash.inc_seed_u32(full_x as u32);
+            hash.inc_seed_u32(y as u32);
+            rng.seed(seed_fill << 32 | hash.reset_final() as u64);
+            let color_i = pick_weighted(&rng, &layer.window_weights, layer.window_colors.len()) as u8;
+            windows.push(Window { color_i, fade_roll: rng.f32() });
+        }
+    }
+    windows
+}
+
+fn too_small(b: &Building, g: &Geometry) -> bool {
+    b.stamp.is_none() && (b.size_x < g.roof_gap_x * 2 || b.size_x < g.window_pad_l + g.window_pad_r + g.window_x)
+}
+
+/// Whether a building draws over its cell at `(x, y)` from its top left, empty stamp cells
+/// and roof corners don't
+fn covers(b: &Building, layer: &LayerDesc, g: &Geometry, (x, y): (usize, usize)) -> bool {
+    if too_small(b, g) {
+        return false;
+    }
+    match b.stamp.map(|i| &layer.stamps[i]) {
+        Some(stamp) => stamp.cell((x, y)) != StampCell::Empty,
+        None => y >= ROOF_GAP_Y || (x >= g.roof_gap_x && x < b.size_x - g.roof_gap_x),
+    }
+}
+
+/// Lowers skyline to the topmost row of each column draw_building fills, roof corners start lower
+fn raise_skyline(skyline: &mut [usize], b: &Building, layer: &LayerDesc, g: &Geometry,
+                 pos_xy: (usize, usize), offset_xy: (usize, usize), limits_xy: (usize, usize)) {
+    let ((ox, oy), (lw, lh)) = (offset_xy, limits_xy);
+    let (cx, cy) = pos_xy;
+    let (iw, ih) = (b.size_x.min(lw), b.size_y.min(lh));
+    if too_small(b, g) {
+        return;
+    }
+
+    let stamp = b.stamp.map(|i| &layer.stamps[i]);
+    for x in ox..ox+iw {
+        let top = match stamp {
+            Some(stamp) => (oy..oy+ih).find(|&y| stamp.cell((x, y)) != StampCell::Empty),
+            None if x >= g.roof_gap_x && x < b.size_x - g.roof_gap_x => Some(oy),
+            None => Some(oy.max(ROOF_GAP_Y)),
+        };
+        if let Some(top) = top.filter(|&top| top < oy + ih) {
+            let sky = &mut skyline[cx + (x - ox)];
+            *sky = (*sky).min(cy + top - oy);
+        }
+    }
+}
+
+/// Ticks buildings of a rising city take to rise on a layer, in layer ticks
+fn rise_grow_ticks(d: &LayerDesc) -> Tick {
+    if d.grow_ticks > 0 { d.grow_ticks } else { RISE_GROW_TICKS }
+}
+
+/// Most buildings a scrolling layer keeps at once: rows of its narrowest buildings with gaps
+/// across the world and beyond its edges, stacked a few deep as collisions let them overlap.
+/// Dense layers on slow steps would keep adding overlapping buildings without it
+fn spawn_cap(d: &LayerDesc, world_w: usize, g: &Geometry) -> usize {
+    let stamp_w = d.stamps.iter().map(|s| s.size().0);
+    let (min_w, max_w) = (stamp_w.clone().fold(g.building_w.0, usize::min).max(1), stamp_w.fold(g.building_w.1, usize::max));
+    let span = world_w + d.spawn_jitter + max_w;
+    span / (min_w + COLLISION_GAP) * SPAWN_CAP_DEPTH + 1
+}
+
+/// Chances to spawn on a spawn opportunity of a scrolling layer while its right world edge
+/// is free and while a building is still entering there. Opportunities come every `speed` ticks,
+/// in which the layer moves `step` cells, so the free chance only depends on the step:
+/// the gaps it leaves make buildings take `density` of every screen width on average.
+/// Big steps leave gaps even at a chance of 1, which caps the density they can reach
+fn spawn_chances(d: &LayerDesc, step: Tick, legacy: bool, g: &Geometry) -> (f32, f32) {
+    let blocked = d.collision.powf(PROBABILITY_CURVE);
+    if legacy {
+        return (d.density.powf(PROBABILITY_CURVE), blocked);
+    }
+    if d.density <= 0.0 {
+        return (0.0, blocked);
+    }
+
+    // a building with its gap takes `unit` cells. The edge is seen free as of the tick before
+    // an opportunity, `lag` cells late, and up to a step after the building passed it.
+    // Then every failed opportunity widens the gap by another step
+    let unit = typical_width(d, g) + COLLISION_GAP as f32;
+    let lag = step.div_ceil(d.speed) as f32;
+    let step = step as f32;
+    let gap = unit * (1.0 / d.density.min(1.0) - 1.0) + (step + 1.0) / 2.0 - lag;
+    (step / gap.max(step), blocked)
+}
+
+/// Rows cut off the top of a building showing `rows` of them, the canvas row the first
+/// shown one is at and how many are shown on a canvas `sy` rows high. The building stands
+/// `sink` rows below the bottom of the canvas, the rows down there are cut off too
+fn vertical_span(rows: usize, sy: usize, sink: usize) -> (usize, usize, usize) {
+    let base = sy + sink;
+    let y = base.saturating_sub(rows).min(sy);
+    (rows.saturating_sub(base), y, sy - y)
+}
+
+/// Spawn chance of a layer with a target count for its next spawn opportunity. Each opportunity
+/// moves it up while fewer buildings than the target are live and down while more are, starting
+/// from `start`, the chance its density gives. Looping layers keep to their schedule instead
+fn steer_spawn_chance(chance: &mut Option<f32>, start: f32, live: usize, target: usize) -> f32 {
+    let error = (target as f32 - live as f32) / target.max(1) as f32;
+    let steered = (chance.unwrap_or(start) + TARGET_GAIN * error).clamp(0.0, 1.0);
+    *chance = Some(steered);
+    // buildings stay for a whole screen width, so spawns past the target are only undone slowly
+    if live >= target { 0.0 } else { steered }
+}
+
+/// Average width of the buildings spawned on a layer
+fn typical_width(d: &LayerDesc, g: &Geometry) -> f32 {
+    let random_w = (g.building_w.0 + g.building_w.1) as f32 / 2.0;
+    match d.stamps.len() {
+        0 => random_w,
+        n => {
+            let stamp_w = d.stamps.iter().map(|s| s.size().0).sum::<usize>() as f32 / n as f32;
+            let chance = d.stamp_chance.clamp(0.0, 1.0);
+            random_w * (1.0 - chance) + stamp_w * chance
+        }
+    }
+}
+
+/// Random building spawned at `tick`, `h` is the min and max height
+fn new_building(d: &LayerDesc, layer_i: usize, rng: &Rng, tick: Tick, g: &Geometry, h: (usize, usize)) -> Building {
+    let w = g.building_w;
+    let colors_len = d.wall_color.len();
+    let color_i = if colors_len > 1 { pick_weighted(rng, &d.wall_weights, colors_len) } else { 0 };
+
+    let mut b = Building {
+        id: 0, // given when the building is placed
+        size_x: rng.usize(w.0..=w.1),
+        size_y: rng.usize(h.0..=h.1),
+        spawn_tick: tick,
+        color: d.wall_color[color_i],
+        seed: rng.u64(..),
+        jitter_x: if d.spawn_jitter > 0 { rng.usize(..=d.spawn_jitter) } else { 0 },
+        stamp: None,
+        windows: Vec::new(),
+    };
+
+    // drawn last, so layers without stamps keep their random sequence
+    if !d.stamps.is_empty() && rng.f32() < d.stamp_chance {
+        let i = rng.usize(..d.stamps.len());
+        let (w, h) = d.stamps[i].size();
+        b.size_x = w;
+        b.size_y = h;
+        b.stamp = Some(i);
+    }
+    b.windows = window_grid(&b, layer_i, d, g);
+    b
+}
+
+/// Rulers keep the colors under them, column labels go along the top and row labels along the left edge
+fn draw_grid(canvas: &mut Vec2D<Cell>, dirty: &mut [(usize, usize)], grid: Grid) {
+    let (_, (sx, sy)) = canvas.as_flat_slice();
+    let fg = Cell::fg_of(grid.color);
+    let ruler = |cell: &mut Cell, glyph| *cell = Cell { glyph, fg, bg: cell.bg };
+
+    for y in 0..sy {
+        let on_row = (sy - 1 - y) % grid.spacing == 0;
+        for (x, cell) in canvas.get_row_mut(y).iter_mut().enumerate() {
+            match (x % grid.spacing == 0, on_row) {
+                (true, true) => ruler(cell, '┼'),
+                (true, false) => ruler(cell, '┆'),
+                (false, true) => ruler(cell, '┄'),
+                (false, false) => {}
+            }
+        }
+    }
+
+    let mut label = |text: String, (x, y): (usize, usize)| {
+        let row = &mut canvas.get_row_mut(y)[x..];
+        row.iter_mut().zip(text.chars()).for_each(|(cell, glyph)| ruler(cell, glyph));
+    };
+    for x in (grid.spacing..sx).step_by(grid.spacing) {
+        label(x.to_string(), (x, 0));
+    }
+    for row in (grid.spacing..sy).step_by(grid.spacing) {
+        label(row.to_string(), (0, sy - 1 - row));
+    }
+    mark_dirty(dirty, (0, 0), (sx, sy));
+}
+
+/// Copies the left half of every row reversed onto the right half, the middle column
+/// of an odd width stays as it is
+fn mirror_halves(canvas: &mut Vec2D<Cell>, skyline: &mut [usize], dirty: &mut [(usize, usize)]) {
+    let (_, (sx, sy)) = canvas.as_flat_slice();
+    let half = sx / 2;
+
+    for y in 0..sy {
+        let (left, right) = canvas.get_row_mut(y).split_at_mut(sx - half);
+        right.iter_mut().zip(left[..half].iter().rev()).for_each(|(r, l)| *r = *l);
+    }
+    for x in 0..half {
+        skyline[sx - 1 - x] = skyline[x];
+    }
+    for span in dirty.iter_mut().filter(|span| span.0 < span.1) {
+        span.1 = sx - span.0;
+    }
+}
+
+/// Window colors rotated by `shift`. With `lit_only` just the ones that aren't a wall
+/// or background color are, the others keep their places
+fn cycle_window_colors(layer: &LayerDesc, background: PaletteColor, shift: usize, lit_only: bool)
+                       -> ArrayVec<[WindowColor; 32]> {
+    let colors = &layer.window_colors;
+    let unlit = |c: WindowColor| match c {
+        WindowColor::Fixed(c) => c == background || layer.wall_color.contains(&c),
+        WindowColor::Wall => true,
+        WindowColor::WallLight | WindowColor::WallDark => false,
+    };
+    let lit: ArrayVec<[usize; 32]> = (0..colors.len()).filter(|&i| !lit_only || !unlit(colors[i])).collect();
+
+    let mut cycled = colors.clone();
+    for (k, &i) in lit.iter().enumerate() {
+        cycled[i] = colors[lit[(k + shift) % lit.len()]];
+    }
+    cycled
+}
+
+/// What a building's look depends on besides itself and its layer
+struct Look<'l> {
+    age: f32, // 0.0 when spawned .. 1.0 when leaving the world
+    layer_key: u64, // see `layer_key`
+    window_colors: &'l [WindowColor], // layer's window colors after palette cycling
+    geometry: &'l Geometry,
+    lights_off_x: usize, // windows left of this canvas column are dark, for the outro
+    dither: bool, // see `City::set_dither`
+}
+
+/// Draws the part of a building inside limits at pos, offset is its first visible cell.
+/// Everything random about a building's look derives only from `b.seed`, its layer index and
+/// coordinates local to the building, never from the shared or layer rng, so at the same age
+/// a building looks the same whatever was drawn before it, and buildings of the same seed on
+/// different layers don't. Picks made at spawn (wall color, size)
+/// come from the layer rng and are stored in the building
+fn draw_building(canvas: &mut Vec2D<Cell>, b: &Building, layer: &LayerDesc, look: &Look,
+                 pos_xy: (usize, usize), offset_xy: (usize, usize), limits_xy: (usize, usize)) {
+    let ((ox, oy), (lw, lh)) = (offset_xy, limits_xy);
+    let (cx, cy) = pos_xy;
+    let (sw, sh) = (b.size_x, b.size_y);
+    let (iw, ih) = (sw.min(lw), sh.min(lh));
+    let g = look.geometry;
+    if lw == 0 || lh == 0 || too_small(b, g) {
+        return; // skip on too small buildings and views
+    }
+
+    let right_gap_x = sw - g.roof_gap_x;
+    let wnd_unix_x = g.window_x + g.window_spc_x;
+    let wnd_unit_y = WINDOW_Y + WINDOW_SPC_Y;
+    let wnd_fst_xy = (g.window_pad_l, ROOF_GAP_Y + WINDOW_PAD_T);
+    let wnd_lim_xy = (sw - g.window_pad_r, sh - WINDOW_PAD_B);
+
+    let wnd_colors = look.window_colors;
+    let wnd_colors_len = wnd_colors.len();
+    let wnd_weights = &layer.window_weights;
+    let wnd_draw = layer.draw_windows && wnd_colors_len > 0;
+    let (wnd_cols, _) = window_grid_size(sw, sh, g);
+    let wall_color = b.color;
+    let wall = Cell::blank(wall_color);
+    let wnd_fade = layer.age_fade * look.age.clamp(0.0, 1.0);
+    let window = move |clr| match layer.window_glyph {
+        Some(glyph) if clr != wall_color => Cell { glyph, fg: Cell::fg_of(clr), bg: wall_color },
+        _ => Cell::blank(clr),
+    };
+
+    let row_x = move || ox..ox+iw;
+    let row_i = move |x| cx + (x - ox);
+
+    if let Some(stamp) = b.stamp.map(|i| &layer.stamps[i]) {
+        let rng = Rng::with_seed(b.seed);
+        let seed_fill = rng.u32(..) as u64;
+
+        for y in oy..oy+ih {
+            let r = canvas.get_row_mut(cy + (y - oy));
+
+            for x in row_x() {
+                r[row_i(x)] = match stamp.cell((x, y)) {
+                    StampCell::Empty => continue,
+                    StampCell::Window if wnd_draw => {
+                        rng.seed(mix_seed(mix_seed(seed_fill, look.layer_key), (y * sw + x) as u64));
+                        match wnd_fade > 0.0 && rng.f32() < wnd_fade {
+                            true => wall,
+                            false => window(wnd_colors[pick_weighted(&rng, wnd_weights, wnd_colors_len)].resolve(wall_color)),
+                        }
+                    }
+                    StampCell::Window | StampCell::Wall => wall,
+                };
+            }
+        }
+        shade_face(canvas, b, layer, look, pos_xy, offset_xy, (iw, ih));
+        return;
+    }
+
+    for y in oy..oy+ih {
+        let r = canvas.get_row_mut(cy + (y - oy));
+
+        if y < ROOF_GAP_Y {
+            // draw upper corners
+
+            for x in row_x() {
+                if x >= g.roof_gap_x && x < right_gap_x {
+                    r[row_i(x)] = wall;
+                }
+            }
+        } else {
+            // draw normal walls and windows
+
+            let mut wnd_drawn_y = false;
+
+            if wnd_draw && y >= wnd_fst_xy.1 && y < wnd_lim_xy.1 {
+                let cwnd_pos_y  = (y - wnd_fst_xy.1) % wnd_unit_y;
+
+                if cwnd_pos_y < WINDOW_Y {
+                    wnd_drawn_y = true;
+                    let wnd_row = (y - wnd_fst_xy.1) / wnd_unit_y * WINDOW_Y + cwnd_pos_y;
+                    let mut wnd_clr = wall_color;
+
+                    for x in row_x() {
+                        let mut cell = wall;
+
+                        if x >= g.window_pad_l && x < wnd_lim_xy.0 {
+                            let cwnd_pos_x = (x - wnd_fst_xy.0) % wnd_unix_x;
+
+                            // also for a window cut by the left edge of the screen or a shard, else its
+                            // cells left on screen would be wall colored
+                            if cwnd_pos_x == 0 || (x == ox && cwnd_pos_x < g.window_x) {
+                                let wnd = b.windows[wnd_row * wnd_cols + (x - wnd_fst_xy.0) / wnd_unix_x];
+
+                                // same random value per window, so windows go dark one by one with age
+                                wnd_clr = match wnd.fade_roll < wnd_fade || row_i(x) < look.lights_off_x {
+                                    true => wall_color,
+                                    false => wnd_colors[wnd.color_i as usize].resolve(wall_color),
+                                };
+                            }
+
+                            if cwnd_pos_x < g.window_x {
+                                cell = window(wnd_clr);
+                            }
+                        }
+
+                        r[row_i(x)] = cell;
+                    }
+                }
+            }
+
+            if !wnd_drawn_y {
+                for x in row_x() {
+                    r[row_i(x)] = wall;
+                }
+            }
+        }
+    }
+    shade_face(canvas, b, layer, look, pos_xy, offset_xy, (iw, ih));
+}
+
+/// Turns the wall colored cells of a drawn building into a vertical RGB gradient,
+/// see `LayerDesc::face_gradient`. Dithered rows mix the two shades around theirs, in a
+/// pattern that moves with the building
+fn shade_face(canvas: &mut Vec2D<Cell>, b: &Building, layer: &LayerDesc, look: &Look,
+              pos_xy: (usize, usize), offset_xy: (usize, usize), size_xy: (usize, usize)) {
+    let ((cx, cy), (ox, oy), (iw, ih)) = (pos_xy, offset_xy, size_xy);
+    if layer.face_gradient == 0.0 {
+        return;
+    }
+
+    let (r, gr, bl) = Cell::rgb_of(b.color);
+    // 0.5 at the top .. -0.5 at the base in steps, blended toward white above 0 and black below
+    let color = |step: f32| {
+        let t = step / FACE_GRADIENT_STEPS;
+        let shade = |v: u8| match t >= 0.0 {
+            true => v as f32 + (255 - v) as f32 * t,
+            false => v as f32 * (1.0 + t),
+        }.round() as u8;
+        Cell::rgb(shade(r), shade(gr), shade(bl))
+    };
+    for y in oy..oy+ih {
+        let t = (0.5 - y as f32 / b.size_y.saturating_sub(1).max(1) as f32) * layer.face_gradient;
+        let at = t * FACE_GRADIENT_STEPS;
+        let (lower, upper, mix) = match look.dither {
+            true => (color(at.floor()), color(at.ceil()), at - at.floor()),
+            false => (color(at.round()), color(at.round()), 0.0),
+        };
+
+        let row = canvas.get_row_mut(cy + (y - oy));
+        for x in ox..ox+iw {
+            let cell = &mut row[cx + (x - ox)];
+            if cell.bg == b.color && covers(b, layer, look.geometry, (x, y)) {
+                let threshold = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0;
+                cell.bg = if mix > threshold { upper } else { lower };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::rc::Rc;
+
+    fn test_layers() -> Vec<LayerDesc> {
+        let layer = |density, speed, walls: &[PaletteColor], windows: &[PaletteColor]| LayerDesc {
+            density,
+            collision: 0.1,
+            speed,
+            wall_color: walls.iter().copied().collect(),
+            wall_weights: Default::default(),
+            draw_windows: !windows.is_empty(),
+            window_colors: windows.iter().map(|&c| WindowColor::Fixed(c)).collect(),
+            window_weights: Default::default(),
+            window_glyph: None,
+            age_fade: 0.0,
+            spawn_jitter: 0,
+            grow_ticks: 0,
+            face_gradient: 0.0,
+            allow_clip: true,
+            stamps: Vec::new(),
+            stamp_chance: 0.0,
+            sink: 0,
+            target_count: None,
+        };
+
+        vec![
+            layer(0.75, 4, &[47], &[]),
+            layer(0.6, 3, &[100, 101], &[]),
+            layer(0.4, 1, &[40], &[40, 107, 101]),
+        ]
+    }
+
+    fn layer0_sequence(layers: &[LayerDesc], layer_seeds: bool) -> Vec<Building> {
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, layers);
+        if layer_seeds {
+            city.seed_layers(42);
+        }
+
+        let mut spawned = Vec::new();
+        for _ in 0..2000 {
+            city.next_tick();
+            let last = city.layers[0].ring.back();
+            if let Some(b) = last.filter(|b| spawned.last().is_none_or(|s: &Building| s.spawn_tick != b.spawn_tick)) {
+                // ids count spawns on every layer, so they're left out
+                spawned.push(Building { id: 0, ..b.clone() });
+            }
+        }
+        spawned
+    }
+
+    #[test]
+    fn skyline_matches_canvas() {
+        let layers = test_layers();
+        for seed in [1, 42, 0x5eed] {
+            let rng = Rng::with_seed(seed);
+            let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+            city.warmup();
+
+            for _ in 0..300 {
+                city.next_tick();
+                let blank = Cell::blank(107);
+                assert_eq!(city.skyline(), &city.canvas.column_heights(|c| *c != blank)[..]);
             }
+        }
+    }
 
-            if !wnd_drawn_y {
-                for x in row_x() {
-                    r[row_i(x)] = wall_color;
+    #[test]
+    fn stamps_replace_buildings() {
+        let tower: Stamp = "   ##\n  #oo#\n ###### \n#o.##.o#\n########\n".parse().unwrap();
+        let mut layers = test_layers();
+        layers[2].stamps = vec![tower];
+        layers[2].stamp_chance = 1.0;
+
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.warmup();
+        city.next_tick();
+
+        assert!(city.layers[2].ring.iter().all(|b| b.stamp == Some(0) && b.size_x == 8 && b.size_y == 5));
+        let blank = Cell::blank(107);
+        assert_eq!(city.skyline(), &city.canvas.column_heights(|c| *c != blank)[..]);
+    }
+
+    #[test]
+    fn weighted_colors() {
+        let canvas = |layers: &[LayerDesc]| {
+            let rng = Rng::with_seed(42);
+            let mut city = City::new(150, 40, 1, &rng, 107, layers);
+            city.warmup();
+            city.next_tick();
+            city.canvas_raw().0.to_vec()
+        };
+
+        let mut uniform = test_layers();
+        uniform[1].wall_weights = [3, 3].iter().copied().collect();
+        uniform[2].window_weights = [1, 1, 1].iter().copied().collect();
+        assert_eq!(canvas(&uniform), canvas(&test_layers()));
+
+        let mut skewed = test_layers();
+        skewed[1].wall_weights = [0, 1].iter().copied().collect();
+        let cells = canvas(&skewed);
+        assert!(!cells.iter().any(|c| c.bg == 100));
+        assert!(cells.iter().any(|c| c.bg == 101));
+
+        let rng = Rng::with_seed(42);
+        let picks: Vec<usize> = (0..1000).map(|_| pick_weighted(&rng, &[1, 0, 3], 3)).collect();
+        assert!(!picks.contains(&1));
+        assert!((650..850).contains(&picks.iter().filter(|&&i| i == 2).count()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Step must be at least 1")]
+    fn zero_step_rejected() {
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        City::new(150, 40, 0, &rng, 107, &layers);
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one layer is needed")]
+    fn empty_layers_rejected() {
+        let rng = Rng::with_seed(42);
+        City::new(150, 40, 1, &rng, 107, &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Layer speed must be at least 1")]
+    fn zero_speed_rejected() {
+        let mut layers = test_layers();
+        layers[1].speed = 0;
+        let rng = Rng::with_seed(42);
+        City::new(150, 40, 1, &rng, 107, &layers);
+    }
+
+    #[test]
+    fn loop_period_repeats() {
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        let period = 600;
+        city.set_loop_period(period, 42);
+        city.warmup();
+        // buildings wider than the screen edge spawned before the first tick are still leaving
+        city.seek(city.get_tick() + period);
+
+        let mut frames = Vec::new();
+        for _ in 0..period * 2 {
+            city.next_tick();
+            frames.push(city.canvas.row_iter().flatten().copied().collect::<Vec<_>>());
+        }
+
+        let (first, second) = frames.split_at(period as usize);
+        assert!(first.iter().zip(second).all(|(a, b)| a == b));
+        assert!(first.windows(2).any(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn world_width_keeps_right_edge() {
+        let layers = test_layers();
+        let frames = |world_w: Option<usize>, view_x: Option<usize>| {
+            let rng = Rng::with_seed(42);
+            let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+            if let Some(w) = world_w {
+                city.set_world_width(w);
+            }
+            city.set_view_x(view_x);
+            (0..1500).map(|_| {
+                city.next_tick();
+                city.canvas_raw().0.to_vec()
+            }).collect::<Vec<_>>()
+        };
+
+        // buildings enter at the right screen edge just like without a world
+        let plain = frames(None, None);
+        assert_eq!(plain, frames(Some(400), None));
+        assert_eq!(plain, frames(Some(100), Some(0)));
+        assert_ne!(plain, frames(Some(400), Some(0)));
+    }
+
+    #[test]
+    fn shards_join_without_seams() {
+        assert_eq!("2/3".parse(), Ok(Shard { index: 2, count: 3 }));
+        assert_eq!(Shard { index: 2, count: 3 }.to_string(), "2/3");
+        assert!("0/3".parse::<Shard>().is_err() && "4/3".parse::<Shard>().is_err());
+
+        // shared random streams are seeded differently, only the shard seed is the same
+        let layers = test_layers();
+        let rngs = [Rng::with_seed(1), Rng::with_seed(2), Rng::with_seed(3)];
+        let mut whole = City::new(160, 30, 1, &rngs[0], 107, &layers);
+        whole.set_shard(Some(Shard { index: 1, count: 1 }), 9);
+        let mut shards: Vec<_> = (1..=2).map(|index| {
+            let mut c = City::new(80, 30, 1, &rngs[index], 107, &layers);
+            c.set_shard(Some(Shard { index, count: 2 }), 9);
+            c
+        }).collect();
+
+        for city in shards.iter_mut().chain([&mut whole]) {
+            city.warmup();
+        }
+        for _ in 0..200 {
+            for city in shards.iter_mut().chain([&mut whole]) {
+                city.next_tick();
+            }
+            for y in 0..30 {
+                let (left, right) = (shards[0].canvas.get_row(y), shards[1].canvas.get_row(y));
+                assert_eq!(whole.canvas.get_row(y), [left, right].concat(), "row {}", y);
+            }
+        }
+        assert_ne!(shards[0].canvas.as_flat_slice(), shards[1].canvas.as_flat_slice());
+    }
+
+    #[test]
+    fn growing_buildings_rise() {
+        let skylines = |grow_ticks| {
+            let mut layers = test_layers();
+            layers.iter_mut().for_each(|d| d.grow_ticks = grow_ticks);
+            let rng = Rng::with_seed(42);
+            let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+            (0..800).map(|_| {
+                city.next_tick();
+                let blank = Cell::blank(107);
+                assert_eq!(city.skyline(), &city.canvas.column_heights(|c| *c != blank)[..]);
+                city.skyline().to_vec()
+            }).collect::<Vec<_>>()
+        };
+
+        // buildings are fully grown one tick after spawning, before they enter the screen
+        let plain = skylines(0);
+        assert_eq!(plain, skylines(1));
+
+        let grown = skylines(2000);
+        assert_ne!(plain, grown);
+        assert!(plain.iter().flatten().zip(grown.iter().flatten()).all(|(p, g)| g >= p));
+    }
+
+    #[test]
+    fn palette_cycle_keeps_lit_windows() {
+        let canvas = |layers: &[LayerDesc], shift| {
+            let rng = Rng::with_seed(42);
+            let mut city = City::new(150, 40, 1, &rng, 107, layers);
+            city.set_palette_shift(shift, true);
+            city.warmup();
+            city.next_tick();
+            city.canvas_raw().0.to_vec()
+        };
+
+        // 40 is a wall color and 107 the background, so 101 is the only lit window color
+        let layers = test_layers();
+        let fixed = |colors: &[WindowColor]| colors.iter().map(|c| c.resolve(0)).collect::<Vec<_>>();
+        assert_eq!(fixed(&cycle_window_colors(&layers[2], 107, 1, true)), [40, 107, 101]);
+        assert_eq!(fixed(&cycle_window_colors(&layers[2], 107, 1, false)), [107, 101, 40]);
+        assert_eq!(canvas(&layers, 0), canvas(&layers, 1));
+
+        let mut lit = test_layers();
+        lit[2].window_colors.push(WindowColor::Fixed(45));
+        assert_eq!(fixed(&cycle_window_colors(&lit[2], 107, 1, true)), [40, 107, 45, 101]);
+        let (plain, shifted) = (canvas(&lit, 0), canvas(&lit, 1));
+        assert_eq!(plain, canvas(&lit, 2));
+
+        // 101 is also a wall color on another layer, those walls keep it
+        assert_ne!(plain, shifted);
+        assert!(plain.iter().zip(&shifted).all(|(p, s)| match p.bg {
+            45 => s.bg == 101,
+            101 => s.bg == 101 || s.bg == 45,
+            c => s.bg == c,
+        }));
+    }
+
+    #[test]
+    fn mirror_is_symmetric() {
+        let layers = test_layers();
+        for width in [150, 151] {
+            let rng = Rng::with_seed(42);
+            let mut city = City::new(width, 40, 1, &rng, 107, &layers);
+            city.set_mirror(true);
+            city.enable_double_buffer();
+            city.warmup();
+
+            for _ in 0..300 {
+                city.next_tick();
+                assert!(city.canvas.row_iter().all(|row| row.iter().eq(row.iter().rev())));
+                let blank = Cell::blank(107);
+                assert_eq!(city.skyline(), &city.canvas.column_heights(|c| *c != blank)[..]);
+            }
+            assert!(city.skyline().iter().any(|&h| h < 40));
+        }
+    }
+
+    #[test]
+    fn rising_plots_keep_gaps() {
+        let layers = test_layers();
+        let run = |resize: bool| {
+            let rng = Rng::with_seed(42);
+            let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+            city.set_rising(true);
+            city.warmup();
+            assert!(city.building_count() > 0);
+
+            let mut frames = Vec::new();
+            for i in 0..600 {
+                if resize && i == 300 {
+                    city.set_wh(90, 30);
+                }
+                city.next_tick();
+                let blank = Cell::blank(107);
+                assert_eq!(city.skyline(), &city.canvas.column_heights(|c| *c != blank)[..]);
+                for l in &city.layers {
+                    for (i, p) in l.plots.iter().enumerate() {
+                        assert!(l.plots[i + 1..].iter().all(|q| !q.overlaps(p.x, p.b.size_x)));
+                    }
+                }
+                frames.push(city.canvas_raw().0.to_vec());
+            }
+            frames
+        };
+
+        let frames = run(false);
+        assert_eq!(frames, run(false));
+        assert!(frames.windows(2).any(|w| w[0] != w[1]));
+        run(true);
+    }
+
+    #[test]
+    fn horizon_stays_behind_buildings() {
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.set_horizon(Some("5,103".parse().unwrap()));
+        city.warmup();
+
+        for size in [(150, 40), (100, 20)] {
+            city.set_wh(size.0, size.1);
+            city.next_tick();
+            let row = city.canvas.get_row(size.1 - 6);
+            assert!(row.iter().zip(city.skyline()).all(|(c, &top)| (c.bg == 103) == (top > size.1 - 6)));
+            assert!(city.canvas.row_iter().enumerate().all(|(y, r)| y == size.1 - 6 || !r.contains(&Cell::blank(103))));
+        }
+
+        city.set_horizon(None);
+        city.next_tick();
+        assert!(city.canvas.row_iter().all(|r| !r.contains(&Cell::blank(103))));
+    }
+
+    #[test]
+    fn grid_rulers_over_buildings() {
+        assert!("0,100".parse::<Grid>().is_err());
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(45, 25, 1, &rng, 107, &layers);
+        city.set_grid(Some("10,100".parse().unwrap()));
+        city.warmup();
+        city.next_tick();
+
+        let glyphs: String = city.canvas.get_row(24).iter().map(|c| c.glyph).collect();
+        assert_eq!(&glyphs[..], "┼┄┄┄┄┄┄┄┄┄┼┄┄┄┄┄┄┄┄┄┼┄┄┄┄┄┄┄┄┄┼┄┄┄┄┄┄┄┄┄┼┄┄┄┄");
+        let top: String = city.canvas.get_row(0).iter().map(|c| c.glyph).collect();
+        assert!(top.starts_with("┆         10        20        30        40"));
+        let left: String = city.canvas.col_iter(0).skip(4).map(|c| c.glyph).collect();
+        assert_eq!(left, "2┆┆┆┆┆┆┆┆┆1┆┆┆┆┆┆┆┆┆┼");
+        assert!(city.canvas.row_iter().flatten().filter(|c| "┼┄┆".contains(c.glyph)).all(|c| c.fg == 90));
+
+        city.set_grid(None);
+        city.next_tick();
+        assert!(city.canvas.row_iter().flatten().all(|c| !"┼┄┆".contains(c.glyph)));
+    }
+
+    #[test]
+    fn stars_behind_buildings() {
+        assert!("80,2".parse::<Stars>().is_err());
+        assert_eq!("80".parse::<Stars>().unwrap().to_string(), "80,0.001");
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.set_stars(Some(Stars { count: 300, shooting_chance: 1.0 }), 42);
+        city.warmup();
+
+        let mut shooting_seen = false;
+        for _ in 0..20 {
+            city.next_tick();
+            let mut stars = 0;
+            for (y, row) in city.canvas.row_iter().enumerate() {
+                for (x, cell) in row.iter().enumerate().filter(|(_, c)| "·+*/".contains(c.glyph)) {
+                    assert!(y < city.skyline[x], "star drawn over a building at ({}, {})", x, y);
+                    stars += 1;
+                    shooting_seen |= cell.glyph == '/';
+                }
+            }
+            assert!(stars > 0);
+        }
+        assert!(shooting_seen);
+
+        city.set_motion(MotionProfile::Calm);
+        for _ in 0..20 {
+            city.next_tick();
+            assert!(city.canvas.row_iter().flatten().all(|c| " ·".contains(c.glyph)), "calm stars flashed");
+        }
+
+        city.set_stars(None, 42);
+        city.next_tick();
+        assert!(city.canvas.row_iter().flatten().all(|c| c.glyph == ' '));
+    }
+
+    /// Line along a row and a dot at a column drawn from the effect stream each tick
+    #[derive(Debug)]
+    struct Beam {
+        y: usize,
+        dot_x: usize,
+    }
+
+    impl Effect for Beam {
+        fn tick(&mut self, ctx: &EffectCtx) {
+            self.dot_x = ctx.rng.usize(..ctx.size.0);
+        }
+
+        fn draw(&self, canvas: &mut EffectCanvas, ctx: &EffectCtx) {
+            (0..ctx.size.0).for_each(|x| canvas.put((x, self.y), '=', 37));
+            canvas.put((self.dot_x, 0), '@', 37);
+        }
+    }
+
+    #[test]
+    fn effects_keep_phases_and_streams() {
+        let layers = test_layers();
+        let beam = |phase| (phase, Some(Box::new(Beam { y: 39, dot_x: 0 }) as Box<dyn Effect>));
+        let (rng, plain_rng) = (Rng::with_seed(42), Rng::with_seed(42));
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        let mut plain = City::new(150, 40, 1, &plain_rng, 107, &layers);
+        let (phase, effect) = beam(EffectPhase::Behind);
+        city.set_effect("beam", phase, 7, effect);
+        city.warmup();
+        plain.warmup();
+
+        let beam_row = |c: &City| c.canvas.get_row(39).iter().map(|c| c.glyph == '=').collect::<Vec<_>>();
+        for _ in 0..20 {
+            city.next_tick();
+            plain.next_tick();
+            assert_eq!(city.visible_buildings(), plain.visible_buildings(), "the effect moved buildings");
+            let covered: Vec<_> = city.skyline().iter().map(|&top| top > 39).collect();
+            assert_eq!(beam_row(&city), covered);
+        }
+
+        let (phase, effect) = beam(EffectPhase::InFront);
+        city.set_effect("beam", phase, 7, effect);
+        city.next_tick();
+        assert!(beam_row(&city).iter().all(|&on| on));
+        assert_eq!(city.effects.len(), 1);
+
+        // the dot is where it would be had every tick been drawn
+        let dot = |c: &City| c.canvas.get_row(0).iter().position(|c| c.glyph == '@');
+        let seek_rng = Rng::with_seed(42);
+        let mut seeking = City::new(150, 40, 1, &seek_rng, 107, &layers);
+        let (phase, effect) = beam(EffectPhase::InFront);
+        seeking.set_effect("beam", phase, 7, effect);
+        seeking.seek(city.get_tick());
+        seeking.next_tick();
+        city.next_tick();
+        assert_eq!(dot(&seeking), dot(&city));
+
+        city.set_effect("beam", EffectPhase::InFront, 7, None);
+        city.next_tick();
+        assert!(city.effects.is_empty() && dot(&city).is_none());
+    }
+
+    #[test]
+    fn face_gradient_shades_walls() {
+        let flat_layers = test_layers();
+        let mut layers = test_layers();
+        layers[0].face_gradient = 1.0;
+        let (rng_a, rng_b) = (Rng::with_seed(42), Rng::with_seed(42));
+        let mut flat = City::new(150, 40, 1, &rng_a, 107, &flat_layers);
+        let mut shaded = City::new(150, 40, 1, &rng_b, 107, &layers);
+        flat.warmup();
+        shaded.warmup();
+        flat.next_tick();
+        shaded.next_tick();
+
+        let mut grays = Vec::new();
+        for (a, b) in flat.canvas_raw().0.iter().zip(shaded.canvas_raw().0) {
+            if a != b {
+                assert_eq!((a.bg, a.glyph), (47, b.glyph));
+                let (r, g, bl) = Cell::rgb_of(b.bg);
+                assert!(r == g && g == bl);
+                grays.push(r);
+            }
+        }
+        // halfway to white at the top and to black at the base
+        let (lightest, darkest) = (grays.iter().max().unwrap(), grays.iter().min().unwrap());
+        assert!(*lightest == 242 && (115..200).contains(darkest), "{} {}", lightest, darkest);
+        assert_eq!(Cell::sgr(Cell::rgb(1, 2, 3), ColorDepth::TrueColor).to_string(), "48;2;1;2;3");
+        assert_eq!(Cell::sgr(47, ColorDepth::TrueColor).to_string(), "47");
+    }
+
+    #[test]
+    fn rgb_on_lesser_terminals() {
+        assert_eq!(nearest_256((0, 0, 0)), 16);
+        assert_eq!(nearest_256((255, 255, 255)), 231);
+        assert_eq!(nearest_256((255, 0, 0)), 196);
+        assert_eq!(nearest_256((100, 140, 180)), 16 + 36 + 6 * 2 + 3);
+        assert_eq!(nearest_256((128, 128, 128)), 244);
+        assert_eq!(nearest_256((18, 18, 18)), 233);
+
+        assert_eq!(nearest_16((250, 250, 250)), 107);
+        assert_eq!(nearest_16((200, 10, 10)), 41);
+        assert_eq!(nearest_16((120, 125, 130)), 100);
+        // palette colors are their own nearest
+        for c in (40..=47).chain(100..=107) {
+            assert_eq!(nearest_16(Cell::rgb_of(c)), c);
+        }
+
+        let gray = Cell::rgb(128, 128, 128);
+        assert_eq!(Cell::sgr(gray, ColorDepth::Ansi256).to_string(), "48;5;244");
+        assert_eq!(Cell::sgr(gray, ColorDepth::Ansi16).to_string(), "100");
+        assert_eq!(Cell::sgr(44, ColorDepth::Ansi16).to_string(), "44");
+
+        // dark grays take the gray ramp over the cube's black and only turn gray at mid gray on 16 colors
+        let cases = [((20, 20, 30), 234, 40), ((40, 40, 40), 235, 40), ((60, 60, 60), 237, 40),
+                     ((90, 90, 90), 240, 100),
+                     // saturated yellows go bright or dark by how light they look, not their distance
+                     ((255, 215, 0), 220, 103), ((230, 230, 60), 185, 103), ((200, 170, 0), 178, 43),
+                     ((128, 128, 0), 100, 43)];
+        for (rgb, index, code) in cases {
+            assert_eq!((nearest_256(rgb), nearest_16(rgb)), (index, code), "{:?}", rgb);
+        }
+        for i in 16..=255 {
+            assert_eq!(nearest_256(xterm_256_rgb(i)), i);
+        }
+        assert_eq!(xterm_256_rgb(9), Cell::rgb_of(101));
+    }
+
+    #[test]
+    fn window_colors_follow_walls() {
+        let parse = |s: &str| s.parse::<WindowColor>();
+        assert_eq!(parse(" wall-light").unwrap().resolve(41), 101);
+        assert_eq!(parse("wall-dark").unwrap().resolve(101), 41);
+        assert_eq!(parse("wall").unwrap().resolve(47), 47);
+        assert_eq!(parse("103").unwrap().resolve(47), 103);
+        assert!(parse("wall-ish").is_err());
+        assert_eq!((WindowColor::WallLight.resolve(47), WindowColor::WallDark.resolve(47)), (107, 100));
+        for name in ["wall", "wall-light", "wall-dark", "45"] {
+            assert_eq!(parse(name).unwrap().to_string(), name);
+        }
+
+        // buildings of each wall color get windows of the matching shade
+        let mut layers = test_layers();
+        layers[2].window_colors = [WindowColor::WallLight, WindowColor::Wall].iter().copied().collect();
+        let d = &layers[2];
+        for (wall, light) in [(41, 101), (42, 102)] {
+            let mut b = Building { id: 0, size_x: 20, size_y: 30, spawn_tick: 1, color: wall, seed: 42,
+                                   jitter_x: 0, stamp: None, windows: Vec::new() };
+            b.windows = window_grid(&b, 2, d, &Geometry::FULL);
+            let mut canvas = Vec2D::new(40, 40, || Cell::blank(49));
+            let look = Look { age: 0.0, layer_key: layer_key(2, d), window_colors: &d.window_colors,
+                              geometry: &Geometry::FULL, lights_off_x: 0, dither: false };
+            draw_building(&mut canvas, &b, d, &look, (10, 10), (0, 0), (20, 30));
+
+            let mut colors: Vec<_> = canvas.row_iter().flatten().map(|c| c.bg).collect();
+            colors.sort_unstable();
+            colors.dedup();
+            assert_eq!(colors, [wall, 49, light]);
+        }
+    }
+
+    #[test]
+    fn face_dither_mixes_neighbor_shades() {
+        let mut layers = test_layers();
+        layers[0].face_gradient = 1.0;
+        let d = &layers[0];
+        let b = Building { id: 0, size_x: 20, size_y: 30, spawn_tick: 1, color: 47, seed: 42,
+                           jitter_x: 0, stamp: None, windows: Vec::new() };
+        let draw = |x, dither| {
+            let mut canvas = Vec2D::new(25, 30, || Cell::blank(49));
+            let look = Look { age: 0.0, layer_key: layer_key(0, d), window_colors: &d.window_colors,
+                              geometry: &Geometry::FULL, lights_off_x: 0, dither };
+            draw_building(&mut canvas, &b, d, &look, (x, 0), (0, 0), (20, 30));
+            canvas.row_iter().map(|row| row[x..x + 20].to_vec()).collect::<Vec<_>>()
+        };
+        // of the walls, roof gaps show the canvas
+        let shades = |rows: &[Vec<Cell>]| {
+            rows.iter().flatten().map(|c| c.bg).filter(|&bg| bg != 49).collect::<HashSet<_>>()
+        };
+
+        let (plain, dithered) = (draw(0, false), draw(0, true));
+        assert_eq!(shades(&plain), shades(&dithered));
+        let mut mixed = 0;
+        for (plain_row, row) in plain.iter().zip(&dithered) {
+            assert_eq!(shades(std::slice::from_ref(plain_row)).len(), 1);
+            match shades(std::slice::from_ref(row)).len() {
+                1 => {}
+                2 => mixed += 1,
+                n => panic!("{} shades in a row", n),
+            }
+        }
+        assert!(mixed > 10, "{} rows mixed", mixed);
+        // the pattern moves with the building
+        assert_eq!(draw(3, true), dithered);
+    }
+
+    #[test]
+    fn sky_dither_mixes_neighbors() {
+        let mut sky: Sky = "44,104,103".parse().unwrap();
+        sky.dither = true;
+        let row = |y| (0..16).map(|x| sky.cell_color((x, y), 30, 1)).collect::<Vec<_>>();
+        let share = |y, color| row(y).iter().filter(|&&c| c == color).count();
+
+        // band centers are solid, halfway between them the colors take turns
+        assert_eq!((share(5, 44), share(15, 104), share(24, 103)), (16, 16, 16));
+        assert_eq!((share(9, 44) + share(10, 44), share(9, 104) + share(10, 104)), (16, 16));
+        // the mix leans to the closer band and only mixes neighbors
+        assert!(share(7, 44) > share(7, 104) && share(12, 104) > share(12, 44));
+        assert!((0..15).all(|y| !row(y).contains(&103)) && (15..30).all(|y| !row(y).contains(&44)));
+    }
+
+    #[test]
+    fn sky_drift_wraps() {
+        let mut sky: Sky = "44,104,103".parse().unwrap();
+        let column = |sky: &Sky, tick| (0..30).map(|y| sky.row_color(y, 30, tick)).collect::<Vec<_>>();
+        let still = column(&sky, 1);
+        assert_eq!((still[0], still[15], still[29]), (44, 104, 103));
+
+        for drift in [3, -3] {
+            sky.drift = drift;
+            assert_eq!(column(&sky, 0), still);
+            assert_eq!(column(&sky, 2), still);
+            assert_ne!(column(&sky, 3), still);
+            assert_eq!(column(&sky, 3 * 60), still);
+            // neighboring rows never skip a band, also across the wrap
+            for tick in (0..3 * 60).step_by(3) {
+                let c = column(&sky, tick);
+                let band = |c| sky.colors.iter().position(|&s| s == c).unwrap() as i32;
+                assert!(c.windows(2).all(|w| (band(w[0]) - band(w[1])).abs() <= 1));
+            }
+        }
+        sky.drift = 3;
+        assert_eq!(column(&sky, 3)[1..], still[..29]);
+    }
+
+    #[test]
+    fn motion_blur_leaves_fading_trails() {
+        assert_eq!(blend(Cell::rgb(0, 0, 0), Cell::rgb(255, 255, 255), 128), Cell::rgb(127, 127, 127));
+        assert_eq!(blend(Cell::rgb(0, 0, 0), Cell::rgb(1, 1, 1), 128), Cell::rgb(0, 0, 0));
+        assert_eq!(blend(44, Cell::rgb(0, 0, 238), 128), 44);
+
+        let layers = test_layers();
+        let (rng, blurred_rng) = (Rng::with_seed(5), Rng::with_seed(5));
+        let mut sharp = City::new(100, 30, 1, &rng, 40, &layers);
+        let mut blurred = City::new(100, 30, 1, &blurred_rng, 40, &layers);
+        blurred.set_motion_blur(0.5);
+        for city in [&mut sharp, &mut blurred] {
+            city.warmup();
+            (0..10).for_each(|_| city.next_tick());
+        }
+
+        let (cells, _) = sharp.canvas.as_flat_slice();
+        let (blurred_cells, _) = blurred.canvas.as_flat_slice();
+        let smeared = cells.iter().zip(blurred_cells).filter(|(a, b)| a != b).count();
+        assert!(smeared > 0);
+        assert!(cells.iter().zip(blurred_cells).all(|(a, b)| (a.glyph, a.fg) == (b.glyph, b.fg)));
+
+        // trails are wiped from both buffers once it's off
+        blurred.set_motion_blur(0.0);
+        for city in [&mut sharp, &mut blurred] {
+            (0..2).for_each(|_| city.next_tick());
+        }
+        assert_eq!(sharp.canvas.as_flat_slice(), blurred.canvas.as_flat_slice());
+    }
+
+    #[test]
+    fn outro_turns_lights_off_then_fades() {
+        let layers = test_layers();
+        let (rng, outro_rng) = (Rng::with_seed(8), Rng::with_seed(8));
+        let mut lit = City::new(100, 30, 1, &rng, 107, &layers);
+        let mut outro = City::new(100, 30, 1, &outro_rng, 107, &layers);
+        for city in [&mut lit, &mut outro] {
+            city.set_background_gradient(vec![44, 104]);
+            city.warmup();
+        }
+
+        // windows in the left half are dark, nothing has faded yet
+        outro.set_outro(0.3);
+        for city in [&mut lit, &mut outro] {
+            city.next_tick();
+        }
+        let mut darkened = 0;
+        for (row, lit_row) in outro.canvas.row_iter().zip(lit.canvas.row_iter()) {
+            assert_eq!(row[50..], lit_row[50..]);
+            for (c, l) in row[..50].iter().zip(&lit_row[..50]).filter(|(c, l)| c != l) {
+                // only the front layer has windows
+                assert!(layers[2].wall_color.contains(&c.bg), "{:?} was {:?}", c, l);
+                darkened += 1;
+            }
+        }
+        assert!(darkened > 0);
+
+        outro.set_outro(1.0);
+        outro.next_tick();
+        for row in outro.canvas.row_iter() {
+            assert!(row.iter().all(|c| c.glyph == ' ' && Cell::rgb_of(c.bg) == Cell::rgb_of(row[0].bg)));
+        }
+    }
+
+    #[test]
+    fn background_changes_in_place() {
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(100, 30, 1, &rng, 107, &layers);
+        city.enable_double_buffer();
+        city.warmup();
+        city.next_tick();
+        city.next_tick();
+
+        city.set_background(44);
+        // both buffers are drawn again with the new color above the skyline
+        for _ in 0..2 {
+            city.next_tick();
+            for x in 0..100 {
+                assert!((0..city.skyline()[x]).all(|y| city.canvas[(x, y)] == Cell::blank(44)), "column {}", x);
+            }
+        }
+
+        city.set_background_gradient(vec![44, 104]);
+        city.next_tick();
+        let x = (0..100).find(|&x| city.skyline()[x] > 15).unwrap();
+        assert_eq!((city.canvas[(x, 0)].bg, city.canvas[(x, 15)].bg), (44, 104));
+    }
+
+    #[test]
+    fn long_frames_diff_against_the_shown_one() {
+        let layers = test_layers();
+        let rngs = [Rng::with_seed(42), Rng::with_seed(42)];
+        for blur in [0.0, 0.5] {
+            let new_city = |rng| {
+                let mut city = City::new(100, 30, 1, rng, 107, &layers);
+                city.enable_double_buffer();
+                city.set_motion_blur(blur);
+                city.warmup();
+                city.next_tick();
+                city
+            };
+            let (mut long, mut short) = (new_city(&rngs[0]), new_city(&rngs[1]));
+
+            // 12 ticks is an unfocused --power-save frame at a 60 fps target
+            for n in [5, 1, 12] {
+                let mut screen = long.get_canvas().as_flat_slice().0.to_vec();
+                long.run_ticks(n);
+                (0..n).for_each(|_| short.next_tick());
+
+                // the cells a diff render writes over the screen make the full frame
+                let prev = long.get_prev_canvas().unwrap().as_flat_slice().0;
+                let canvas = long.get_canvas().as_flat_slice().0;
+                for (i, cell) in canvas.iter().enumerate() {
+                    if *cell != prev[i] {
+                        screen[i] = *cell;
+                    }
+                }
+                assert_eq!(screen, canvas, "{} ticks, blur {}", n, blur);
+                // skipped draws don't change the picture, blurred ones blend in fewer frames
+                if blur == 0.0 {
+                    assert_eq!(short.get_canvas().as_flat_slice().0, canvas, "{} ticks", n);
+                }
+            }
+        }
+    }
+
+    /// Buildings spawned on layer 0 per screen width it scrolled
+    fn spawns_per_screen(layers: &[LayerDesc], step: Tick, legacy: bool) -> f32 {
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(100, 20, step, &rng, 107, layers);
+        city.set_legacy_spawn(legacy);
+
+        // scroll the same number of screens whatever the speed and step
+        let screens = 150;
+        let ticks = screens * 100 * layers[0].speed / step;
+        let mut spawns = 0;
+        for _ in 0..ticks {
+            city.advance(false);
+            spawns += city.layers[0].ring.back().is_some_and(|b| b.spawn_tick == city.tick - 1) as usize;
+        }
+        spawns as f32 / screens as f32
+    }
+
+    #[test]
+    fn density_is_buildings_per_screen() {
+        let mut layers = test_layers();
+        layers[0].collision = 0.0;
+        let unit = (BUILDING_MIN_W + BUILDING_MAX_W) as f32 / 2.0 + COLLISION_GAP as f32;
+
+        // denser layers on big steps are capped by the cells the edge stays blocked for
+        for density in [0.2, 0.45, 0.7] {
+            layers[0].density = density;
+            let expected = density * 100.0 / unit;
+            for (speed, step) in [(1, 1), (4, 1), (1, 3), (3, 2), (5, 5)] {
+                layers[0].speed = speed;
+                let realized = spawns_per_screen(&layers, step, false);
+                assert!((realized / expected - 1.0).abs() < 0.08,
+                        "density {} speed {} step {}: {} buildings per screen, expected {}",
+                        density, speed, step, realized, expected);
+            }
+        }
+
+        // the old chance thins out layers with bigger steps
+        layers[0].speed = 1;
+        let (step1, step3) = (spawns_per_screen(&layers, 1, true), spawns_per_screen(&layers, 3, true));
+        assert!(step3 < step1 * 0.8, "{} vs {}", step3, step1);
+    }
+
+    #[test]
+    fn target_count_is_kept() {
+        let mut layers = test_layers();
+        layers.truncate(1);
+        layers[0].collision = 0.5;
+
+        for (density, target, step) in [(0.1, 8, 1), (0.9, 3, 1), (0.3, 6, 2)] {
+            layers[0].density = density;
+            layers[0].target_count = Some(target);
+            let rng = Rng::with_seed(7);
+            let mut c = City::new(100, 30, step, &rng, 107, &layers);
+
+            let mut live = 0;
+            for t in 0..6000 {
+                c.next_tick();
+                if t >= 2000 {
+                    live += c.layers[0].live;
+                }
+            }
+            let average = live as f32 / 4000.0;
+            assert!((average / target as f32 - 1.0).abs() < 0.2,
+                    "density {} step {}: {} buildings on average, target {}", density, step, average, target);
+        }
+    }
+
+    #[test]
+    fn sunk_buildings_are_cut_at_the_bottom() {
+        let mut layers = test_layers();
+        layers.truncate(1);
+        let mut sunk_layers = layers.clone();
+        sunk_layers[0].sink = 6;
+        let (rng_a, rng_b) = (Rng::with_seed(5), Rng::with_seed(5));
+        let mut plain = City::new(80, 20, 1, &rng_a, 107, &layers);
+        let mut sunk = City::new(80, 20, 1, &rng_b, 107, &sunk_layers);
+
+        for _ in 0..300 {
+            plain.next_tick();
+            sunk.next_tick();
+            // everything moves down, the rows pushed below the bottom aren't drawn
+            for y in 0..20 - 6 {
+                assert_eq!(sunk.get_canvas().get_row(y + 6), plain.get_canvas().get_row(y));
+            }
+            assert!(sunk.visible_buildings().iter().all(|v| v.pos.1 + v.size.1 == 20));
+        }
+        assert!(!sunk.visible_buildings().is_empty());
+    }
+
+    #[test]
+    fn despawn_margin_keeps_buildings_longer() {
+        let layers = test_layers();
+        let (rng_a, rng_b) = (Rng::with_seed(42), Rng::with_seed(42));
+        let mut plain = City::new(100, 30, 1, &rng_a, 107, &layers);
+        let mut kept = City::new(100, 30, 1, &rng_b, 107, &layers);
+        kept.set_despawn_margin(12);
+        assert!(kept.building_cap() > plain.building_cap());
+
+        let mut lingered = 0;
+        for _ in 0..3000 {
+            plain.next_tick();
+            kept.next_tick();
+            // buildings past the edge aren't drawn, and don't change what spawns
+            assert_eq!(plain.canvas_raw(), kept.canvas_raw());
+            assert_eq!(plain.visible_buildings(), kept.visible_buildings());
+
+            let (front, front_kept) = (&plain.layers[2].ring, &kept.layers[2].ring);
+            assert!(front_kept.len() >= front.len());
+            assert!(front_kept.iter().rev().zip(front.iter().rev()).all(|(a, b)| a.id == b.id));
+            lingered = lingered.max(front_kept.len() - front.len());
+        }
+        // the margin fits at most two of the narrowest buildings with their gaps
+        assert!((1..=2).contains(&lingered), "{}", lingered);
+    }
+
+    fn worst_case_layers() -> Vec<LayerDesc> {
+        let mut layers = test_layers();
+        for d in &mut layers {
+            (d.density, d.collision, d.speed) = (1.0, 1.0, 1);
+        }
+        layers
+    }
+
+    #[test]
+    fn worst_case_spawns_stay_capped() {
+        let (layers, rng) = (worst_case_layers(), Rng::with_seed(42));
+        let mut city = City::new(60, 20, 1, &rng, 107, &layers);
+        let cap = city.building_cap();
+
+        let mut most = 0;
+        for _ in 0..5_000 {
+            city.advance(false);
+            most = most.max(city.layers[0].ring.len());
+            assert!(city.building_count() <= cap);
+        }
+        assert_eq!(most, spawn_cap(&layers[0], 60, &Geometry::FULL));
+    }
+
+    #[test]
+    #[ignore] // a million ticks, run with --ignored in release builds
+    fn worst_case_spawns_stay_bounded() {
+        let (layers, rng) = (worst_case_layers(), Rng::with_seed(42));
+        let mut city = City::new(60, 20, 1, &rng, 107, &layers);
+        let cap = city.building_cap();
+
+        let mut chunk_times = Vec::new();
+        for _ in 0..10 {
+            let start = std::time::Instant::now();
+            for _ in 0..100_000 {
+                city.advance(false);
+            }
+            chunk_times.push(start.elapsed());
+            assert!(city.building_count() <= cap);
+        }
+
+        // per tick time doesn't grow with the run
+        let first = chunk_times[..3].iter().min().unwrap();
+        assert!(chunk_times[7..].iter().min().unwrap() < &(*first * 3), "{:?}", chunk_times);
+    }
+
+    #[test]
+    fn building_look_depends_only_on_seed() {
+        let mut layers = test_layers();
+        layers[2].age_fade = 0.5;
+        layers[2].stamps = vec!["  ##  \n #oo# \n#o##o#\n######\n".parse().unwrap()];
+        let d = &layers[2];
+        let building = |seed, stamp| {
+            let mut b = Building { id: 0, size_x: 20, size_y: 30, spawn_tick: 1, color: 40, seed,
+                                   jitter_x: 0, stamp, windows: Vec::new() };
+            if stamp.is_some() {
+                (b.size_x, b.size_y) = d.stamps[0].size();
+            }
+            b.windows = window_grid(&b, 2, d, &Geometry::FULL);
+            b
+        };
+        let draw = |canvas: &mut Vec2D<Cell>, b: &Building, x| {
+            let (w, h) = (b.size_x, b.size_y.min(40));
+            let look = Look { age: 0.7, layer_key: layer_key(2, d), window_colors: &d.window_colors,
+                              geometry: &Geometry::FULL, lights_off_x: 0, dither: false };
+            draw_building(canvas, b, d, &look, (x, 40 - h), (0, b.size_y - h), (w, h));
+        };
+
+        for stamp in [None, Some(0)] {
+            let b = building(42, stamp);
+            let mut alone = Vec2D::new(150, 40, || Cell::blank(49));
+            draw(&mut alone, &b, 60);
+
+            // other buildings drawn before, including one overdrawn by b and b itself twice
+            let mut busy = Vec2D::new(150, 40, || Cell::blank(49));
+            for (seed, x) in [(1, 0), (2, 100), (3, 55)] {
+                draw(&mut busy, &building(seed, stamp), x);
+            }
+            draw(&mut busy, &b, 60);
+            draw(&mut busy, &b, 60);
+
+            // cells b leaves empty show whatever was there before
+            let blank = Cell::blank(49);
+            let cells = |c: &Vec2D<Cell>| c.row_iter().flat_map(|r| r[60..60 + b.size_x].to_vec()).collect::<Vec<_>>();
+            let (alone, busy) = (cells(&alone), cells(&busy));
+            assert!(alone.iter().any(|c| c.bg != 40 && c.bg != 49));
+            assert!(alone.iter().zip(&busy).all(|(a, b)| *a == blank || a == b));
+        }
+    }
+
+    #[test]
+    fn buildings_cut_by_the_left_edge_keep_their_windows() {
+        let layers = test_layers();
+        let d = &layers[2];
+        let mut b = Building { id: 0, size_x: 20, size_y: 30, spawn_tick: 1, color: 40, seed: 42,
+                               jitter_x: 0, stamp: None, windows: Vec::new() };
+        b.windows = window_grid(&b, 2, d, &Geometry::FULL);
+        let look = Look { age: 0.0, layer_key: layer_key(2, d), window_colors: &d.window_colors,
+                          geometry: &Geometry::FULL, lights_off_x: 0, dither: false };
+        let drawn = |ox: usize| {
+            let mut canvas = Vec2D::new(20 - ox, 30, || Cell::blank(49));
+            draw_building(&mut canvas, &b, d, &look, (0, 0), (ox, 0), (20 - ox, 30));
+            canvas
+        };
+
+        // the screen edge or a shard's left edge can fall inside a window
+        let whole = drawn(0);
+        for ox in 1..20 {
+            let cut = drawn(ox);
+            for y in 0..30 {
+                assert_eq!(cut.get_row(y), &whole.get_row(y)[ox..], "cut at {}, row {}", ox, y);
+            }
+        }
+    }
+
+    #[test]
+    fn layers_dont_repeat_buildings() {
+        let mut layers = test_layers();
+        layers[2].stamps = vec!["  ##  \n #oo# \n#o##o#\n######\n".parse().unwrap()];
+        let d = &layers[2];
+        let drawn = |d: &LayerDesc, layer_i, stamp: Option<usize>| {
+            let mut b = Building { id: 0, size_x: 20, size_y: 30, spawn_tick: 1, color: 40, seed: 42,
+                                   jitter_x: 0, stamp, windows: Vec::new() };
+            if stamp.is_some() {
+                (b.size_x, b.size_y) = d.stamps[0].size();
+            }
+            b.windows = window_grid(&b, layer_i, d, &Geometry::FULL);
+            let mut canvas = Vec2D::new(20, 30, || Cell::blank(49));
+            let look = Look { age: 0.0, layer_key: layer_key(layer_i, d), window_colors: &d.window_colors,
+                              geometry: &Geometry::FULL, lights_off_x: 0, dither: false };
+            draw_building(&mut canvas, &b, d, &look, (0, 0), (0, 0), (b.size_x, b.size_y));
+            canvas.row_iter().flatten().map(|c| c.bg).collect::<Vec<_>>()
+        };
+
+        // layers apart by their index, speed or walls, not by how dense they spawn
+        let faster = LayerDesc { speed: d.speed + 1, ..d.clone() };
+        let repainted = LayerDesc { wall_color: std::iter::once(41).collect(), ..d.clone() };
+        let denser = LayerDesc { density: d.density / 2.0, ..d.clone() };
+        for stamp in [None, Some(0)] {
+            assert_eq!(drawn(d, 0, stamp), drawn(d, 0, stamp));
+            assert_ne!(drawn(d, 0, stamp), drawn(d, 1, stamp));
+            assert_ne!(drawn(d, 1, stamp), drawn(d, 2, stamp));
+            assert_ne!(drawn(d, 2, stamp), drawn(&faster, 2, stamp));
+            assert_ne!(drawn(d, 2, stamp), drawn(&repainted, 2, stamp));
+            assert_eq!(drawn(d, 2, stamp), drawn(&denser, 2, stamp));
+        }
+    }
+
+    #[test]
+    fn windows_keep_colors_in_narrow_layouts() {
+        let layers = test_layers();
+        let b = Building { id: 0, size_x: 20, size_y: 30, spawn_tick: 1, color: 40, seed: 42,
+                           jitter_x: 0, stamp: None, windows: Vec::new() };
+        let rows = |g: &Geometry| {
+            let cols = window_grid_size(b.size_x, b.size_y, g).0;
+            let colors = window_grid(&b, 2, &layers[2], g).iter().map(|w| (w.color_i, w.fade_roll)).collect::<Vec<_>>();
+            colors.chunks(cols).map(|row| row.to_vec()).collect::<Vec<_>>()
+        };
+
+        let full = rows(&Geometry::FULL);
+        for w in [45, 30, 20] {
+            let narrow = rows(&Geometry::for_width(w));
+            assert_eq!(narrow.len(), full.len());
+            for (n, f) in narrow.iter().zip(&full) {
+                let shared = n.len().min(f.len());
+                assert_eq!(n[..shared], f[..shared], "width {}", w);
+            }
+        }
+    }
+
+    #[test]
+    fn drawn_tick_replays_the_frame() {
+        let layers = test_layers();
+        let (rng, replay_rng) = (Rng::with_seed(42), Rng::with_seed(42));
+        let mut city = City::new(100, 30, 2, &rng, 107, &layers);
+        city.seek(500);
+        city.next_tick();
+        city.next_tick();
+        assert_eq!(city.drawn_tick() + 1, city.get_tick());
+
+        // what a bookmark does: seek to the tick and draw it
+        let mut replay = City::new(100, 30, 2, &replay_rng, 107, &layers);
+        replay.seek(city.drawn_tick());
+        replay.next_tick();
+        assert_eq!(replay.canvas.as_flat_slice(), city.canvas.as_flat_slice());
+    }
+
+    #[test]
+    fn narrow_canvas_scales_buildings() {
+        assert_eq!(Geometry::for_width(GEOMETRY_FULL_W - 1), Geometry::FULL);
+        let g = Geometry::for_width(20);
+        assert_eq!((g.building_w, g.window_x, g.window_pad_l + g.window_pad_r), ((4, 10), 1, 3));
+        for w in 1..GEOMETRY_FULL_W {
+            let g = Geometry::for_width(w);
+            let b = Building { id: 0, size_x: g.building_w.0, size_y: 30, spawn_tick: 1, color: 40, seed: 42,
+                               jitter_x: 0, stamp: None, windows: Vec::new() };
+            assert!(!too_small(&b, &g) || g.building_w.0 == BUILDING_MIN_W, "width {}", w);
+        }
+
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.warmup();
+        let blank = Cell::blank(107);
+        for w in [30, 20, 45, 150] {
+            // buildings of the old size keep going with windows laid out for the new one
+            city.set_wh(w, 40);
+            let resized = city.tick;
+            for _ in 0..city.warmup_ticks() {
+                city.next_tick();
+                assert_eq!(city.skyline(), &city.canvas.column_heights(|c| *c != blank)[..]);
+            }
+            let max_w = Geometry::for_width(w).building_w.1;
+            let spawned = city.layers.iter().flat_map(|l| &l.ring).filter(|b| b.spawn_tick >= resized);
+            assert!(spawned.map(|b| b.size_x).all(|sx| sx <= max_w), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn unclipped_layers_fit_the_screen() {
+        let mut layers = test_layers();
+        layers[0].allow_clip = false;
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(100, 12, 1, &rng, 107, &layers);
+
+        let (mut fitted, mut clipped) = (0, 0);
+        for _ in 0..20_000 {
+            city.advance(false);
+            fitted = fitted.max(city.layers[0].ring.iter().map(|b| b.size_y).max().unwrap_or(0));
+            clipped = clipped.max(city.layers[2].ring.iter().map(|b| b.size_y).max().unwrap_or(0));
+        }
+        assert_eq!((fitted, clipped), (12, 14));
+    }
+
+    #[test]
+    fn building_ids_follow_buildings() {
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.warmup();
+
+        let (mut last, mut gone): (Vec<VisibleBuilding>, Vec<BuildingId>) = (Vec::new(), Vec::new());
+        for _ in 0..1000 {
+            city.next_tick();
+            let visible = city.visible_buildings();
+            for (i, v) in visible.iter().enumerate() {
+                assert!(!gone.contains(&v.id), "id {} came back", v.id);
+                // drawn oldest first on each layer
+                assert!(visible[..i].iter().all(|o| o.id != v.id && (o.layer != v.layer || o.id < v.id)));
+                if let Some(prev) = last.iter().find(|p| p.id == v.id) {
+                    assert_eq!(prev.layer, v.layer);
+                    assert!(v.pos.0 <= prev.pos.0);
+                }
+            }
+            gone.extend(last.iter().map(|p| p.id).filter(|id| visible.iter().all(|v| v.id != *id)));
+            last = visible.to_vec();
+        }
+        assert!(gone.len() > 20);
+    }
+
+    /// Effect keeping the buildings it was told about, like one following them would
+    #[derive(Debug, Default)]
+    struct Followers(Rc<RefCell<HashMap<BuildingId, BuildingInfo>>>);
+
+    impl Effect for Followers {
+        fn tick(&mut self, _ctx: &EffectCtx) {}
+        fn draw(&self, _canvas: &mut EffectCanvas, _ctx: &EffectCtx) {}
+
+        fn spawned(&mut self, b: &BuildingInfo) {
+            assert!(self.0.borrow_mut().insert(b.id, *b).is_none(), "id {} spawned twice", b.id);
+        }
+
+        fn despawned(&mut self, id: BuildingId) {
+            assert!(self.0.borrow_mut().remove(&id).is_some(), "id {} despawned unknown", id);
+        }
+    }
+
+    #[test]
+    fn building_maps_dont_leak() {
+        let layers = test_layers();
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.set_despawn_margin(10);
+        city.warmup();
+
+        // set after warmup, so the buildings already there are told first
+        let followed = Rc::new(RefCell::new(HashMap::new()));
+        city.set_effect("followers", EffectPhase::Behind, 0, Some(Box::new(Followers(followed.clone()))));
+        let matches = |city: &City| {
+            let followed = followed.borrow();
+            let ids: HashSet<_> = city.layers.iter().enumerate()
+                .flat_map(|(i, l)| l.ring.iter().chain(l.plots.iter().map(|p| &p.b)).map(move |b| b.info(i)))
+                .inspect(|b| assert_eq!(followed.get(&b.id), Some(b)))
+                .map(|b| b.id)
+                .collect();
+            ids.len() == followed.len()
+        };
+
+        let mut seen = HashSet::new();
+        for rising in [false, true] {
+            city.set_rising(rising);
+            city.reseed(7);
+            assert!(followed.borrow().is_empty());
+            for i in 0..3000 {
+                match i % 5 {
+                    0 => city.next_tick(),
+                    _ => city.run_ticks(3),
+                }
+                assert!(matches(&city), "tick {}", city.tick);
+                seen.extend(followed.borrow().keys().copied());
+            }
+        }
+        assert!(seen.len() > 100 + city.building_count());
+    }
+
+    #[test]
+    fn building_at_matches_canvas() {
+        let mut layers = test_layers();
+        layers[1].grow_ticks = 30;
+        let rng = Rng::with_seed(42);
+        let mut city = City::new(150, 40, 1, &rng, 107, &layers);
+        city.warmup();
+
+        for (w, h, mirror) in [(150, 40, false), (80, 12, false), (80, 30, true)] {
+            city.set_wh(w, h);
+            city.set_mirror(mirror);
+            for _ in 0..200 {
+                city.next_tick();
+            }
+            for x in 0..w {
+                let top = city.skyline()[x];
+                for y in 0..h {
+                    let at = city.building_at((x, y));
+                    assert_eq!(at.is_some(), y >= top, "({}, {}) at {}x{}", x, y, w, h);
+                    // front layer walls are the only black cells
+                    if let Some(b) = at.filter(|_| city.canvas[(x, y)].bg == 40) {
+                        assert_eq!((b.layer, b.color), (2, 40));
+                    }
                 }
             }
+            assert_eq!(city.building_at((w, 0)), None);
         }
     }
+
+    #[test]
+    fn layer_seeds_isolate_layers() {
+        let layers = test_layers();
+        let mut tweaked = test_layers();
+        tweaked[2].density = 0.9;
+
+        let seq = layer0_sequence(&layers, true);
+        assert!(!seq.is_empty());
+        assert_eq!(seq, layer0_sequence(&tweaked, true));
+
+        // shared rng interleaves layers, so the same tweak reshuffles layer 0
+        assert_ne!(layer0_sequence(&layers, false), layer0_sequence(&tweaked, false));
+    }
 }
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+const FILE_NAME: &str = "config.toml";
+
+/// Config file in the platform config directory, it may not exist
+pub fn default_path() -> Option<PathBuf> {
+    paths::config_dir().map(|d| d.join(FILE_NAME))
+}
+
+/// Reads a flat TOML file of `name = value` pairs, where names are long option names.
+/// Each line becomes the option arguments it stands for, numbered by line.
+/// Switches take booleans and are omitted when false
+pub fn load(path: &Path) -> io::Result<Vec<(usize, Vec<String>)>> {
+    let text = fs::read_to_string(path)?;
+    let mut lines = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData,
+                                                    format!("line {}: {}", line_no, msg));
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.find('=') {
+            Some(eq) => (line[..eq].trim(), line[eq + 1..].trim()),
+            None => return Err(invalid(format!("expected name = value, got '{}'", line))),
+        };
+
+        if key.is_empty() || key.starts_with('-') || key.starts_with('[') {
+            return Err(invalid(format!("bad option name '{}'", key)));
+        }
+
+        let option = format!("--{}", key);
+        let tokens = match parse_value(value).map_err(invalid)? {
+            Value::Bool(true) => vec![option],
+            Value::Bool(false) => vec![],
+            Value::Other(v) => vec![option, v],
+        };
+        lines.push((line_no, tokens));
+    }
+
+    Ok(lines)
+}
+
+enum Value {
+    Bool(bool),
+    Other(String),
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    let unterminated = || format!("unterminated string {}", value);
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some(c @ '"') | Some(c @ '\\') => out.push(c),
+                    Some(c) => return Err(format!("unknown escape \\{}", c)),
+                    None => return Err(unterminated()),
+                },
+                Some(c) => out.push(c),
+                None => return Err(unterminated()),
+            }
+        }
+        return check_trailing(chars.as_str()).map(|_| Value::Other(out));
+    }
+
+    if let Some(rest) = value.strip_prefix('\'') {
+        return match rest.find('\'') {
+            Some(end) => check_trailing(&rest[end + 1..]).map(|_| Value::Other(rest[..end].to_string())),
+            None => Err(unterminated()),
+        };
+    }
+
+    let value = match value.find('#') {
+        Some(comment) => value[..comment].trim_end(),
+        None => value,
+    };
+
+    Ok(match value {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "" => return Err("missing value".to_string()),
+        v => Value::Other(v.to_string()),
+    })
+}
+
+fn check_trailing(rest: &str) -> Result<(), String> {
+    match rest.trim() {
+        r if r.is_empty() || r.starts_with('#') => Ok(()),
+        r => Err(format!("unexpected '{}' after string", r)),
+    }
+}
@@ -1,65 +1,568 @@
-use std::fmt::{Display, Write as fmtWrite};
+use std::fmt::{self, Display, Write as fmtWrite};
 use std::io::Write as ioWrite;
-use std::io::StdoutLock;
+use std::io::{self, IsTerminal, StdoutLock};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-use crate::city::City;
-use crate::{STATUS_LINEFEEDS, TITLE_LINEFEEDS};
+use crate::city::{Cell, City, ColorDepth, PaletteColor, WHSize, FG_DEFAULT, GEOMETRY_FULL_W};
+use crate::STATUS_LINEFEEDS;
+use crate::title::Title;
+
+static CURSOR_HIDDEN: AtomicBool = AtomicBool::new(false);
+static MOUSE_REPORTING: AtomicBool = AtomicBool::new(false);
+static FOCUS_REPORTING: AtomicBool = AtomicBool::new(false);
+static IS_TTY: OnceLock<bool> = OnceLock::new();
+#[cfg(unix)]
+static STDOUT_FILE: OnceLock<Option<std::fs::File>> = OnceLock::new();
 
 pub const SIZE_DEFAULT_W: usize = 150;
 pub const SIZE_DEFAULT_H: usize = 40;
-pub const SIZE_MIN_W: usize = 50;
+pub const SIZE_MIN_W: usize = 20;
+pub const SIZE_FULL_W: usize = GEOMETRY_FULL_W; // narrower canvases get smaller buildings, no title and a shorter status
 pub const SIZE_MIN_H: usize = 10;
+pub const MINIMAP_W: usize = 40;
+pub const MINIMAP_H: usize = 5;
+pub const SPLASH_W: usize = 30;
+pub const WRITE_CHUNK_DEFAULT: usize = 1 << 16;
 pub const SIZE_AUTO_PAD_W: usize = 0;
-pub const SIZE_AUTO_PAD_H: usize = STATUS_LINEFEEDS + TITLE_LINEFEEDS + 1;
+const SKIP_RUN_MIN: usize = 5; // shorter runs are cheaper to print than to skip or erase
+pub const SIZE_AUTO_PAD_H: usize = STATUS_LINEFEEDS + 1; // title header lines are added on top
+
+/// Terminal cells kept free around the canvas
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Margins {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl Margins {
+    #[inline]
+    pub fn horizontal(&self) -> usize {
+        self.left + self.right
+    }
+
+    #[inline]
+    pub fn vertical(&self) -> usize {
+        self.top + self.bottom
+    }
+}
+
+impl FromStr for Margins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(',')
+            .map(|v| v.trim().parse::<usize>().map_err(|e| format!("{}: {}", v, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match values[..] {
+            [top, right, bottom, left] => Ok(Margins { top, right, bottom, left }),
+            _ => Err("expected top,right,bottom,left".to_string()),
+        }
+    }
+}
+
+impl Display for Margins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.top, self.right, self.bottom, self.left)
+    }
+}
+
+/// Order of the title header, canvas and status lines from top to bottom
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Layout {
+    #[default]
+    TitleTop, // title, canvas, status
+    StatusTop, // title, status, canvas
+    TitleBottom, // canvas, status, title
+}
+
+impl Layout {
+    /// Lines from the resting cursor line up to the first canvas row
+    pub fn canvas_up(self, height: usize, header_lines: usize, margins: &Margins) -> usize {
+        match self {
+            Layout::TitleTop => height + STATUS_LINEFEEDS + margins.bottom,
+            Layout::StatusTop => height - 1 + margins.bottom,
+            Layout::TitleBottom => height + STATUS_LINEFEEDS + header_lines + margins.bottom,
+        }
+    }
+
+    /// Moves from the last canvas row to the first status line
+    pub fn status_from_canvas(self, height: usize) -> CursorMove {
+        match self {
+            Layout::StatusTop => CursorMove::up(height + STATUS_LINEFEEDS),
+            _ => CursorMove::down(1),
+        }
+    }
+
+    /// Moves from the last status line to the resting cursor line
+    pub fn rest_from_status(self, height: usize, header_lines: usize, margins: &Margins) -> CursorMove {
+        match self {
+            Layout::TitleTop => CursorMove::down(margins.bottom),
+            Layout::StatusTop => CursorMove::down(height + margins.bottom),
+            Layout::TitleBottom => CursorMove::down(header_lines + margins.bottom),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title-top" => Ok(Layout::TitleTop),
+            "status-top" => Ok(Layout::StatusTop),
+            "title-bottom" => Ok(Layout::TitleBottom),
+            _ => Err("expected title-top, status-top or title-bottom".to_string()),
+        }
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Layout::TitleTop => "title-top",
+            Layout::StatusTop => "status-top",
+            Layout::TitleBottom => "title-bottom",
+        })
+    }
+}
 
-pub fn get_term_size() -> (usize, usize) {
-    match term_size::dimensions() {
-        Some((w, h)) => (w.saturating_sub(SIZE_AUTO_PAD_W), h.saturating_sub(SIZE_AUTO_PAD_H)),
+/// Moves cursor by N cells, prints nothing when N is 0.
+/// Piped output has no cursor, there it's spaces forward, newlines down and a single newline up,
+/// so frames follow each other
+pub struct CursorMove(usize, char);
+
+impl CursorMove {
+    #[inline]
+    pub fn fwd(n: usize) -> CursorMove {
+        CursorMove(n, 'C')
+    }
+
+    #[inline]
+    pub fn down(n: usize) -> CursorMove {
+        CursorMove(n, 'B')
+    }
+
+    #[inline]
+    pub fn up(n: usize) -> CursorMove {
+        CursorMove(n, 'A')
+    }
+}
+
+impl Display for CursorMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (0, _) => Ok(()),
+            (n, dir) if is_tty() => write!(f, "\x1b[{}{}", n, dir),
+            (n, 'C') => write!(f, "{:n$}", "", n = n),
+            (n, 'B') => (0..n).try_for_each(|_| f.write_char('\n')),
+            _ => f.write_char('\n'),
+        }
+    }
+}
+
+#[inline]
+pub fn get_term_dimensions() -> Option<(usize, usize)> {
+    term_size::dimensions()
+}
+
+/// Canvas size filling the terminal and whether the title header is shown above it.
+/// The header is left out when the canvas would be too small with it or is narrower than `SIZE_FULL_W`
+pub fn get_term_size(margins: &Margins, title: &Title) -> (usize, usize, bool) {
+    match get_term_dimensions() {
+        Some((w, h)) => {
+            let w = w.saturating_sub(SIZE_AUTO_PAD_W + margins.horizontal());
+            let h = h.saturating_sub(SIZE_AUTO_PAD_H + margins.vertical());
+            match h.checked_sub(title.header_lines(w)) {
+                Some(with_header) if with_header >= SIZE_MIN_H && w >= SIZE_FULL_W => (w, with_header, true),
+                _ => (w, h, false),
+            }
+        }
         None => panic!("Can't get terminal size, try removing -a"),
     }
 }
 
+/// Terminal columns and rows each part of the screen needs at least,
+/// the title header isn't listed since it's dropped when there's no room for it
+pub fn min_size_parts(margins: &Margins) -> [(&'static str, WHSize); 3] {
+    [
+        ("canvas", (SIZE_MIN_W, SIZE_MIN_H)),
+        ("status", (SIZE_AUTO_PAD_W, SIZE_AUTO_PAD_H)),
+        ("margins", (margins.horizontal(), margins.vertical())),
+    ]
+}
+
+/// Explains a terminal too small for the canvas, e.g.
+/// "Too small (45x11) < (50x12): canvas 50x10, status 2 rows"
+pub fn too_small_msg(term: Option<WHSize>, margins: &Margins) -> String {
+    let parts = min_size_parts(margins);
+    let (min_w, min_h) = parts.iter().fold((0, 0), |(w, h), (_, (pw, ph))| (w + pw, h + ph));
+    let (tw, th) = term.unwrap_or((0, 0));
+
+    let mut msg = format!("Too small ({}x{}) < ({}x{}):", tw, th, min_w, min_h);
+    let needs = parts.iter().filter(|(_, (w, h))| w + h > 0).map(|(name, wh)| match wh {
+        (0, h) => format!("{} {} rows", name, h),
+        (w, 0) => format!("{} {} columns", name, w),
+        (w, h) => format!("{} {}x{}", name, w, h),
+    });
+    for (i, need) in needs.enumerate() {
+        msg.push_str(if i > 0 { ", " } else { " " });
+        msg.push_str(&need);
+    }
+    msg
+}
+
+/// Extends margins so the canvas is centered in the terminal, vertical centering is optional
+pub fn center_margins(term: Option<(usize, usize)>, canvas: (usize, usize),
+                      margins: &Margins, title: &Title, vertical: bool) -> Margins {
+    let ((tw, th), (cw, ch)) = match term {
+        Some(term) => (term, canvas),
+        None => return *margins,
+    };
+
+    let free_w = tw.saturating_sub(SIZE_AUTO_PAD_W + margins.horizontal() + cw);
+    let free_h = th.saturating_sub(SIZE_AUTO_PAD_H + title.header_lines(cw) + margins.vertical() + ch);
+    let mut centered = *margins;
+
+    centered.left += free_w / 2;
+    centered.right += free_w - free_w / 2;
+    if vertical {
+        centered.top += free_h / 2;
+        centered.bottom += free_h - free_h / 2;
+    }
+
+    centered
+}
+
 pub fn clear_line_msg(lck: &mut StdoutLock, msg: impl Display) {
-    write!(lck, "\x1b[1;1H\x1b[2J{}", msg).unwrap();
+    match is_tty() {
+        true => write!(lck, "\x1b[1;1H\x1b[2J{}", msg),
+        false => writeln!(lck, "{}", msg),
+    }.unwrap();
     lck.flush().unwrap();
 }
 
+/// Progress bar on the current line, nothing is printed unless stdout is a terminal
+pub fn draw_splash(percent: u32, bg: PaletteColor) {
+    if !is_tty() {
+        return;
+    }
+    let filled = SPLASH_W * percent as usize / 100;
+    print!("\r\x1b[0m\x1b[{}m{}\x1b[0m{} generating city… {}%\x1b[K",
+           Cell::fg_of(bg), "█".repeat(filled), "░".repeat(SPLASH_W - filled), percent);
+    let _ = std::io::stdout().flush();
+}
+
+/// Erases the line `draw_splash` drew on
+pub fn clear_splash() {
+    if is_tty() {
+        print!("\r\x1b[0m\x1b[K");
+    }
+}
+
+/// Prints every background code a palette color can be, as labeled blocks
+/// in rows of normal and bright colors
+pub fn print_colors() {
+    for codes in [40..48, 100..108] {
+        for code in codes {
+            // dark label on light colors and the other way around
+            let fg = if code == 47 || code >= 100 { 30 } else { 97 };
+            print!("\x1b[{};{}m {:>3} \x1b[0m ", code, fg, code);
+        }
+        println!();
+    }
+}
+
+/// Size of a terminal cell in pixels, None when the terminal doesn't report it
+pub fn term_cell_pixels() -> Option<WHSize> {
+    #[cfg(unix)]
+    {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0;
+        if !ok || ws.ws_col == 0 || ws.ws_row == 0 || ws.ws_xpixel == 0 || ws.ws_ypixel == 0 {
+            return None;
+        }
+        Some(((ws.ws_xpixel / ws.ws_col) as usize, (ws.ws_ypixel / ws.ws_row) as usize))
+    }
+    #[cfg(not(unix))]
+    return None;
+}
+
+/// Whether stdout is a terminal, checked on the first call and kept. Escapes that hide or move
+/// the cursor or ask the terminal something are left out otherwise, they'd be garbage in a pipe
+pub fn is_tty() -> bool {
+    *IS_TTY.get_or_init(|| std::io::stdout().is_terminal())
+}
+
+/// How frames are written to the terminal
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOpts {
+    pub bce: bool, // erase blank runs at the end of rows instead of printing them, see term_has_bce
+    pub chunk: usize, // write out the frame every time this many bytes are buffered, 0 for one write
+    pub colors: ColorDepth, // RGB colors are written as the nearest ones the terminal has
+}
+
+/// Bytes written to the terminal and time spent writing them, apart from formatting
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WriteStats {
+    pub bytes: usize,
+    pub time: Duration,
+}
+
+impl WriteStats {
+    /// Writes `bytes` out at once, a slow terminal makes it wait here
+    pub fn write(&mut self, out: &mut StdoutLock, bytes: &[u8]) {
+        let start = Instant::now();
+        write_unbuffered(out, bytes).unwrap();
+        self.time += start.elapsed();
+        self.bytes += bytes.len();
+    }
+}
+
+/// Writes to stdout past its line buffering, which would take a write for the lines and one
+/// more for the rest after the last newline once it's flushed. Callers buffer the whole frame
+/// (or chunk) themselves, so it goes out in one write
+fn write_unbuffered(out: &mut StdoutLock, bytes: &[u8]) -> io::Result<()> {
+    // whatever print! left buffered goes first
+    out.flush()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsFd;
+        // a duplicate of stdout's fd, made once and never closed, writes to the same terminal.
+        // Holding `out` keeps other threads from printing in the middle of the frame
+        let file = STDOUT_FILE.get_or_init(|| out.as_fd().try_clone_to_owned().ok().map(Into::into));
+        if let Some(mut file) = file.as_ref() {
+            return file.write_all(bytes);
+        }
+    }
+    out.write_all(bytes)?;
+    out.flush()
+}
+
+/// Writes the buffer out and empties it once it holds at least `chunk` bytes,
+/// so frames of huge canvases don't have to be buffered whole
+pub fn write_chunk(buf: &mut String, out: &mut StdoutLock, chunk: usize, stats: &mut WriteStats) {
+    if chunk > 0 && buf.len() >= chunk {
+        stats.write(out, buf.as_bytes());
+        buf.clear();
+    }
+}
+
+/// Whether erasing fills cells with the current background color ("bce"), otherwise
+/// they get the default background. GNU screen (and tmux with its default TERM) doesn't by default
+pub fn term_has_bce() -> bool {
+    match std::env::var("TERM") {
+        Ok(term) => !(term == "dumb" || term.starts_with("screen")),
+        Err(_) => false,
+    }
+}
+
+/// Richest colors the terminal is known to show, from COLORTERM, TERM and on Windows
+/// WT_SESSION, which Windows Terminal sets. Only looked at once, at startup
+pub fn term_color_depth() -> ColorDepth {
+    let var = |name| std::env::var(name).ok();
+    let windows_terminal = cfg!(windows) && var("WT_SESSION").is_some();
+    color_depth_of(var("COLORTERM").as_deref(), var("TERM").as_deref(), windows_terminal)
+}
+
+fn color_depth_of(colorterm: Option<&str>, term: Option<&str>, windows_terminal: bool) -> ColorDepth {
+    match (colorterm, term) {
+        (Some("truecolor" | "24bit"), _) => ColorDepth::TrueColor,
+        _ if windows_terminal => ColorDepth::TrueColor,
+        (_, Some(term)) if term.ends_with("-direct") => ColorDepth::TrueColor,
+        (_, Some(term)) if term.contains("256color") => ColorDepth::Ansi256,
+        _ => ColorDepth::Ansi16,
+    }
+}
+
 pub fn setup_console() {
     //print!("\x1b[?1049h\x1b[1;1H\x1b[?25l"); // switch to alt buffer and disable cursor
-    print!("\x1b[?25l\x1b[0m") // disable cursor and clear styles
+    if is_tty() {
+        CURSOR_HIDDEN.store(true, Ordering::SeqCst);
+        print!("\x1b[?25l"); // disable cursor
+    }
+    print!("\x1b[0m") // clear styles
+}
+
+/// Shows the cursor again if setup_console hid it and stops mouse and focus reporting, safe to call
+/// from a panic hook
+pub fn restore_cursor() {
+    set_mouse_reporting(false);
+    set_focus_reporting(false);
+    if CURSOR_HIDDEN.swap(false, Ordering::SeqCst) {
+        print!("\x1b[?25h");
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Makes the terminal send clicks and pointer motion as input (SGR encoded, see `input::Event`).
+/// Text can't be selected meanwhile
+pub fn set_mouse_reporting(on: bool) {
+    if is_tty() && MOUSE_REPORTING.swap(on, Ordering::SeqCst) != on {
+        print!("{}", if on { "\x1b[?1003h\x1b[?1006h" } else { "\x1b[?1006l\x1b[?1003l" });
+        let _ = std::io::stdout().flush();
+    }
 }
 
-pub fn prepare_canvas(height: usize) {
-    for _ in 0..height + STATUS_LINEFEEDS {
+/// Makes the terminal send when it gains or loses focus as input, see `input::Event::Focus`
+pub fn set_focus_reporting(on: bool) {
+    if is_tty() && FOCUS_REPORTING.swap(on, Ordering::SeqCst) != on {
+        print!("{}", if on { "\x1b[?1004h" } else { "\x1b[?1004l" });
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Asks the terminal where the cursor is, the answer comes as `input::Event::CursorPos`
+pub fn request_cursor_pos(out: &mut StdoutLock) {
+    if !is_tty() {
+        return;
+    }
+    write!(out, "\x1b[6n").unwrap();
+    out.flush().unwrap();
+}
+
+pub fn prepare_margin_top(margins: &Margins) {
+    for _ in 0..margins.top {
+        println!();
+    }
+}
+
+/// Makes room for canvas and status, with the title below them only a line to start it on
+pub fn prepare_canvas(height: usize, margins: &Margins, layout: Layout) {
+    let below = match layout {
+        Layout::TitleBottom => 1,
+        _ => margins.bottom,
+    };
+    for _ in 0..height + STATUS_LINEFEEDS + below {
+        println!();
+    }
+}
+
+pub fn prepare_margin_bottom(margins: &Margins) {
+    for _ in 0..margins.bottom {
         println!();
     }
 }
 
 pub fn destroy_console() {
     //println!("\x1b[?25h\x1b[?1049l"); // enable cursor and switch to normal buffer
-    println!("\x1b[?25h"); // enable cursor
+    restore_cursor();
+    println!();
 }
 
-pub fn draw_to_console(c: &City, buf: &mut String, out: &mut StdoutLock) {
+/// Draws canvas rows starting `up` lines above the cursor, leaving it on the last row.
+/// Unless `full` is set, rows and runs of cells equal to the previous canvas
+/// (see `City::enable_double_buffer`) are skipped with newlines and cursor movement,
+/// so `full` must be set whenever the screen may not show the previous frame.
+/// Only full chunks are written, the rest is left in `buf` to be written with the status
+pub fn draw_to_console(c: &City, buf: &mut String, out: &mut StdoutLock, margins: &Margins, up: usize,
+                       full: bool, opts: &WriteOpts) -> WriteStats {
     buf.clear();
-    let (_, height) = c.get_size();
+    let mut stats = WriteStats::default();
 
     // move up to beginning and clear styles
-    write!(buf, "\x1b[0m\x1b[{}A\r", height + STATUS_LINEFEEDS).unwrap();
+    write!(buf, "\x1b[0m{}\r", CursorMove::up(up)).unwrap();
 
     let canvas = c.get_canvas();
-    let mut last_clr = 0;
-    for row in canvas.row_iter() {
-        for &color in row {
-            if last_clr != color {
-                last_clr = color;
-                write!(buf, "\x1b[{}m ", color).unwrap();
-            } else {
-                buf.push(' ');
+    // piped frames follow each other, each of them has to be whole
+    let prev = c.get_prev_canvas().filter(|_| !full && is_tty());
+    // nothing is printed for skipped cells, so the terminal styles still match last_bg and last_fg
+    let (mut last_bg, mut last_fg) = (0, FG_DEFAULT);
+    for (y, row) in canvas.row_iter().enumerate() {
+        if y > 0 {
+            buf.push('\n');
+        }
+        let prev_row = prev.map(|p| p.get_row(y));
+        if prev_row == Some(row) {
+            continue;
+        }
+        write!(buf, "{}", CursorMove::fwd(margins.left)).unwrap();
+
+        // unchanged cells at the end of a row are left as they are
+        let mut end = row.len();
+        if let Some(p) = prev_row {
+            end -= row.iter().rev().zip(p.iter().rev()).take_while(|(a, b)| a == b).count();
+        }
+
+        let mut erase = 0;
+        if let Some(&last) = row[..end].last().filter(|c| opts.bce && c.glyph == ' ') {
+            let run = row[..end].iter().rev().take_while(|&&c| c == last).count();
+            if run >= SKIP_RUN_MIN {
+                erase = run;
+                end -= run;
             }
         }
-        buf.push('\n');
+
+        let mut x = 0;
+        while x < end {
+            if let Some(p) = prev_row {
+                let same = row[x..end].iter().zip(&p[x..end]).take_while(|(a, b)| a == b).count();
+                if same >= SKIP_RUN_MIN {
+                    write!(buf, "{}", CursorMove::fwd(same)).unwrap();
+                    x += same;
+                    continue;
+                }
+            }
+
+            let cell = row[x];
+            if last_bg != cell.bg {
+                last_bg = cell.bg;
+                write!(buf, "\x1b[{}m", Cell::sgr(cell.bg, opts.colors)).unwrap();
+            }
+            // foreground doesn't matter for blank cells
+            if last_fg != cell.fg && cell.glyph != ' ' {
+                last_fg = cell.fg;
+                write!(buf, "\x1b[{}m", cell.fg).unwrap();
+            }
+            buf.push(cell.glyph);
+            x += 1;
+        }
+
+        if erase > 0 {
+            let bg = row[end].bg;
+            if last_bg != bg {
+                last_bg = bg;
+                write!(buf, "\x1b[{}m", Cell::sgr(bg, opts.colors)).unwrap();
+            }
+            // erase characters, unlike erase in line it stops before the right margin
+            write!(buf, "\x1b[{}X", erase).unwrap();
+        }
+        write_chunk(buf, out, opts.chunk, &mut stats);
     }
+    stats
+}
+
+/// Puts the minimap into `buf` like `draw_to_console` leaves the canvas there, nothing is written
+pub fn draw_minimap(c: &City, (width, height): WHSize, buf: &mut String, margins: &Margins, up: usize) {
+    buf.clear();
+    write!(buf, "\x1b[0m{}\r", CursorMove::up(up)).unwrap();
 
-    out.write_all(buf.as_bytes()).unwrap()
+    for (y, row) in c.minimap(width, height).lines().enumerate() {
+        if y > 0 {
+            buf.push('\n');
+        }
+        write!(buf, "{}{}", CursorMove::fwd(margins.left), row).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_depth_from_env() {
+        assert_eq!(color_depth_of(Some("truecolor"), Some("xterm"), false), ColorDepth::TrueColor);
+        assert_eq!(color_depth_of(Some("24bit"), None, false), ColorDepth::TrueColor);
+        assert_eq!(color_depth_of(None, Some("xterm-direct"), false), ColorDepth::TrueColor);
+        assert_eq!(color_depth_of(None, Some("screen-256color"), false), ColorDepth::Ansi256);
+        assert_eq!(color_depth_of(Some("yes"), Some("xterm-256color"), false), ColorDepth::Ansi256);
+        assert_eq!(color_depth_of(None, Some("xterm"), false), ColorDepth::Ansi16);
+        assert_eq!(color_depth_of(None, None, true), ColorDepth::TrueColor);
+        assert_eq!(color_depth_of(None, None, false), ColorDepth::Ansi16);
+    }
 }
@@ -0,0 +1,720 @@
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::bookmarks::{self, Bookmark};
+use crate::city::{City, LayerDesc, PaletteColor, Sky, Tick};
+use crate::console::{self, SIZE_MIN_H, SIZE_MIN_W};
+use crate::demo;
+use crate::input::Event;
+use crate::pbm::Ink;
+use crate::screenshot::{self, Frame};
+
+pub const FPS_RANGE: Range<u64> = 1..1000; // frame times of whole milliseconds
+pub const RAMP_START_FPS: f32 = 5.0;
+pub const POWER_SAVE_FPS: f32 = 5.0;
+pub const POWER_SAVE_IDLE: Duration = Duration::from_secs(2); // of unchanged frames before saving
+
+/// Something the main loop is asked to do, by a key press, a scenario, the control socket or Ctrl-C.
+/// Commands are sent to one channel and applied at the start of the next frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Bookmark,
+    ToggleInspector,
+    Scene(usize), // demo scene by index, the city starts over with its layers and sky
+    Seed(u64), // the city starts over with this seed
+    Sky(Vec<PaletteColor>), // one color for a plain background, more for a gradient
+    Fps(u64),
+    Pause,
+    Resume,
+    TogglePause,
+    ToggleLayer(usize), // layer index from the back, hidden layers keep moving
+    // last drawn frame as .ans (and .pbm with --screenshot-pbm) in the screenshot directory,
+    // or as a PBM at the path
+    Screenshot(Option<PathBuf>),
+    Stop,
+    // terminal settled on a new size, with the canvas size fitting it when it's auto-sized
+    Resize { term: Option<(usize, usize)>, canvas: Option<(usize, usize, bool)> },
+    Pointer { col: usize, row: usize }, // 1-based terminal cell under the mouse
+    CursorRow(usize), // 1-based terminal row the cursor was at when drawing started
+    Focus(bool), // terminal window gained or lost focus
+}
+
+impl Command {
+    /// Command bound to a key, if any. Digits toggle layers, 1 is the back one
+    pub fn from_key(key: u8) -> Option<Command> {
+        match key {
+            b'b' => Some(Command::Bookmark),
+            b'i' => Some(Command::ToggleInspector),
+            b'p' => Some(Command::TogglePause),
+            b'q' => Some(Command::Stop),
+            b's' => Some(Command::Screenshot(None)),
+            b'1'..=b'9' => Some(Command::ToggleLayer((key - b'1') as usize)),
+            _ => None,
+        }
+    }
+
+    pub fn from_event(event: Event) -> Option<Command> {
+        match event {
+            Event::Key(key) => Command::from_key(key),
+            Event::Pointer { col, row } => Some(Command::Pointer { col, row }),
+            Event::CursorPos { row, .. } => Some(Command::CursorRow(row)),
+            Event::Focus(focused) => Some(Command::Focus(focused)),
+        }
+    }
+}
+
+/// Parsed from a name followed by its argument, e.g. `fps 30` or `scene late night`
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, arg) = s.split_once(char::is_whitespace).map_or((s, ""), |(n, a)| (n, a.trim()));
+        let no_arg = |command| match arg {
+            "" => Ok(command),
+            _ => Err(format!("{} takes no argument", name)),
+        };
+        let need_arg = || match arg {
+            "" => Err(format!("{} needs an argument", name)),
+            arg => Ok(arg),
+        };
+
+        match name {
+            "bookmark" => no_arg(Command::Bookmark),
+            "inspector" => no_arg(Command::ToggleInspector),
+            "pause" => no_arg(Command::Pause),
+            "resume" => no_arg(Command::Resume),
+            "stop" | "quit" => no_arg(Command::Stop),
+            "layer" => match need_arg()?.parse() {
+                Ok(layer) => Ok(Command::ToggleLayer(layer)),
+                Err(e) => Err(format!("layer '{}': {}", arg, e)),
+            },
+            "scene" => {
+                let arg = need_arg()?;
+                demo::scene_index(arg).map(Command::Scene).ok_or_else(|| {
+                    format!("unknown scene '{}', expected one of {}", arg, demo::scene_names().join(", "))
+                })
+            }
+            "seed" => match need_arg()?.parse() {
+                Ok(seed) => Ok(Command::Seed(seed)),
+                Err(e) => Err(format!("seed '{}': {}", arg, e)),
+            },
+            "sky" => Ok(Command::Sky(need_arg()?.parse::<Sky>()?.colors)),
+            "fps" => match need_arg()?.parse() {
+                Ok(fps) if FPS_RANGE.contains(&fps) => Ok(Command::Fps(fps)),
+                Ok(_) => Err(format!("fps must be at least {} and below {}", FPS_RANGE.start, FPS_RANGE.end)),
+                Err(e) => Err(format!("fps '{}': {}", arg, e)),
+            },
+            "screenshot" => match arg {
+                "" => Ok(Command::Screenshot(None)),
+                path => Ok(Command::Screenshot(Some(path.into()))),
+            },
+            _ => Err(format!("unknown command '{}'", name)),
+        }
+    }
+}
+
+/// How the city was set up, for commands that start it over or save it
+pub struct Setup<'a> {
+    pub step: Tick,
+    pub layer_seeds: bool,
+    pub legacy_spawn: bool,
+    pub scene_layers: &'a [Vec<LayerDesc>], // layers of every demo scene, empty unless scenes can be shown
+    pub sky: Option<Sky>,
+    pub bg_color: PaletteColor,
+    pub bookmarks: bool, // false for demos, their scenes can't be reproduced from a bookmark
+    pub text_canvas: bool, // false for the minimap and sixels, the inspector needs terminal cells
+    pub pbm_scale: usize,
+    pub pbm_ink: Ink,
+    pub screenshot_dir: PathBuf,
+    pub screenshot_pbm: bool,
+}
+
+/// Everything in the main loop commands can change
+pub struct LoopState<'a> {
+    pub city: City<'a>,
+    pub seed: u64,
+    pub running: bool,
+    pub reset_console: bool, // console is set up again, e.g. for a new size
+    pub paused: bool,
+    pub pacer: TickPacer,
+    pub speed: Option<u32>, // cells a second of --speed, kept by `pace` whatever the fps
+    pub calm_interval: Option<Duration>, // least time between ticks of calm motion
+    pub target_frame_time_ms: u64,
+    pub frame_time: Duration,
+    pub size: (usize, usize), // may be below the minimum, the city keeps its last size then
+    pub show_header: bool,
+    pub term_wh: Option<(usize, usize)>,
+    pub inspecting: bool,
+    pub pointer: Option<(usize, usize)>,
+    pub cursor_row: Option<usize>, // asked for again after each console reset
+    pub focused: bool, // true unless the terminal reported losing focus
+    pub status_msg: Option<(String, Instant)>,
+    pub shown: Option<Frame>, // copy of the frame on screen, only made once the canvas stops showing it
+    pub shown_bg: PaletteColor, // background the frame on screen was drawn with
+}
+
+impl<'a> LoopState<'a> {
+    pub fn new(city: City<'a>, seed: u64, fps: u64, size: (usize, usize), show_header: bool,
+               term_wh: Option<(usize, usize)>) -> LoopState<'a> {
+        let target_frame_time_ms = 1000 / fps;
+        let shown_bg = city.get_background();
+        LoopState {
+            city,
+            seed,
+            running: true,
+            reset_console: true,
+            paused: false,
+            pacer: TickPacer::default(),
+            speed: None,
+            calm_interval: None,
+            target_frame_time_ms,
+            frame_time: Duration::from_millis(target_frame_time_ms),
+            size,
+            show_header,
+            term_wh,
+            inspecting: false,
+            pointer: None,
+            cursor_row: None,
+            focused: true,
+            status_msg: None,
+            shown: None,
+            shown_bg,
+        }
+    }
+
+    /// The canvas is on screen now. It's only copied once a command changes it or a
+    /// screenshot is taken, commands changing the background don't touch the canvas
+    pub fn drawn(&mut self) {
+        self.shown = None;
+        self.shown_bg = self.city.get_background();
+    }
+
+    /// Paces ticks of `step` cells for `fps`, so they keep `speed` and stay calm. Returns
+    /// the speed reached if one frame per tick is too slow for it
+    pub fn pace(&mut self, step: Tick, fps: u64) -> Option<u32> {
+        let speed_interval = self.speed.and_then(|speed| tick_interval(speed, step, fps));
+        let interval = match (speed_interval, self.calm_interval) {
+            (Some(speed), Some(calm)) => Some(speed.max(calm)),
+            (speed, calm) => speed.or(calm),
+        };
+        self.pacer = interval.map_or_else(TickPacer::default, TickPacer::new);
+        let reached = step as u64 * fps;
+        self.speed.filter(|&speed| speed as u64 > reached).map(|_| reached as u32)
+    }
+
+    /// Frame on screen, copied from the canvas unless it was already
+    fn keep_shown(&mut self) -> &Frame {
+        let (city, background) = (&self.city, self.shown_bg);
+        self.shown.get_or_insert_with(|| Frame::with_background(city, background))
+    }
+}
+
+/// Lets ticks run at most once per `interval` of target frame time, whatever the fps.
+/// Frames between them show the last tick again, a zero interval ticks every frame
+#[derive(Debug, Default)]
+pub struct TickPacer {
+    interval: Duration,
+    clock: Duration,
+}
+
+impl TickPacer {
+    pub fn new(interval: Duration) -> TickPacer {
+        TickPacer { interval, clock: Duration::ZERO }
+    }
+
+    /// Whether the frame taking `frame_time` runs a tick, frames slower than the interval tick once
+    pub fn due(&mut self, frame_time: Duration) -> bool {
+        self.clock += frame_time;
+        if self.clock < self.interval {
+            return false;
+        }
+        self.clock = (self.clock - self.interval).min(self.interval);
+        true
+    }
+}
+
+/// Step and, if ticks can't run every frame, the time between them that scroll layers of
+/// speed 1 by `cells_per_sec` cells a second at `fps`. Steps are whole cells, so speeds that
+/// aren't a multiple of the fps take a larger step less often than every frame
+pub fn step_for_speed(cells_per_sec: u32, fps: u64) -> (Tick, Option<Duration>) {
+    assert!(cells_per_sec > 0 && fps > 0, "Speed and fps must be at least 1");
+    let step = (cells_per_sec as u64).div_ceil(fps) as Tick;
+    (step, tick_interval(cells_per_sec, step, fps))
+}
+
+/// Time between ticks of `step` cells that scroll layers of speed 1 by `cells_per_sec` cells
+/// a second, None if that takes a tick every frame at `fps` or more
+pub fn tick_interval(cells_per_sec: u32, step: Tick, fps: u64) -> Option<Duration> {
+    let ticks_per_sec = cells_per_sec as f64 / step as f64;
+    (ticks_per_sec < fps as f64).then(|| Duration::from_secs_f64(1.0 / ticks_per_sec))
+}
+
+/// Frame rate easing from `RAMP_START_FPS` up to the target over `duration` after the start,
+/// for a calmer first impression. Longer frames run the ticks of the target frames they
+/// stand for (see `OwedFrames`), so the city moves at the same speed throughout
+#[derive(Debug)]
+pub struct FpsRamp {
+    duration: Duration,
+}
+
+impl FpsRamp {
+    pub fn new(duration: Duration) -> FpsRamp {
+        FpsRamp { duration }
+    }
+
+    /// Frame time `elapsed` into the ramp to a target of `target`, None once it's reached
+    pub fn frame_time(&self, elapsed: Duration, target: Duration) -> Option<Duration> {
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        let target_fps = 1.0 / target.as_secs_f32();
+        if progress >= 1.0 || target_fps <= RAMP_START_FPS {
+            return None;
+        }
+        let eased = progress * progress * (3.0 - 2.0 * progress);
+        Some(Duration::from_secs_f32(1.0 / (RAMP_START_FPS + (target_fps - RAMP_START_FPS) * eased)))
+    }
+}
+
+/// Target frames frames longer than the target stand for, they run the ticks of all of them
+#[derive(Debug, Default)]
+pub struct OwedFrames(f32); // not run yet, less than one
+
+impl OwedFrames {
+    /// Target frames of `target` a frame of `frame_time` runs, the fraction left is carried over
+    pub fn take(&mut self, frame_time: Duration, target: Duration) -> u32 {
+        self.0 += frame_time.as_secs_f32() / target.as_secs_f32();
+        let whole = self.0.floor();
+        self.0 -= whole;
+        whole as u32
+    }
+}
+
+/// Frame rate of --power-save: `POWER_SAVE_FPS` while the terminal isn't focused or after
+/// the picture stayed the same for `POWER_SAVE_IDLE`, the target otherwise
+#[derive(Debug, Default)]
+pub struct PowerSave {
+    unchanged_since: Option<Instant>,
+}
+
+impl PowerSave {
+    /// Notes whether the frame drawn at `now` changed the picture
+    pub fn drawn(&mut self, changed: bool, now: Instant) {
+        match changed {
+            true => self.unchanged_since = None,
+            false => {
+                self.unchanged_since.get_or_insert(now);
+            }
+        }
+    }
+
+    /// Frame time saving power at `now`, None if the target of `target` is kept
+    pub fn frame_time(&self, focused: bool, now: Instant, target: Duration) -> Option<Duration> {
+        let idle = self.unchanged_since.is_some_and(|since| now.duration_since(since) >= POWER_SAVE_IDLE);
+        let frame = Duration::from_secs_f32(1.0 / POWER_SAVE_FPS);
+        Some(frame).filter(|&frame| (idle || !focused) && frame > target)
+    }
+}
+
+/// Plain background for one color, a gradient for more
+pub fn set_sky_colors(c: &mut City, colors: &[PaletteColor]) {
+    match colors {
+        [] => {}
+        &[color] => {
+            c.set_sky(None);
+            c.set_background(color);
+        }
+        colors => c.set_background_gradient(colors.to_vec()),
+    }
+}
+
+/// Starts the city over with the layers of a demo scene, `sky` and `bg_color` are the
+/// ones the city was started with for scenes without a sky of their own
+pub fn show_scene<'a>(c: &mut City<'a>, layers: &'a [LayerDesc], scene_i: usize, sky: Option<Sky>,
+                      bg_color: PaletteColor) {
+    c.set_layers(layers);
+    c.set_sky(sky);
+    c.set_background(bg_color);
+    set_sky_colors(c, demo::scene_sky(scene_i));
+}
+
+/// Carries out a command, commands worth telling about leave a status message
+pub fn apply_command<'a>(state: &mut LoopState<'a>, setup: &Setup<'a>, command: Command) {
+    let msg = match command {
+        Command::Bookmark if !setup.bookmarks => "demo scenes can't be bookmarked".to_string(),
+        Command::Bookmark => {
+            let (width, height) = state.city.get_size();
+            let b = Bookmark { seed: state.seed, tick: state.city.drawn_tick(), width, height, step: setup.step,
+                               layer_seeds: setup.layer_seeds, legacy_spawn: setup.legacy_spawn };
+            match bookmarks::append(&b) {
+                Ok(n) => format!("bookmark {} saved", n),
+                Err(e) => format!("can't save bookmark: {}", e),
+            }
+        }
+        Command::ToggleInspector if !setup.text_canvas => "inspector needs the text canvas".to_string(),
+        Command::ToggleInspector => {
+            state.inspecting = !state.inspecting;
+            state.pointer = None;
+            console::set_mouse_reporting(state.inspecting);
+            let msg = if state.inspecting { "inspector on, point at a building" } else { "inspector off" };
+            msg.to_string()
+        }
+        Command::Scene(scene_i) => match setup.scene_layers.get(scene_i) {
+            Some(layers) => {
+                show_scene(&mut state.city, layers, scene_i, setup.sky.clone(), setup.bg_color);
+                if setup.layer_seeds {
+                    state.city.seed_layers(state.seed);
+                }
+                state.city.warmup();
+                format!("scene: {}", demo::scene_name(scene_i))
+            }
+            None => format!("no scene {}", scene_i),
+        },
+        Command::Seed(seed) => {
+            state.seed = seed;
+            state.city.reseed(seed);
+            state.city.warmup();
+            format!("seed: {}", seed)
+        }
+        Command::Sky(colors) => {
+            set_sky_colors(&mut state.city, &colors);
+            return;
+        }
+        Command::Fps(fps) => {
+            state.target_frame_time_ms = 1000 / fps;
+            state.frame_time = Duration::from_millis(state.target_frame_time_ms);
+            // the step stays, changing it would move every building on screen
+            match state.pace(setup.step, fps) {
+                Some(reached) => format!("fps: {}, {} cells a second at most", fps, reached),
+                None => return,
+            }
+        }
+        Command::Pause | Command::Resume => {
+            state.paused = command == Command::Pause;
+            return;
+        }
+        Command::TogglePause => {
+            state.paused = !state.paused;
+            if state.paused { "paused" } else { "resumed" }.to_string()
+        }
+        Command::ToggleLayer(layer) => match state.city.toggle_layer(layer) {
+            Some(true) => format!("layer {} hidden", layer),
+            Some(false) => format!("layer {} shown", layer),
+            None => format!("no layer {}", layer),
+        },
+        Command::Screenshot(_) if !setup.text_canvas => "screenshots need the text canvas".to_string(),
+        Command::Screenshot(path) => {
+            let frame = state.keep_shown();
+            match path {
+                Some(path) => match frame.write_pbm(&path, setup.pbm_scale, &setup.pbm_ink) {
+                    Ok(()) => format!("saved {}", path.display()),
+                    Err(e) => format!("can't write {}: {}", path.display(), e),
+                },
+                None => {
+                    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+                    let pbm = setup.screenshot_pbm.then_some((setup.pbm_scale, &setup.pbm_ink));
+                    match screenshot::save(frame, &setup.screenshot_dir, secs, pbm) {
+                        Ok(path) if pbm.is_some() => format!("saved {} and .pbm", path.display()),
+                        Ok(path) => format!("saved {}", path.display()),
+                        Err(e) => format!("can't save screenshot in {}: {}", setup.screenshot_dir.display(), e),
+                    }
+                }
+            }
+        }
+        Command::Stop => {
+            state.running = false;
+            return;
+        }
+        Command::Resize { term, canvas } => {
+            state.term_wh = term;
+            if let Some((w, h, header)) = canvas {
+                state.show_header = header;
+                if w >= SIZE_MIN_W && h >= SIZE_MIN_H {
+                    state.keep_shown();
+                    state.city.set_wh(w, h);
+                }
+                state.size = (w, h);
+            }
+            state.reset_console = true;
+            return;
+        }
+        Command::Pointer { col, row } => {
+            if state.inspecting {
+                state.pointer = Some((col, row));
+            }
+            return;
+        }
+        Command::CursorRow(row) => {
+            state.cursor_row = Some(row);
+            return;
+        }
+        Command::Focus(focused) => {
+            state.focused = focused;
+            return;
+        }
+    };
+    state.status_msg = Some((msg, Instant::now()));
+}
+
+#[cfg(test)]
+mod tests {
+    use fastrand::Rng;
+
+    use super::*;
+    use crate::console::{SIZE_DEFAULT_H, SIZE_DEFAULT_W};
+    use crate::{default_layers, BG_COLOR};
+
+    fn setup(scene_layers: &[Vec<LayerDesc>]) -> Setup<'_> {
+        Setup {
+            step: 1, layer_seeds: false, legacy_spawn: false, scene_layers, sky: None, bg_color: BG_COLOR,
+            bookmarks: false, text_canvas: false, pbm_scale: 1, pbm_ink: Ink::default(),
+            screenshot_dir: PathBuf::new(), screenshot_pbm: false,
+        }
+    }
+
+    fn state<'a>(rng: &'a Rng, layers: &'a [LayerDesc]) -> LoopState<'a> {
+        let city = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, rng, BG_COLOR, layers);
+        LoopState::new(city, 1, 60, (SIZE_DEFAULT_W, SIZE_DEFAULT_H), false, None)
+    }
+
+    fn msg<'s>(state: &'s LoopState) -> &'s str {
+        state.status_msg.as_ref().map_or("", |(msg, _)| msg)
+    }
+
+    #[test]
+    fn loop_commands() {
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let setup = setup(&[]);
+        let mut s = state(&rng, &layers);
+
+        apply_command(&mut s, &setup, Command::Pause);
+        assert!(s.paused);
+        apply_command(&mut s, &setup, Command::Resume);
+        assert!(!s.paused);
+        apply_command(&mut s, &setup, Command::TogglePause);
+        assert!(s.paused && msg(&s) == "paused");
+
+        apply_command(&mut s, &setup, Command::Fps(30));
+        assert_eq!((s.target_frame_time_ms, s.frame_time), (33, Duration::from_millis(33)));
+
+        apply_command(&mut s, &setup, Command::from_event(Event::Focus(false)).unwrap());
+        assert!(!s.focused);
+
+        apply_command(&mut s, &setup, Command::Stop);
+        assert!(!s.running);
+    }
+
+    #[test]
+    fn fps_ramp_keeps_the_tick_rate() {
+        let (secs, target) = (Duration::from_secs(2), Duration::from_secs_f32(1.0 / 60.0));
+        let ramp = FpsRamp::new(secs);
+        assert_eq!(ramp.frame_time(Duration::ZERO, target).map(|d| d.as_millis()), Some(200));
+        assert_eq!(ramp.frame_time(secs, target), None);
+        assert_eq!(ramp.frame_time(Duration::ZERO, Duration::from_millis(250)), None);
+
+        // frames get shorter, the ticks they run add up to the target rate
+        let (mut elapsed, mut ticks, mut last) = (Duration::ZERO, 0, Duration::MAX);
+        let mut owed = OwedFrames::default();
+        while let Some(frame) = ramp.frame_time(elapsed, target) {
+            assert!(frame <= last);
+            last = frame;
+            ticks += owed.take(frame, target);
+            elapsed += frame;
+        }
+        let expected = elapsed.as_secs_f32() * 60.0;
+        assert!((ticks as f32 - expected).abs() <= 1.0, "{} ticks in {:?}", ticks, elapsed);
+    }
+
+    #[test]
+    fn power_save_when_idle_or_unfocused() {
+        let target = Duration::from_secs_f32(1.0 / 60.0);
+        let saving = Some(Duration::from_secs_f32(1.0 / POWER_SAVE_FPS));
+        let start = Instant::now();
+        let mut save = PowerSave::default();
+        assert_eq!(save.frame_time(true, start, target), None);
+        assert_eq!(save.frame_time(false, start, target), saving);
+        assert_eq!(save.frame_time(false, start, Duration::from_secs(1)), None);
+
+        save.drawn(false, start);
+        save.drawn(false, start + POWER_SAVE_IDLE / 2);
+        assert_eq!(save.frame_time(true, start + POWER_SAVE_IDLE / 2, target), None);
+        assert_eq!(save.frame_time(true, start + POWER_SAVE_IDLE, target), saving);
+        save.drawn(true, start + POWER_SAVE_IDLE);
+        assert_eq!(save.frame_time(true, start + POWER_SAVE_IDLE * 2, target), None);
+    }
+
+    #[test]
+    fn speed_is_fps_independent() {
+        // the same as -t 1 -f 60
+        assert_eq!(step_for_speed(60, 60), (1, None));
+        assert_eq!(step_for_speed(120, 60), (2, None));
+        assert_eq!(step_for_speed(60, 120), (1, Some(Duration::from_secs_f64(1.0 / 60.0))));
+        assert_eq!(step_for_speed(90, 60), (2, Some(Duration::from_secs_f64(1.0 / 45.0))));
+
+        for (speed, fps) in [(60, 60), (60, 120), (90, 60), (7, 30), (250, 60), (30, 144)] {
+            let (step, interval) = step_for_speed(speed, fps);
+            let mut pacer = TickPacer::new(interval.unwrap_or_default());
+            let frame = Duration::from_secs_f64(1.0 / fps as f64);
+            let ticks = (0..fps * 10).filter(|_| pacer.due(frame)).count() as u32;
+            let cells_per_sec = (ticks * step) as f32 / 10.0;
+            assert!((cells_per_sec - speed as f32).abs() <= step as f32, "{} at {} fps: {}", speed, fps, cells_per_sec);
+        }
+    }
+
+    #[test]
+    fn fps_changes_keep_the_speed() {
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let setup = setup(&[]);
+        let mut s = state(&rng, &layers);
+        s.speed = Some(60);
+        assert_eq!(s.pace(setup.step, 60), None);
+
+        let cells_per_sec = |s: &mut LoopState| {
+            let frames = 10_000 / s.target_frame_time_ms;
+            (0..frames).filter(|_| s.pacer.due(s.frame_time)).count() as f32 * setup.step as f32 / 10.0
+        };
+        for fps in [120, 144, 75] {
+            apply_command(&mut s, &setup, Command::Fps(fps));
+            assert!((cells_per_sec(&mut s) - 60.0).abs() <= 1.0, "{} fps", fps);
+        }
+
+        // a tick every frame is as fast as the step goes
+        apply_command(&mut s, &setup, Command::Fps(40));
+        assert_eq!(msg(&s), "fps: 40, 40 cells a second at most");
+        assert_eq!(cells_per_sec(&mut s), 40.0);
+
+        // calm motion stays the slower one
+        s.calm_interval = Some(Duration::from_millis(50));
+        apply_command(&mut s, &setup, Command::Fps(120));
+        assert!((cells_per_sec(&mut s) - 20.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn pacer_caps_ticks() {
+        let mut pacer = TickPacer::new(Duration::from_secs(1));
+        let frame = Duration::from_millis(250);
+        let ticks: Vec<bool> = (0..8).map(|_| pacer.due(frame)).collect();
+        assert_eq!(ticks, [false, false, false, true, false, false, false, true]);
+
+        // slow frames tick once each, the time they overshoot doesn't pile up
+        let ticks: Vec<bool> = (0..4).map(|_| pacer.due(Duration::from_secs(3))).collect();
+        assert_eq!(ticks, [true; 4]);
+        assert!(TickPacer::default().due(Duration::ZERO));
+    }
+
+    #[test]
+    fn resize_resets_console() {
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let setup = setup(&[]);
+        let mut s = state(&rng, &layers);
+        s.reset_console = false;
+
+        apply_command(&mut s, &setup, Command::Resize { term: Some((100, 40)), canvas: None });
+        assert!(s.reset_console);
+        assert_eq!((s.term_wh, s.size), (Some((100, 40)), (SIZE_DEFAULT_W, SIZE_DEFAULT_H)));
+
+        let canvas = Some((SIZE_DEFAULT_W + 10, SIZE_DEFAULT_H + 5, true));
+        apply_command(&mut s, &setup, Command::Resize { term: Some((200, 60)), canvas });
+        assert_eq!(s.city.get_size(), (SIZE_DEFAULT_W + 10, SIZE_DEFAULT_H + 5));
+        assert!(s.show_header);
+
+        // too small for the city, which keeps its size until the terminal grows
+        let canvas = Some((SIZE_MIN_W - 1, SIZE_MIN_H, false));
+        apply_command(&mut s, &setup, Command::Resize { term: Some((10, 10)), canvas });
+        assert_eq!(s.size, (SIZE_MIN_W - 1, SIZE_MIN_H));
+        assert_eq!(s.city.get_size(), (SIZE_DEFAULT_W + 10, SIZE_DEFAULT_H + 5));
+    }
+
+    #[test]
+    fn hidden_layers_arent_drawn() {
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let setup = setup(&[]);
+        let mut s = state(&rng, &layers);
+        let front_wall = layers[2].wall_color[0];
+        let front_drawn = |s: &LoopState| s.city.get_canvas().row_iter().flatten().any(|c| c.bg == front_wall);
+
+        s.city.warmup();
+        s.city.next_tick();
+        assert!(front_drawn(&s));
+
+        apply_command(&mut s, &setup, Command::ToggleLayer(2));
+        assert_eq!(msg(&s), "layer 2 hidden");
+        s.city.next_tick();
+        assert!(!front_drawn(&s));
+
+        apply_command(&mut s, &setup, Command::ToggleLayer(2));
+        s.city.next_tick();
+        assert!(front_drawn(&s));
+
+        apply_command(&mut s, &setup, Command::ToggleLayer(3));
+        assert_eq!(msg(&s), "no layer 3");
+    }
+
+    #[test]
+    fn scenes_and_screenshots() {
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let scenes = demo::scene_layers(&layers);
+        let setup = Setup { text_canvas: true, ..setup(&scenes) };
+        let mut s = state(&rng, &layers);
+
+        apply_command(&mut s, &setup, Command::Scene(1));
+        assert_eq!(msg(&s), format!("scene: {}", demo::scene_name(1)));
+        assert!(s.city.get_tick() > 1, "scene is warmed up");
+
+        // the frame on screen is saved, not the city commands changed since
+        s.city.next_tick();
+        s.drawn();
+        let shown = Frame::of(&s.city);
+        apply_command(&mut s, &setup, Command::Seed(9));
+        apply_command(&mut s, &setup, Command::Sky(vec![44]));
+        let canvas = Some((SIZE_DEFAULT_W + 10, SIZE_DEFAULT_H, false));
+        apply_command(&mut s, &setup, Command::Resize { term: None, canvas });
+
+        let path = std::env::temp_dir().join(format!("city-control-test-{}.pbm", std::process::id()));
+        let expected = path.with_extension("expected.pbm");
+        apply_command(&mut s, &setup, Command::Screenshot(Some(path.clone())));
+        assert_eq!(msg(&s), format!("saved {}", path.display()));
+        shown.write_pbm(&expected, 1, &Ink::default()).unwrap();
+        let (saved, expected_bytes) = (std::fs::read(&path).unwrap(), std::fs::read(&expected).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&expected).unwrap();
+        assert!(saved == expected_bytes, "screenshot isn't the drawn frame");
+    }
+
+    #[test]
+    fn commands_refused_by_setup() {
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let setup = setup(&[]);
+        let mut s = state(&rng, &layers);
+
+        apply_command(&mut s, &setup, Command::Bookmark);
+        assert_eq!(msg(&s), "demo scenes can't be bookmarked");
+        apply_command(&mut s, &setup, Command::ToggleInspector);
+        assert_eq!(msg(&s), "inspector needs the text canvas");
+        apply_command(&mut s, &setup, Command::Screenshot(None));
+        assert_eq!(msg(&s), "screenshots need the text canvas");
+        assert!(!s.inspecting);
+
+        // pointing only counts while inspecting
+        apply_command(&mut s, &setup, Command::Pointer { col: 5, row: 5 });
+        assert_eq!(s.pointer, None);
+        apply_command(&mut s, &setup, Command::CursorRow(7));
+        assert_eq!(s.cursor_row, Some(7));
+    }
+
+    #[test]
+    fn keys_and_names() {
+        assert_eq!(Command::from_key(b'3'), Some(Command::ToggleLayer(2)));
+        assert_eq!(Command::from_key(b'p'), Some(Command::TogglePause));
+        assert_eq!(Command::from_event(Event::CursorPos { col: 1, row: 4 }), Some(Command::CursorRow(4)));
+        assert_eq!("layer 0".parse(), Ok(Command::ToggleLayer(0)));
+        assert_eq!("screenshot".parse(), Ok(Command::Screenshot(None)));
+        assert_eq!("screenshot a.pbm".parse(), Ok(Command::Screenshot(Some("a.pbm".into()))));
+        assert!("layer front".parse::<Command>().is_err());
+        assert_eq!("fps 999".parse(), Ok(Command::Fps(999)));
+        for fps in ["0", "1000", "5000"] {
+            assert_eq!(format!("fps {}", fps).parse::<Command>(), Err("fps must be at least 1 and below 1000".into()));
+        }
+    }
+}
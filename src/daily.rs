@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::city::hash_str;
+
+/// Calendar date in UTC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// Date `days` after the unix epoch, negative ones go before it
+    pub fn from_days(days: i64) -> Date {
+        // civil_from_days from https://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        Date { year, month, day }
+    }
+
+    /// Date at unix time `secs`, moved by `offset` days
+    pub fn at(secs: u64, offset: i64) -> Date {
+        Date::from_days((secs / 86400) as i64 + offset)
+    }
+
+    /// Seed everyone gets on this date
+    pub fn seed(&self) -> u64 {
+        hash_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_to_dates() {
+        let date = |days| Date::from_days(days).to_string();
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(-1), "1969-12-31");
+        assert_eq!(date(11016), "2000-02-29");
+        assert_eq!(date(19875), "2024-06-01");
+        assert_eq!(Date::at(19875 * 86400 + 86399, 0), Date::from_days(19875));
+        assert_eq!(Date::at(19875 * 86400, -31), Date { year: 2024, month: 5, day: 1 });
+    }
+
+    #[test]
+    fn seeds_never_change() {
+        let seed = |days| Date::from_days(days).seed();
+        assert_eq!(seed(0), 7571646157632313207);
+        assert_eq!(seed(19875), 14904685100314521203);
+        assert_eq!(seed(20000), 15852625702179504493);
+    }
+}
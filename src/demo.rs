@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+use crate::city::{LayerDesc, PaletteColor};
+
+/// Step of the demo, tweaks every layer of the ones the city was started with
+struct Scene {
+    name: &'static str,
+    apply: fn(&mut LayerDesc),
+    sky: &'static [PaletteColor], // sky colors from the top down, empty for the city's own sky
+}
+
+const SCENES: [Scene; 8] = [
+    Scene { name: "default", apply: |_| {}, sky: &[] },
+    Scene { name: "window glyphs", apply: |d| d.window_glyph = Some('▪'), sky: &[] },
+    Scene { name: "late night", apply: |d| d.age_fade = 0.8, sky: &[44] },
+    Scene { name: "dusk", apply: |d| d.wall_color.iter_mut().for_each(|c| *c = dusk(*c)), sky: &[44, 104, 103] },
+    Scene { name: "staggered", apply: |d| d.spawn_jitter = 12, sky: &[] },
+    Scene { name: "crowded", apply: |d| d.density = (d.density * 1.3).min(1.0), sky: &[] },
+    Scene { name: "no windows", apply: |d| d.draw_windows = false, sky: &[] },
+    Scene { name: "construction", apply: |d| d.grow_ticks = 40, sky: &[] },
+];
+
+fn dusk(color: PaletteColor) -> PaletteColor {
+    match color {
+        47 => 105,
+        100 => 45,
+        101 => 41,
+        c => c,
+    }
+}
+
+/// Layers of every scene in order, for `City::set_layers`
+pub fn scene_layers(base: &[LayerDesc]) -> Vec<Vec<LayerDesc>> {
+    SCENES.iter()
+        .map(|s| {
+            let mut layers = base.to_vec();
+            layers.iter_mut().for_each(s.apply);
+            layers
+        })
+        .collect()
+}
+
+pub fn scene_name(i: usize) -> &'static str {
+    SCENES[i].name
+}
+
+pub fn scene_names() -> Vec<&'static str> {
+    SCENES.iter().map(|s| s.name).collect()
+}
+
+pub fn scene_index(name: &str) -> Option<usize> {
+    SCENES.iter().position(|s| s.name == name)
+}
+
+/// Sky colors of a scene from the top down, empty if it keeps the city's own sky
+pub fn scene_sky(i: usize) -> &'static [PaletteColor] {
+    SCENES[i].sky
+}
+
+/// Timer switching through the scenes in a loop, starting with the first one
+pub struct Demo {
+    scene_i: usize,
+    switches: u64,
+    since: Instant,
+    scene_time: Duration,
+}
+
+impl Demo {
+    pub fn new(scene_time: Duration) -> Demo {
+        Demo { scene_i: 0, switches: 0, since: Instant::now(), scene_time }
+    }
+
+    /// Moves to the next scene once the current one has been shown long enough.
+    /// Returns its index and the number of switches so far, to derive a new seed from
+    pub fn poll(&mut self) -> Option<(usize, u64)> {
+        if self.since.elapsed() < self.scene_time {
+            return None;
+        }
+
+        self.scene_i = (self.scene_i + 1) % SCENES.len();
+        self.switches += 1;
+        self.since = Instant::now();
+        Some((self.scene_i, self.switches))
+    }
+}
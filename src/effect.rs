@@ -0,0 +1,163 @@
+use std::fmt;
+
+use fastrand::Rng;
+
+use crate::city::{self, BuildingId, BuildingInfo, Cell, MotionProfile, PaletteColor, Stars, Tick, WHSize, TICK_WRAP};
+use crate::vec2d::Vec2D;
+
+const STAR_SKY_SHARE: f32 = 0.6; // stars are placed in this much of the canvas from the top
+const STAR_TWINKLE_TICKS: Tick = 8;
+const SHOOTING_LIFE_TICKS: (Tick, Tick) = (10, 20);
+const SHOOTING_TRAIL: usize = 4;
+pub const SHOOTING_SALT: u64 = 0x5407;
+
+/// When an effect is drawn: `Behind` on the sky after it's wiped and before any layer, so
+/// buildings cover it, `InFront` over every layer, after mirroring and under the debug grid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectPhase {
+    Behind,
+    InFront,
+}
+
+/// What an effect gets to know of the tick it's at
+#[derive(Debug)]
+pub struct EffectCtx {
+    pub tick: Tick,
+    pub size: WHSize,
+    pub motion: MotionProfile,
+    pub rng: Rng, // of this effect and tick
+}
+
+/// Thing in the sky that isn't a building, with its own timing, state and drawing, see
+/// `City::set_effect`. Effects of a phase are drawn in the order they were set, later ones
+/// over earlier ones.
+///
+/// A city has to look the same whichever of its ticks were drawn, seeks and shards depend
+/// on it. So `tick` is called for every tick, drawn or not, before the buildings of the tick
+/// and `draw` only for drawn ones. The state may depend on ticks and `EffectCtx::rng` only,
+/// never on the canvas. The rng is hashed from the seed the effect was set with and the tick
+/// and shared with nothing else, so adding an effect leaves everything else as it was.
+///
+/// Effects following buildings keep their state by `BuildingId`. They're told of every building
+/// when it's placed (or when the effect is set, for ones already there) and when it's dropped,
+/// after which nothing kept for it should stay
+pub trait Effect: fmt::Debug {
+    fn tick(&mut self, ctx: &EffectCtx);
+    fn draw(&self, canvas: &mut EffectCanvas, ctx: &EffectCtx);
+
+    fn spawned(&mut self, _b: &BuildingInfo) {}
+    fn despawned(&mut self, _id: BuildingId) {}
+}
+
+/// Canvas of the city as effects see it, changed cells are marked for the next wipe
+pub struct EffectCanvas<'c> {
+    cells: &'c mut Vec2D<Cell>,
+    dirty: &'c mut [(usize, usize)],
+}
+
+impl<'c> EffectCanvas<'c> {
+    pub fn new(cells: &'c mut Vec2D<Cell>, dirty: &'c mut [(usize, usize)]) -> EffectCanvas<'c> {
+        EffectCanvas { cells, dirty }
+    }
+
+    pub fn get(&self, (x, y): (usize, usize)) -> Cell {
+        self.cells.get_row(y)[x]
+    }
+
+    pub fn set(&mut self, (x, y): (usize, usize), cell: Cell) {
+        self.cells.get_row_mut(y)[x] = cell;
+        city::mark_dirty(self.dirty, (x, y), (1, 1));
+    }
+
+    /// Puts a glyph over the color the cell has
+    pub fn put(&mut self, xy: (usize, usize), glyph: char, fg: PaletteColor) {
+        let bg = self.get(xy).bg;
+        self.set(xy, Cell { glyph, fg, bg });
+    }
+}
+
+/// Streak crossing the sky down to the left, placed by fractions of the canvas size
+/// so it keeps its way across a resize
+#[derive(Debug, Clone, Copy)]
+struct ShootingStar {
+    start: (f32, f32),
+    spawn_tick: Tick,
+    life: Tick,
+}
+
+impl ShootingStar {
+    /// Started at `tick` if the chance drawn from `rng` says so
+    fn at(stars: &Stars, rng: &Rng, tick: Tick) -> Option<ShootingStar> {
+        if rng.f32() >= stars.shooting_chance {
+            return None;
+        }
+        let start = (0.3 + 0.7 * rng.f32(), STAR_SKY_SHARE * 0.5 * rng.f32());
+        Some(ShootingStar { start, spawn_tick: tick, life: rng.u32(SHOOTING_LIFE_TICKS.0..=SHOOTING_LIFE_TICKS.1) })
+    }
+
+    fn age(&self, tick: Tick) -> Tick {
+        (tick + TICK_WRAP - self.spawn_tick) % TICK_WRAP
+    }
+}
+
+/// Stars placed by a seed and shooting stars crossing them, they don't twinkle or shoot
+/// with calm motion. Set with `SHOOTING_SALT` mixed into the seed for the stream
+#[derive(Debug)]
+pub struct StarsEffect {
+    stars: Stars,
+    seed: u64,
+    shooting: Vec<ShootingStar>, // ones crossing the sky, oldest first
+}
+
+impl StarsEffect {
+    pub fn new(stars: Stars, seed: u64) -> StarsEffect {
+        StarsEffect { stars, seed, shooting: Vec::new() }
+    }
+}
+
+impl Effect for StarsEffect {
+    fn tick(&mut self, ctx: &EffectCtx) {
+        match ctx.motion.flashing() {
+            true => {
+                self.shooting.retain(|s| s.age(ctx.tick) < s.life);
+                self.shooting.extend(ShootingStar::at(&self.stars, &ctx.rng, ctx.tick));
+            }
+            false => self.shooting.clear(),
+        }
+    }
+
+    /// Glyphs keep the sky color under them
+    fn draw(&self, canvas: &mut EffectCanvas, ctx: &EffectCtx) {
+        let (sx, sy) = ctx.size;
+        let sky_h = (sy as f32 * STAR_SKY_SHARE) as usize;
+        // bright to faint
+        let shades = [Cell::fg_of(107), Cell::fg_of(47), Cell::fg_of(100)];
+
+        for i in 0..self.stars.count {
+            let star_seed = city::mix_seed(self.seed, i as u64);
+            let rng = Rng::with_seed(star_seed);
+            let (x, y) = (rng.usize(..sx), (rng.f32() * sky_h as f32) as usize);
+            // each star lights up for a while now and then, at its own times
+            let lit = ctx.motion.flashing()
+                && city::mix_seed(star_seed, (ctx.tick / STAR_TWINKLE_TICKS) as u64).is_multiple_of(16);
+            match lit {
+                true => canvas.put((x, y), '+', shades[0]),
+                false => canvas.put((x, y), '·', shades[1]),
+            }
+        }
+
+        for s in &self.shooting {
+            let age = s.age(ctx.tick);
+            let head = ((s.start.0 * sx as f32) as i64 - 2 * age as i64, (s.start.1 * sy as f32) as i64 + age as i64);
+            // fades as it goes, the trail a shade fainter than the head
+            let fade = (age * shades.len() as Tick / s.life) as usize;
+            for i in 0..=SHOOTING_TRAIL.min(age as usize) {
+                let (x, y) = (head.0 + 2 * i as i64, head.1 - i as i64);
+                if (0..sx as i64).contains(&x) && (0..sy as i64).contains(&y) {
+                    let glyph = if i == 0 { '*' } else { '/' };
+                    canvas.put((x as usize, y as usize), glyph, shades[(fade + i.min(1)).min(shades.len() - 1)]);
+                }
+            }
+        }
+    }
+}
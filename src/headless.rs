@@ -0,0 +1,127 @@
+use std::time::{Duration, Instant};
+
+use fastrand::Rng;
+
+use crate::city::{City, MotionProfile, Tick};
+use crate::console::{SIZE_DEFAULT_H, SIZE_DEFAULT_W};
+use crate::opts::Opts;
+use crate::screenshot::Frame;
+use crate::{layers_from_opts, setup_city, unix_time, BG_COLOR};
+
+pub const PANORAMA_W_DEFAULT: usize = 1000;
+
+/// What `run` measured of a frame, passed to its frame hook
+#[derive(Debug, Clone, Copy)]
+pub struct FrameMetrics {
+    pub frame: usize, // from 0
+    pub tick: Tick,
+    pub tick_time: Duration, // of `City::next_tick`
+    pub buildings: usize,
+    pub building_cap: usize,
+}
+
+/// Frame hook of `run` that does nothing, for runs that only want the city moving
+pub fn no_frame_hook(_: &City, _: &FrameMetrics) {}
+
+/// Runs the city `opts` describe for `frames` ticks without a terminal and returns the frames
+/// one after another, each as --export-ans writes it. Options of the terminal, timing and input
+/// are left out. With a seed set the text is always the same, else it's seeded by the time
+///
+/// ```
+/// # use city::headless::run_to_string;
+/// # use city::opts::Opts;
+/// let opts = Opts { seed: Some(7), width: Some(60), height: Some(20), ..Opts::default() };
+/// let text = run_to_string(&opts, 3);
+/// assert_eq!(text.lines().count(), 3 * 20);
+/// assert_eq!(text, run_to_string(&opts, 3));
+/// ```
+pub fn run_to_string(opts: &Opts, frames: usize) -> String {
+    let size = (opts.width.unwrap_or(SIZE_DEFAULT_W), opts.height.unwrap_or(SIZE_DEFAULT_H));
+    let mut out = String::new();
+    run(opts, size, frames, |c, _| out.push_str(&Frame::of(c).to_ans()));
+    out
+}
+
+/// Builds the city `opts` describe at `size` like `run_to_string` does and calls `on_frame`
+/// with it after each of `frames` drawn ticks, so an embedding app can record, log or adapt
+/// to them. The hook runs on the thread drawing the city and the next tick waits for it, it
+/// should be fast. `no_frame_hook` is the one to pass when nothing is watched
+pub fn run(opts: &Opts, (width, height): (usize, usize), frames: usize,
+           mut on_frame: impl FnMut(&City, &FrameMetrics)) {
+    let seed = opts.seed.unwrap_or_else(unix_time);
+    let motion = if opts.calm { MotionProfile::Calm } else { MotionProfile::Full };
+    let layers = layers_from_opts(opts);
+
+    let rng = Rng::with_seed(seed);
+    let mut c = City::new(width, height, opts.step.unwrap_or(1), &rng, BG_COLOR, &layers);
+    setup_city(&mut c, opts, seed, motion, &layers);
+    c.seek(1 + c.warmup_ticks());
+
+    for frame in 0..frames {
+        let start = Instant::now();
+        c.next_tick();
+        let metrics = FrameMetrics {
+            frame,
+            tick: c.get_tick(),
+            tick_time: start.elapsed(),
+            buildings: c.building_count(),
+            building_cap: c.building_cap(),
+        };
+        on_frame(&c, &metrics);
+    }
+}
+
+/// The city `opts` describe on a canvas as wide as its whole world, `width` cells, drawn once
+/// buildings have crossed all of it. The city stops there, a panorama is a single frame
+pub fn panorama(opts: &Opts, (width, height): (usize, usize)) -> Frame {
+    let mut frame = None;
+    run(opts, (width, height), 1, |c, _| frame = Some(Frame::of(c)));
+    frame.expect("a frame is drawn")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::city::Stars;
+    use crate::screenshot;
+
+    #[test]
+    fn same_seed_same_frames() {
+        let opts = Opts { seed: Some(7), width: Some(60), height: Some(20), ..Opts::default() };
+        let text = run_to_string(&opts, 3);
+        assert_eq!(text.lines().count(), 3 * 20);
+        assert_eq!(text, run_to_string(&opts, 3));
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_ne!(lines[..20], lines[20..40], "frames don't move");
+        let starry = Opts { stars: Some(Stars { count: 30, shooting_chance: 0.0 }), ..opts };
+        assert_ne!(run_to_string(&starry, 3), text);
+    }
+
+    #[test]
+    fn frame_hook_sees_every_frame() {
+        let opts = Opts { seed: Some(4), width: Some(60), height: Some(20), ..Opts::default() };
+        let mut seen = Vec::new();
+        run(&opts, (60, 20), 5, |c, m| {
+            assert_eq!(m.tick, c.get_tick());
+            assert!(m.buildings <= m.building_cap);
+            seen.push((m.frame, m.tick));
+        });
+        let first_tick = seen[0].1;
+        assert_eq!(seen, (0..5).map(|i| (i, first_tick + i as Tick)).collect::<Vec<_>>());
+        run(&opts, (60, 20), 5, no_frame_hook);
+    }
+
+    #[test]
+    fn panorama_is_filled_end_to_end() {
+        let opts = Opts { seed: Some(2), ..Opts::default() };
+        let colors = screenshot::parse_ans(&panorama(&opts, (400, 30)).to_ans()).unwrap();
+        let (_, size) = colors.as_flat_slice();
+        assert_eq!(size, (400, 30));
+
+        // buildings in every part of it, not just where a screen would have been
+        for part in colors.get_row(29).chunks(100) {
+            assert!(part.iter().filter(|&&c| c != BG_COLOR).count() > 50);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::path::Path;
+
+use crate::headless;
+use crate::opts::Opts;
+use crate::png;
+use crate::vec2d::Vec2D;
+
+pub const TICKS_DEFAULT: usize = 1000;
+
+/// How many drawn frames each canvas cell was covered by buildings in
+pub struct Heatmap {
+    counts: Vec2D<u32>,
+    frames: u32,
+}
+
+impl Heatmap {
+    pub fn new(w: usize, h: usize) -> Heatmap {
+        Heatmap { counts: Vec2D::new(w, h, || 0), frames: 0 }
+    }
+
+    /// Counts the cells at and below `skyline` (see `City::skyline`) as covered
+    pub fn add(&mut self, skyline: &[usize]) {
+        for (x, &top) in skyline.iter().enumerate() {
+            self.counts.col_iter_mut(x).skip(top).for_each(|n| *n += 1);
+        }
+        self.frames += 1;
+    }
+
+    /// Gray level of each cell, rows from the top: black for never covered, white for every frame
+    pub fn to_gray(&self) -> Vec<u8> {
+        let (counts, _) = self.counts.as_flat_slice();
+        counts.iter().map(|&n| (n as u64 * 255 / self.frames.max(1) as u64) as u8).collect()
+    }
+}
+
+/// Runs the city `opts` describe at `size` for `ticks` ticks and writes its heatmap as a PNG
+/// with a pixel per cell
+pub fn write(opts: &Opts, size: (usize, usize), ticks: usize, path: &Path) -> io::Result<()> {
+    let mut heatmap = Heatmap::new(size.0, size.1);
+    headless::run(opts, size, ticks, |c, _| heatmap.add(c.skyline()));
+    png::write_gray(path, size, &heatmap.to_gray())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heatmap_counts_covered_frames() {
+        let mut heatmap = Heatmap::new(3, 4);
+        heatmap.add(&[4, 2, 0]);
+        heatmap.add(&[4, 3, 0]);
+        assert_eq!(heatmap.to_gray(), [
+            0, 0, 255,
+            0, 0, 255,
+            0, 127, 255,
+            0, 255, 255,
+        ]);
+
+        // buildings are denser toward the bottom of a real city
+        let opts = Opts { seed: Some(3), ..Opts::default() };
+        let mut heatmap = Heatmap::new(80, 30);
+        headless::run(&opts, (80, 30), 200, |c, _| heatmap.add(c.skyline()));
+        let gray = heatmap.to_gray();
+        let row_sum = |y: usize| gray[y * 80..(y + 1) * 80].iter().map(|&g| g as u32).sum::<u32>();
+        assert!((1..30).all(|y| row_sum(y) >= row_sum(y - 1)));
+        assert!(row_sum(29) > row_sum(0));
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::mpsc::Sender;
+#[cfg(unix)]
+use std::{io::Read, thread};
+
+use crate::control::Command;
+
+/// Key press or terminal report, positions are 1-based terminal columns and rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Key(u8),
+    Pointer { col: usize, row: usize }, // mouse moved or clicked, see console::set_mouse_reporting
+    CursorPos { col: usize, row: usize }, // answer to console::request_cursor_pos
+    Focus(bool), // terminal window gained or lost focus, see console::set_focus_reporting
+}
+
+/// Unbuffered keyboard input read on a background thread, sending the commands of
+/// events to `tx`. Terminal mode is restored on drop
+pub struct Input {
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl Input {
+    #[cfg(unix)]
+    pub fn start(tx: Sender<Command>) -> Input {
+        let saved = raw_mode();
+        if saved.is_some() {
+            spawn_reader(tx);
+        }
+        Input { saved }
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(_tx: Sender<Command>) -> Input {
+        Input {}
+    }
+}
+
+/// Event of an escape sequence after its `ESC [`, None for ones that aren't understood
+#[cfg(unix)]
+fn parse_csi(seq: &[u8]) -> Option<Event> {
+    match seq {
+        b"I" => return Some(Event::Focus(true)),
+        b"O" => return Some(Event::Focus(false)),
+        _ => {}
+    }
+    let (&last, params) = seq.split_last()?;
+    let params = std::str::from_utf8(params).ok()?;
+    let mut nums = params.trim_start_matches('<').split(';').map(|n| n.parse::<usize>().ok());
+    let (a, b, c) = (nums.next()??, nums.next()??, nums.next());
+
+    match (params.starts_with('<'), last, c) {
+        // buttons with wheel (64) or release ('m') bits aren't pointing anywhere new
+        (true, b'M', Some(Some(row))) if a & 64 == 0 => Some(Event::Pointer { col: b, row }),
+        (false, b'R', None) => Some(Event::CursorPos { col: b, row: a }),
+        _ => None,
+    }
+}
+
+/// Events of the bytes read from the terminal, fed one at a time
+#[cfg(unix)]
+#[derive(Debug, Default)]
+struct Decoder {
+    seq: Option<Vec<u8>>, // bytes of an escape sequence read so far
+}
+
+#[cfg(unix)]
+impl Decoder {
+    fn feed(&mut self, b: u8) -> Option<Event> {
+        let s = match self.seq.as_mut() {
+            None if b == 0x1b => {
+                self.seq = Some(Vec::new());
+                return None;
+            }
+            None => return Some(Event::Key(b)),
+            Some(s) => s,
+        };
+        if s.is_empty() && b != b'[' {
+            // not a CSI sequence, nothing here uses those. The byte is a key of its own after
+            // a bare Esc or with Alt, or the start of the next sequence
+            self.seq = None;
+            return self.feed(b);
+        }
+        s.push(b);
+        if s.len() < 2 || !(0x40..=0x7e).contains(&b) {
+            return None;
+        }
+        let event = parse_csi(&s[1..]);
+        self.seq = None;
+        event
+    }
+}
+
+#[cfg(unix)]
+fn spawn_reader(tx: Sender<Command>) {
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        let mut decoder = Decoder::default();
+        for byte in stdin.lock().bytes() {
+            let b = match byte {
+                Ok(b) => b,
+                Err(_) => break,
+            };
+            let event = match decoder.feed(b) {
+                Some(event) => event,
+                None => continue,
+            };
+            if let Some(command) = Command::from_event(event) {
+                if tx.send(command).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn raw_mode() -> Option<libc::termios> {
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return None;
+        }
+
+        let mut term: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+            return None;
+        }
+
+        // keys without enter and echo, ctrl-c still raises SIGINT
+        let saved = term;
+        term.c_lflag &= !(libc::ICANON | libc::ECHO);
+        term.c_cc[libc::VMIN] = 1;
+        term.c_cc[libc::VTIME] = 0;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) != 0 {
+            return None;
+        }
+
+        Some(saved)
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = self.saved.as_ref() {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn events(bytes: &[u8]) -> Vec<Event> {
+        let mut decoder = Decoder::default();
+        bytes.iter().filter_map(|&b| decoder.feed(b)).collect()
+    }
+
+    #[test]
+    fn keys_after_esc_are_kept() {
+        assert_eq!(events(b"\x1bq"), [Event::Key(b'q')]);
+        assert_eq!(events(b"p\x1b\x1bp"), [Event::Key(b'p'), Event::Key(b'p')]);
+        assert_eq!(events(b"\x1b\x1b[I3"), [Event::Focus(true), Event::Key(b'3')]);
+        assert_eq!(events(b"\x1b[12;5Rq"), [Event::CursorPos { col: 5, row: 12 }, Event::Key(b'q')]);
+        assert_eq!(events(b"\x1b[<0;7;3M"), [Event::Pointer { col: 7, row: 3 }]);
+    }
+}
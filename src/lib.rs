@@ -0,0 +1,240 @@
+//! The city without the terminal around it, `headless` draws it for apps embedding it.
+//! The binary runs it in a terminal
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use arrayvec::ArrayVec;
+
+use crate::city::{City, Grid, LayerDesc, MotionProfile, PaletteColor, Sky, WindowColor};
+use crate::opts::Opts;
+use crate::sprite::{Sprite, SpriteEffect};
+use crate::stamp::Stamp;
+
+pub mod bookmarks;
+pub mod city;
+mod config;
+pub mod console;
+pub mod control;
+pub mod daily;
+pub mod demo;
+mod effect;
+pub mod headless;
+pub mod heatmap;
+pub mod input;
+pub mod oneline;
+pub mod opts;
+mod paths;
+pub mod pbm;
+mod png;
+pub mod profile;
+pub mod scenario;
+pub mod screenshot;
+pub mod selftest;
+pub mod sixel;
+#[cfg(unix)]
+pub mod socket;
+pub mod sprite;
+pub mod stamp;
+pub mod status;
+pub mod title;
+mod vec2d;
+pub mod width;
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+macro_rules! av {($($x:expr),*$(,)*) => {{
+    let mut vec = ArrayVec::new();
+    vec.try_extend_from_slice(&[$($x,)*]).unwrap();
+    vec
+}}}
+
+pub const INFO_LINEFEEDS: usize = 2;
+pub const STATUS_LINEFEEDS: usize = 1;
+
+pub const BG_COLOR: PaletteColor = 107;
+
+/// Densities of the default layers under the old spawn chance, they look about the same as the new ones at step 1
+const LEGACY_DENSITIES: [f32; 3] = [0.75, 0.6, 0.4];
+
+pub fn default_layers() -> Vec<LayerDesc> {
+    vec![
+        LayerDesc {
+            density: 0.9,
+            collision: 0.4,
+            speed: 4,
+            wall_color: av![47],
+            wall_weights: Default::default(),
+            draw_windows: false,
+            window_colors: Default::default(),
+            window_weights: Default::default(),
+            window_glyph: None,
+            age_fade: 0.0,
+            spawn_jitter: 0,
+            grow_ticks: 0,
+            face_gradient: 0.0,
+            allow_clip: true,
+            stamps: Vec::new(),
+            stamp_chance: 0.0,
+            sink: 0,
+            target_count: None,
+        },
+        LayerDesc {
+            density: 0.84,
+            collision: 0.1,
+            speed: 3,
+            wall_color: av![100, 101],
+            wall_weights: Default::default(),
+            draw_windows: false,
+            window_colors: Default::default(),
+            window_weights: Default::default(),
+            window_glyph: None,
+            age_fade: 0.0,
+            spawn_jitter: 0,
+            grow_ticks: 0,
+            face_gradient: 0.0,
+            allow_clip: true,
+            stamps: Vec::new(),
+            stamp_chance: 0.0,
+            sink: 0,
+            target_count: None,
+        },
+        LayerDesc {
+            density: 0.64,
+            collision: 0.05,
+            speed: 1,
+            wall_color: av![40],
+            wall_weights: Default::default(),
+            draw_windows: true,
+            window_colors: av![WindowColor::Wall, WindowColor::Fixed(107), WindowColor::Fixed(101)],
+            window_weights: Default::default(),
+            window_glyph: None,
+            age_fade: 0.0,
+            spawn_jitter: 0,
+            grow_ticks: 0,
+            face_gradient: 0.0,
+            allow_clip: true,
+            stamps: Vec::new(),
+            stamp_chance: 0.0,
+            sink: 0,
+            target_count: None,
+        }
+    ]
+}
+
+/// Default layers changed as the options ask
+pub fn layers_from_opts(opts: &Opts) -> Vec<LayerDesc> {
+    let mut layers = default_layers();
+    if opts.legacy_spawn {
+        layers.iter_mut().zip(LEGACY_DENSITIES).for_each(|(d, density)| d.density = density);
+    }
+    if let Some(glyph) = opts.window_glyph {
+        if width::char_width(glyph) != Some(1) {
+            panic!("Window glyph must take one terminal cell")
+        }
+        layers.iter_mut().for_each(|d| d.window_glyph = Some(glyph));
+    }
+    if let Some(jitter) = opts.spawn_jitter {
+        layers.iter_mut().for_each(|d| d.spawn_jitter = jitter);
+    }
+    let colored = opts.walls.iter().map(|c| c.layer).chain(opts.windows.iter().map(|c| c.layer));
+    if let Some(layer) = colored.max().filter(|&layer| layer >= layers.len()) {
+        panic!("No layer {} to set colors of, layers are 0 to {}", layer, layers.len() - 1)
+    }
+    for colors in &opts.walls {
+        let d = &mut layers[colors.layer];
+        d.wall_color = colors.colors.iter().map(|c| c.0).collect();
+        d.wall_weights.clear();
+    }
+    for colors in &opts.windows {
+        let d = &mut layers[colors.layer];
+        d.window_colors = colors.colors.clone();
+        d.window_weights.clear();
+    }
+    if let Some(&layer) = opts.no_clip.iter().find(|&&l| l >= layers.len()) {
+        panic!("No layer {} to keep from clipping, layers are 0 to {}", layer, layers.len() - 1)
+    }
+    for &layer in &opts.no_clip {
+        layers[layer].allow_clip = false;
+    }
+    if let Some(t) = opts.target_counts.iter().find(|t| t.layer >= layers.len()) {
+        panic!("No layer {} to set a target count of, layers are 0 to {}", t.layer, layers.len() - 1)
+    }
+    for t in &opts.target_counts {
+        layers[t.layer].target_count = Some(t.n);
+    }
+    if let Some(s) = opts.sinks.iter().find(|s| s.layer >= layers.len()) {
+        panic!("No layer {} to sink, layers are 0 to {}", s.layer, layers.len() - 1)
+    }
+    for s in &opts.sinks {
+        layers[s.layer].sink = s.n;
+    }
+    if let Some(grow) = opts.grow_ticks {
+        layers.iter_mut().for_each(|d| d.grow_ticks = grow);
+    }
+    if let Some(front) = layers.last_mut() {
+        front.stamps = opts.stamps.iter()
+            .map(|path| Stamp::load(path)
+                .unwrap_or_else(|e| panic!("Can't load stamp {}: {}", path.display(), e)))
+            .collect();
+        front.stamp_chance = opts.stamp_chance.unwrap_or(0.05);
+        if !(0.0..=1.0).contains(&front.stamp_chance) {
+            panic!("Invalid stamp chance")
+        }
+    }
+    if let Some(gradient) = opts.face_gradient {
+        if !(-1.0..=1.0).contains(&gradient) {
+            panic!("Invalid face gradient")
+        }
+        layers.iter_mut().for_each(|d| d.face_gradient = gradient);
+    }
+    if let Some(fade) = opts.age_fade {
+        if !(0.0..=1.0).contains(&fade) {
+            panic!("Invalid age fade")
+        }
+        layers.iter_mut().for_each(|d| d.age_fade = fade);
+    }
+    layers
+}
+
+/// Sets up a new city as the options ask, returns the sky it got
+pub fn setup_city(c: &mut City, opts: &Opts, seed: u64, motion: MotionProfile, layers: &[LayerDesc]) -> Option<Sky> {
+    if opts.layer_seeds {
+        c.seed_layers(seed);
+    }
+    if let Some(w) = opts.world_width {
+        c.set_world_width(w);
+    }
+    c.set_view_x(opts.view_x);
+    c.set_mirror(opts.mirror);
+    c.set_rising(opts.rising);
+    c.set_shard(opts.shard, seed);
+    c.set_legacy_spawn(opts.legacy_spawn);
+    c.set_despawn_margin(opts.despawn_margin.unwrap_or(0));
+    c.set_horizon(opts.horizon);
+    c.set_stars(opts.stars, seed);
+    if let Some(path) = &opts.sprite {
+        let sprite = Sprite::load(path).unwrap_or_else(|e| panic!("Can't load sprite {}: {}", path.display(), e));
+        let phase = sprite.phase();
+        let front = layers.last().expect("a city has layers");
+        let effect = SpriteEffect::new(sprite, c.get_size(), front).unwrap_or_else(|e| panic!("{}", e));
+        c.set_effect("sprite", phase, 0, Some(Box::new(effect)));
+    }
+    c.set_motion(motion);
+    c.set_motion_blur(opts.motion_blur.unwrap_or(0.0));
+    c.set_dither(opts.dither);
+    c.set_grid(opts.grid.or(opts.debug_grid.then(Grid::default)));
+    let sky = opts.sky.clone().map(|sky| Sky { drift: opts.sky_drift.unwrap_or(0), dither: opts.dither, ..sky });
+    c.set_sky(sky.clone());
+    if let Some(period) = opts.loop_period {
+        if opts.rising {
+            panic!("Loop period doesn't apply to a rising city")
+        }
+        if period < 1 || layers.iter().any(|d| !period.is_multiple_of(d.speed)) {
+            panic!("Loop period must be a multiple of every layer speed")
+        }
+        c.set_loop_period(period, seed);
+    }
+    sky
+}
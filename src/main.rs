@@ -1,265 +1,803 @@
-use std::{env, fmt, slice};
-use std::borrow::Borrow;
+use std::{fmt, panic, slice};
+use std::fmt::Write;
 use std::collections::VecDeque;
 use std::process::exit;
-use std::str::FromStr;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
 use std::thread::sleep;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::io;
+use std::fs;
+use std::path::Path;
 
-use arrayvec::ArrayVec;
 use bounded_vec_deque::BoundedVecDeque;
 use fastrand::Rng;
 
-use city::{City, LayerDesc, Tick, TICK_WRAP};
-
-use crate::console::{SIZE_DEFAULT_H, SIZE_DEFAULT_W, SIZE_MIN_H, SIZE_MIN_W};
-
-mod city;
-mod console;
-mod vec2d;
+use city::{bookmarks, console, control, daily, demo, headless, heatmap, oneline, opts, pbm, screenshot, selftest,
+           sixel, status, width};
+use city::{layers_from_opts, setup_city, unix_time, BG_COLOR};
+use city::city::{City, MotionProfile, Tick, TICK_WRAP};
+use city::bookmarks::Bookmark;
+use city::control::{apply_command, step_for_speed, Command, FpsRamp, LoopState, OwedFrames, PowerSave, Setup,
+                    FPS_RANGE};
+use city::demo::Demo;
+use city::input::Input;
+use city::opts::parse_args;
+use city::profile::Profile;
+use city::scenario::Scenario;
+#[cfg(unix)]
+use city::socket::{ControlSocket, MetricsSocket};
+use city::status::Status;
+use city::title::{Title, TitleStyle};
+use city::console::{CursorMove, Layout, Margins, WriteOpts, WriteStats, WRITE_CHUNK_DEFAULT, MINIMAP_H, MINIMAP_W, SIZE_DEFAULT_H, SIZE_DEFAULT_W, SIZE_FULL_W, SIZE_MIN_H, SIZE_MIN_W};
+
+fn info_center(msg: impl fmt::Display, width: usize, margins: &Margins) {
+    println!("{}{}", CursorMove::fwd(margins.left), width::center(&msg.to_string(), width));
+}
 
-#[derive(Debug, Default)]
-struct Opts {
-    fps: Option<u64>,
-    step: Option<Tick>,
-    seed: Option<u64>,
-    auto_size: bool,
-    width: Option<usize>,
-    height: Option<usize>,
+unsafe fn deque_raw_slice<T>(d: &mut VecDeque<T>) -> &mut [T] {
+    let len = d.len();
+    let (right, left) = d.as_mut_slices();
+    let leftmost = if left.is_empty() { right } else { left };
+    slice::from_raw_parts_mut(leftmost.as_mut_ptr(), len)
 }
 
-fn parse_args() -> Opts {
-    fn parse<A: Borrow<str>, T: FromStr>(arg: Option<A>, name: &str) -> Option<T>
-    where <T as FromStr>::Err: fmt::Debug {
-        match arg.map(|a| a.borrow().parse()) {
-            Some(Ok(value)) => Some(value),
-            Some(Err(e)) => panic!("Can't parse {} value: {:?}", name, e),
-            None => panic!("Expected value for {}", name),
+pub const FPS_SMOOTH_FRAMES: usize = 30;
+pub const STATUS_MSG_TIME: Duration = Duration::from_secs(3);
+pub const SPLASH_DELAY: Duration = Duration::from_millis(200);
+pub const OUTRO_TIME: Duration = Duration::from_millis(1500);
+
+/// Waits for commands until `until`, returns whether a stop came in, which ends the wait.
+/// Other commands are added to `queued`
+fn wait_for_stop(commands: &Receiver<Command>, until: Instant, queued: &mut Vec<Command>) -> bool {
+    while let Ok(command) = commands.recv_timeout(until.saturating_duration_since(Instant::now())) {
+        match command {
+            Command::Stop => return true,
+            command => queued.push(command),
         }
     }
+    // also when the senders are gone, the frame still lasts until then
+    sleep(until.saturating_duration_since(Instant::now()));
+    false
+}
 
-    const HELP: &str = r#"
-Usage: city [options] [width] [height]
--f      Set target fps (default: 60)
--t      Set step per frame (default: 1)
--s      Custom seed
--a      Use terminal size (auto-size)
-"#;
-
-    let pos_names = ["", "[width]", "[height]"];
-    let mut pos_i = 0;
-    let mut opts = Opts::default();
-    let mut args = env::args();
-
-    while let Some(a) = args.next() {
-        match a.as_str() {
-            "-h" | "--help" => {
-                println!("{}", HELP);
-                exit(0);
-            }
-            "-f" => opts.fps = parse(args.next(), &a),
-            "-t" => opts.step = parse(args.next(), &a),
-            "-s" => opts.seed = parse(args.next(), &a),
-            "-a" => opts.auto_size = true,
-            u if u.starts_with("-") => panic!("Unknown arg {}", u),
-            pos => {
-                match pos_i {
-                    0 => {},
-                    1 => opts.width = parse(Some(pos), pos_names[pos_i]),
-                    2 => opts.height = parse(Some(pos), pos_names[pos_i]),
-                    _ => panic!("Unknown arg at position {}", pos_i)
-                }
-                pos_i += 1;
+/// Seeks in chunks, showing a progress bar if that takes a while.
+/// Returns whether a stop command came in and ended it early, other commands are dropped
+fn seek_with_splash(c: &mut City, tick: Tick, commands: &Receiver<Command>) -> bool {
+    let from = c.get_tick();
+    let total = tick.saturating_sub(from).max(1);
+    let chunk = total.div_ceil(100);
+    let started = Instant::now();
+    let tty = console::is_tty();
+    let mut shown = None;
+    let mut stopped = false;
+
+    while c.get_tick() < tick && !stopped {
+        c.seek((c.get_tick() + chunk).min(tick));
+
+        let percent = (c.get_tick() - from) * 100 / total;
+        if tty && started.elapsed() >= SPLASH_DELAY && shown != Some(percent) {
+            if shown.is_none() {
+                console::setup_console();
             }
+            console::draw_splash(percent, BG_COLOR);
+            shown = Some(percent);
         }
+        stopped = commands.try_iter().any(|command| command == Command::Stop);
     }
 
-    opts
+    if shown.is_some() {
+        console::clear_splash();
+    }
+    stopped
 }
 
-fn unix_time() -> u64 {
-    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+fn print_bookmarks() {
+    match bookmarks::load() {
+        Ok(list) => list.iter().enumerate().for_each(|(i, b)| println!("{: >3}: {}", i + 1, b)),
+        Err(e) => {
+            eprintln!("Can't read bookmarks: {}", e);
+            exit(1);
+        }
+    }
 }
 
-fn info_center(msg: impl fmt::Display, width: usize) {
-    println!("{:^w$}", msg, w = width);
+fn load_bookmark(n: usize) -> Bookmark {
+    let list = bookmarks::load().unwrap_or_else(|e| panic!("Can't read bookmarks: {}", e));
+    match n.checked_sub(1).and_then(|i| list.get(i)) {
+        Some(b) => b.clone(),
+        None => panic!("No bookmark number {}, there are {}", n, list.len()),
+    }
 }
 
-unsafe fn deque_raw_slice<T>(d: &mut VecDeque<T>) -> &mut [T] {
-    let len = d.len();
-    let (right, left) = d.as_mut_slices();
-    let leftmost = if left.is_empty() { right } else { left };
-    slice::from_raw_parts_mut(leftmost.as_mut_ptr(), len)
+/// Seed stored in a file, the file gets the next seed so every run shows another city.
+/// A missing or empty file starts from the current time, unreadable ones fall back to it with a warning
+fn seed_from_file(path: &Path) -> u64 {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            eprintln!("Can't read seed from {}, using the current time: {}", path.display(), e);
+            return unix_time();
+        }
+    };
+    let seed = match text.trim() {
+        "" => unix_time(),
+        text => match text.parse() {
+            Ok(seed) => seed,
+            Err(e) => {
+                eprintln!("Invalid seed in {}, using the current time: {}", path.display(), e);
+                return unix_time();
+            }
+        },
+    };
+
+    if let Err(e) = fs::write(path, format!("{}\n", seed.wrapping_add(1))) {
+        eprintln!("Can't write next seed to {}: {}", path.display(), e);
+    }
+    seed
 }
 
-macro_rules! av {($($x:expr),*$(,)*) => {{
-    let mut vec = ArrayVec::new();
-    vec.try_extend_from_slice(&[$($x,)*]).unwrap();
-    vec
-}}}
+fn main() {
+    let mut opts = parse_args();
 
-pub const TITLE_LINEFEEDS: usize = 3;
-pub const STATUS_LINEFEEDS: usize = 1;
+    if opts.selftest {
+        exit(if selftest::run() { 0 } else { 1 });
+    }
 
-fn main() {
-    let opts = parse_args();
+    if opts.list_colors {
+        console::print_colors();
+        exit(0);
+    }
+
+    if opts.bookmark_list {
+        print_bookmarks();
+        exit(0);
+    }
+
+    if let Some(path) = opts.check_ans.as_ref() {
+        let colors = fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|t| screenshot::parse_ans(&t));
+        match colors {
+            Ok(colors) => {
+                let (cells, (w, h)) = colors.as_flat_slice();
+                let mut used = cells.to_vec();
+                used.sort_unstable();
+                used.dedup();
+                println!("{}: {}x{} cells, {} colors", path.display(), w, h, used.len());
+                exit(0);
+            }
+            Err(e) => {
+                eprintln!("Can't read {}: {}", path.display(), e);
+                exit(1);
+            }
+        }
+    }
+
+    let bookmark = opts.from_bookmark.map(load_bookmark);
+    if let Some(b) = bookmark.as_ref() {
+        opts.seed = Some(b.seed);
+        opts.step = Some(b.step);
+        opts.width = Some(b.width);
+        opts.height = Some(b.height);
+        opts.layer_seeds = b.layer_seeds;
+        opts.legacy_spawn = b.legacy_spawn;
+        opts.auto_size = false;
+    }
+
+    if let Some(fit) = opts.fit {
+        let scale = opts.pbm_scale.unwrap_or(1);
+        let (w, h) = fit.cells(scale).unwrap_or_else(|e| panic!("Can't fit {}: {}", fit, e));
+        if w < SIZE_MIN_W || h < SIZE_MIN_H {
+            panic!("Can't fit {}: {}x{} cells is too small, the least is {}x{} at --pbm-scale {}",
+                   fit, w, h, SIZE_MIN_W, SIZE_MIN_H, scale);
+        }
+        opts.width = Some(w);
+        opts.height = Some(h);
+        opts.auto_size = false;
+    }
+
+    if let Some(shard) = opts.shard {
+        // a seed from the time would differ between the processes of the parts
+        let seed = opts.shard_seed.unwrap_or_else(|| panic!("--shard {} needs --shard-seed", shard));
+        opts.seed = Some(seed);
+    }
+
+    // held until exit, so runs started at the same time continue the city one after another
+    let oneline = opts.oneline.map(|_| {
+        let lock = oneline::lock().unwrap_or_else(|e| panic!("Can't lock one line state: {}", e));
+        let state = oneline::resume(&lock, opts.seed, unix_time())
+            .unwrap_or_else(|e| panic!("Can't load one line state: {}", e));
+        opts.seed = Some(state.seed);
+        if opts.loop_period.is_none() && !opts.rising {
+            opts.loop_period = Some(oneline::LOOP_PERIOD);
+        }
+        (lock, state)
+    });
 
     let fps = opts.fps.unwrap_or(60);
-    let step = opts.step.unwrap_or(1);
-    let seed = opts.seed.unwrap_or_else(unix_time);
+    if !FPS_RANGE.contains(&fps) {
+        panic!("Invalid fps")
+    }
+    // the step and how often it's taken
+    let step = opts.speed.map_or_else(|| opts.step.unwrap_or(1), |cells_per_sec| step_for_speed(cells_per_sec, fps).0);
+    let daily = match opts.seed {
+        None if opts.daily => Some(daily::Date::at(unix_time(), opts.daily_offset.unwrap_or(0))),
+        _ => None,
+    };
+    let seed = opts.seed.or_else(|| daily.map(|date| date.seed()))
+        .unwrap_or_else(|| opts.seed_file.as_deref().map_or_else(unix_time, seed_from_file));
+
+    opts.calm |= std::env::var("CITY_CALM").is_ok_and(|v| v == "1");
+    let motion = if opts.calm { MotionProfile::Calm } else { MotionProfile::Full };
+    // demo scenes and sky commands may bring a sky later
+    if opts.dither && opts.sky.is_none() && opts.face_gradient.unwrap_or(0.0) == 0.0 && !opts.demo {
+        eprintln!("--dither blends sky and face gradients only, neither --sky nor --face-gradient is set");
+    }
+
+    // defaults are spelled out in the command, so it doesn't depend on them
+    opts.fps = Some(fps);
+    opts.step = Some(step);
+    opts.seed = Some(seed);
+    // containers and CI runs without a terminal can give the size in the environment instead
+    let (env_w, env_h) = opts::env_size(&|var| std::env::var(var).ok()).unwrap_or_else(|e| panic!("{}", e));
+    if opts.auto_size && (env_w.is_some() || env_h.is_some()) && console::get_term_dimensions().is_none() {
+        opts.auto_size = false;
+    }
+    if !opts.auto_size {
+        opts.width = Some(opts.width.or(env_w).unwrap_or(SIZE_DEFAULT_W));
+        opts.height = Some(opts.height.or(env_h).unwrap_or(SIZE_DEFAULT_H));
+    }
+    let cmdline = opts.cmdline();
+    if opts.print_cmdline {
+        println!("{}", cmdline);
+    }
 
     let auto_size = opts.auto_size;
-    let (mut width, mut height) = if auto_size {
-        console::get_term_size()
+    let resize_delay = Duration::from_millis(opts.resize_delay.unwrap_or(150));
+    let center = !auto_size && !opts.no_center;
+    let user_margins = opts.margins.unwrap_or_default();
+    let title = Title::new(opts.title.take().unwrap_or_else(|| "oO0OoO0OoO0Oo CiTY oO0OoO0OoO0Oo".to_string()),
+                           opts.title_style.unwrap_or(TitleStyle::Text),
+                           BG_COLOR);
+    let show_header;
+    let (width, height) = if auto_size {
+        let (w, h, header) = console::get_term_size(&user_margins, &title);
+        show_header = header;
+        (w, h)
     } else {
-        (opts.width.unwrap_or(SIZE_DEFAULT_W),
-         opts.height.unwrap_or(SIZE_DEFAULT_H))
+        let w = opts.width.unwrap_or(SIZE_DEFAULT_W);
+        show_header = w >= SIZE_FULL_W;
+        (w, opts.height.unwrap_or(SIZE_DEFAULT_H))
     };
 
     if !auto_size && (width < SIZE_MIN_W || height < SIZE_MIN_H) {
         panic!("Size is too small")
     }
 
-    if step < 1 || step > (width / 2) as u32 {
-        panic!("Invalid step")
+    if step > (width / 2) as u32 {
+        match opts.speed {
+            Some(_) => panic!("Invalid speed, at most {} cells a second fit this width at {} fps",
+                              width / 2 * fps as usize, fps),
+            None => panic!("Invalid step"),
+        }
     }
 
-    if !(1..1000).contains(&fps) {
-        panic!("Invalid fps")
+    let bg_color = BG_COLOR;
+    let layers = layers_from_opts(&opts);
+    let palette_cycle = opts.palette_cycle.filter(|&secs| secs != 0.0);
+    if palette_cycle.is_some_and(|secs| secs.is_nan() || secs < 0.0) {
+        panic!("Invalid palette cycle")
     }
-
-    let bg_color = 107;
-    let layers = vec![
-        LayerDesc {
-            density: 0.75,
-            collision: 0.4,
-            speed: 4,
-            wall_color: av![47],
-            draw_windows: false,
-            window_colors: Default::default(),
-        },
-        LayerDesc {
-            density: 0.6,
-            collision: 0.1,
-            speed: 3,
-            wall_color: av![100, 101],
-            draw_windows: false,
-            window_colors: Default::default(),
-        },
-        LayerDesc {
-            density: 0.4,
-            collision: 0.05,
-            speed: 1,
-            wall_color: av![40],
-            draw_windows: true,
-            window_colors: av![40, 107, 101],
-        }
-    ];
-
-    let running = {
-        let r1 = Arc::new(AtomicBool::new(true));
-        let r2 = Arc::clone(&r1);
+    let palette_cycle = palette_cycle.map(|secs| secs * motion.window_change_slowdown());
+
+    // a panic mid-frame would leave the terminal without a cursor
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        console::restore_cursor();
+        default_hook(info)
+    }));
+
+    // Ctrl-C and keys send commands here, the loop applies them once per frame
+    let (commands_tx, commands) = mpsc::channel();
+    {
+        let tx = commands_tx.clone();
         ctrlc::set_handler(move || {
-            r2.store(false, Ordering::SeqCst);
+            let _ = tx.send(Command::Stop);
         }).unwrap();
-        r1
-    };
+    }
 
-    let tick_num_width = (TICK_WRAP as f32).log10() as usize + 1;
-    let target_frame_time_ms = 1000 / fps;
-    let frame_time = Duration::from_millis(target_frame_time_ms);
     let error_refresh_time = Duration::from_millis(500);
     let zero_d = Duration::new(0, 0);
     let mut r_times = BoundedVecDeque::new(1000);
+    // sim, format and write time with bytes written, summed over all frames
+    let mut phase_totals = (zero_d, zero_d, zero_d, 0u64);
+    let mut end_write = WriteStats::default();
+    let term_wh = console::get_term_dimensions();
+    let mut pending_term: Option<(Option<(usize, usize)>, Instant)> = None;
+    // started by the first quit, the outro is motion too and only makes sense on a terminal
+    let outro_allowed = opts.outro == Some(true) && !opts.calm && console::is_tty();
+    let mut outro_since: Option<Instant> = None;
+
+    if opts.demo_time == Some(0) {
+        panic!("Invalid demo time")
+    }
+    let mut scenario = opts.scenario.as_ref().map(|path| Scenario::load(path)
+        .unwrap_or_else(|e| panic!("Can't load scenario {}: {}", path.display(), e)));
+    let demo_layers = match opts.demo || scenario.is_some() {
+        true => demo::scene_layers(&layers),
+        false => Vec::new(),
+    };
+    let mut demo = match opts.demo {
+        true => Some(Demo::new(Duration::from_secs(opts.demo_time.unwrap_or(8)))),
+        false => None,
+    };
 
     let rng = Rng::with_seed(seed);
     let mut console_buf = String::new();
-    let mut city_state = City::new(width, height, step, &rng, bg_color, &layers);
-    let mut skip_ticks = layers.iter().map(|d| d.speed).max().unwrap_or(0) * width as u32;
+    // an auto-sized terminal may start too small, the city gets its size once it grows
+    let mut city_state = City::new(width.max(SIZE_MIN_W), height.max(SIZE_MIN_H), step, &rng, bg_color, &layers);
+    let sky = setup_city(&mut city_state, &opts, seed, motion, &layers);
+    let start_tick = match bookmark.as_ref() {
+        Some(b) => b.tick,
+        None => 1 + city_state.warmup_ticks(),
+    };
+    let stopped = match oneline {
+        Some(_) => {
+            city_state.seek(start_tick);
+            false
+        }
+        None => seek_with_splash(&mut city_state, start_tick, &commands),
+    };
+    if stopped {
+        console::restore_cursor();
+        exit(0);
+    }
+    if let (Some(period), None) = (opts.loop_period, bookmark.as_ref()) {
+        // wide buildings spawned before the first tick can still be leaving after warmup
+        let tick = city_state.get_tick();
+        city_state.seek(tick + period.min(TICK_WRAP - tick));
+    }
+
+    if let (Some(width), Some((lock, state))) = (opts.oneline, oneline.as_ref()) {
+        let period = opts.loop_period.unwrap_or(oneline::LOOP_PERIOD);
+        let tick = city_state.get_tick();
+        city_state.seek(tick + ((state.tick % period as u64) as Tick).min(TICK_WRAP - tick));
+        city_state.next_tick();
+        println!("{}", oneline::render(&city_state.skyline_colors(width.max(1)), opts.oneline_tmux));
+        if let Err(e) = oneline::save(lock, state) {
+            eprintln!("Can't save one line state: {}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(path) = opts.export_ans.as_ref() {
+        city_state.next_tick();
+        if let Err(e) = fs::write(path, screenshot::Frame::of(&city_state).to_ans()) {
+            eprintln!("Can't write {}: {}", path.display(), e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(path) = opts.heatmap.as_ref() {
+        let ticks = opts.heatmap_ticks.unwrap_or(heatmap::TICKS_DEFAULT);
+        if let Err(e) = heatmap::write(&opts, (width, height), ticks, path) {
+            eprintln!("Can't write {}: {}", path.display(), e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(path) = opts.panorama.as_ref() {
+        let width = opts.panorama_width.unwrap_or(headless::PANORAMA_W_DEFAULT);
+        if width < SIZE_MIN_W {
+            panic!("Panorama width must be at least {}", SIZE_MIN_W)
+        }
+        if let Err(e) = headless::panorama(&opts, (width, height)).write_png(path) {
+            eprintln!("Can't write {}: {}", path.display(), e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(path) = opts.pbm.as_ref() {
+        let scale = opts.pbm_scale.unwrap_or(1);
+        if scale < 1 {
+            panic!("Invalid PBM scale")
+        }
+        city_state.next_tick();
+        let ink = opts.pbm_ink.unwrap_or_default();
+        if let Err(e) = pbm::write(city_state.get_canvas(), city_state.get_background(), path, scale, &ink) {
+            eprintln!("Can't write {}: {}", path.display(), e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(secs) = opts.start_at {
+        if let Ok(wait) = (UNIX_EPOCH + Duration::from_secs(secs)).duration_since(SystemTime::now()) {
+            sleep(wait);
+        }
+    }
+
+    let cycle_ticks = if opts.one_cycle { Some(city_state.cycle_ticks() as u64) } else { None };
+    let mut profile = match opts.profile {
+        Some(secs) if secs.is_nan() || secs <= 0.0 => panic!("Invalid profile time"),
+        Some(secs) => Some((Duration::from_secs_f32(secs), Profile::default())),
+        None => None,
+    };
+    city_state.set_profiling(profile.is_some());
+    let mut frames: u64 = 0;
+    let mut ticks: u64 = 0; // fewer than frames when paused or paced
+    let mut fps_ramp = match opts.fps_ramp {
+        Some(secs) if secs.is_nan() || secs < 0.0 => panic!("Invalid fps ramp"),
+        Some(secs) if secs > 0.0 => Some(FpsRamp::new(Duration::from_secs_f32(secs))),
+        _ => None,
+    };
+    let mut owed_frames = OwedFrames::default();
+    // the last frame is kept to tell whether the picture changed
+    let mut power_save = if opts.power_save { Some((PowerSave::default(), None)) } else { None };
+    let started = Instant::now();
+    let segments = opts.status_segments.clone().unwrap_or_default();
+    let mut status = Status::new(if opts.show_elapsed { segments.with_elapsed() } else { segments });
+
+    let _input = Input::start(commands_tx);
+    #[cfg(unix)]
+    let mut control = {
+        let force = opts.control_force;
+        opts.control.as_ref().map(|path| ControlSocket::bind(path, force)
+            .unwrap_or_else(|e| panic!("Can't listen on {}: {}", path.display(), e)))
+    };
+    #[cfg(unix)]
+    let mut metrics = {
+        let force = opts.metrics_force;
+        opts.metrics_socket.as_ref().map(|path| MetricsSocket::bind(path, force)
+            .unwrap_or_else(|e| panic!("Can't listen on {}: {}", path.display(), e)))
+    };
+    #[cfg(not(unix))]
+    if opts.control.is_some() || opts.metrics_socket.is_some() {
+        panic!("Control and metrics sockets need a Unix system")
+    }
 
-    let seed_str = format!("seed: {}", seed);
+    let sixel = opts.sixel.unwrap_or_else(sixel::term_supports);
+    let colors = opts.colors.unwrap_or_else(console::term_color_depth);
 
-    let mut reset_console = true;
+    // demo changes the seed with every scene, the status line shows it instead
+    let seed_str = match opts.demo {
+        true => "demo".to_string(),
+        false => match daily {
+            Some(date) => format!("daily seed for {}: {}", date, seed),
+            None => format!("seed: {}", seed),
+        },
+    };
+    let seed_str = match sixel {
+        true => seed_str,
+        false => format!("{} / colors: {}", seed_str, colors),
+    };
+
+    if !opts.minimap {
+        // previous frame is compared row by row to skip unchanged lines
+        city_state.enable_double_buffer();
+    }
+
+    let write_opts = WriteOpts {
+        bce: !opts.no_bce && console::term_has_bce(),
+        chunk: opts.write_chunk.unwrap_or(WRITE_CHUNK_DEFAULT),
+        colors,
+    };
+    let setup = Setup {
+        step,
+        layer_seeds: opts.layer_seeds,
+        legacy_spawn: opts.legacy_spawn,
+        scene_layers: &demo_layers,
+        sky: sky.clone(),
+        bg_color,
+        bookmarks: demo.is_none(),
+        text_canvas: !opts.minimap && !sixel,
+        pbm_scale: opts.pbm_scale.unwrap_or(1).max(1),
+        pbm_ink: opts.pbm_ink.clone().unwrap_or_default(),
+        screenshot_dir: opts.screenshot_dir.clone().unwrap_or_else(|| ".".into()),
+        screenshot_pbm: opts.screenshot_pbm,
+    };
+    let mut state = LoopState::new(city_state, seed, fps, (width, height), show_header, term_wh);
+    state.speed = opts.speed;
+    if let Some(cells_per_sec) = motion.max_cells_per_sec() {
+        // the fastest layer of any scene moves `step` cells every `speed` ticks
+        let fastest = layers.iter().chain(demo_layers.iter().flatten()).map(|d| d.speed).min().unwrap_or(1);
+        state.calm_interval = Some(Duration::from_secs_f32(step as f32 / fastest as f32 / cells_per_sec));
+    }
+    state.pace(step, fps);
 
-    while reset_console {
-        reset_console = false;
+    while state.reset_console {
+        state.reset_console = false;
         console_buf.clear();
         console_buf.shrink_to_fit();
 
+        let show_header = state.show_header;
+        let (view_w, view_h) = if opts.minimap { (MINIMAP_W, MINIMAP_H) } else { state.size };
+        let margins = match center {
+            true => console::center_margins(state.term_wh, (view_w, view_h), &user_margins,
+                                            &title, opts.center_vertical),
+            false => user_margins,
+        };
+
+        // without a header there's nothing to put below the status
+        let layout = match opts.layout.unwrap_or_default() {
+            Layout::TitleBottom if !show_header => Layout::TitleTop,
+            layout => layout,
+        };
+        let header_lines = if show_header { title.header_lines(view_w) } else { 0 };
+        let canvas_up = layout.canvas_up(view_h, header_lines, &margins);
+        let cell_px = console::term_cell_pixels().unwrap_or(sixel::CELL_PX_DEFAULT);
+        let print_header = || {
+            title.print(view_w, &margins);
+            info_center(&seed_str, view_w, &margins);
+        };
+
         console::setup_console();
-        info_center("oO0OoO0OoO0Oo CiTY oO0OoO0OoO0Oo", width);
-        info_center(&seed_str, width);
-        println!();
+        console::prepare_margin_top(&margins);
+        if layout != Layout::TitleBottom && show_header {
+            print_header();
+            println!();
+        }
 
-        console::prepare_canvas(height);
+        console::prepare_canvas(view_h, &margins, layout);
+        if layout == Layout::TitleBottom {
+            print_header();
+            console::prepare_margin_bottom(&margins);
+        }
         let out = std::io::stdout();
         let mut out_lock = out.lock();
+        let mut full_redraw = true;
+        // inspector shows the building under the pointer, found from its terminal cell and
+        // the terminal row of the canvas top
+        let mut canvas_row_asked = false;
+        state.cursor_row = None;
+        if state.inspecting {
+            console::set_mouse_reporting(true);
+        }
+        if power_save.is_some() {
+            console::set_focus_reporting(true);
+        }
 
-        while running.load(Ordering::Relaxed) {
+        // commands that came in while the outro waited for a stop, applied with the next frame's
+        let mut queued = Vec::new();
+        while state.running {
             let start = SystemTime::now();
 
-            if skip_ticks == 0 {
-                if auto_size {
-                    let (w, h) = console::get_term_size();
-                    if w != width || h != height {
-                        if w >= SIZE_MIN_W && h >= SIZE_MIN_H {
-                            city_state.set_wh(w, h);
-                        }
-                        width = w;
-                        height = h;
-                        reset_console = true;
-                        break;
-                    }
+            // a scenario's commands for this frame run first, then the control socket's,
+            // then keys and Ctrl-C sent since the last frame
+            let mut incoming = scenario.as_mut().map_or_else(Vec::new, |s| s.poll(state.frame_time));
+            #[cfg(unix)]
+            incoming.extend(control.as_mut().map_or_else(Vec::new, ControlSocket::poll));
+            for command in incoming.into_iter().chain(queued.drain(..)).chain(commands.try_iter()) {
+                apply_command(&mut state, &setup, command);
+                // the first quit starts the outro instead, quitting again ends it right away
+                if !state.running && outro_allowed && !state.paused && outro_since.is_none() {
+                    outro_since = Some(Instant::now());
+                    state.running = true;
                 }
-
-                if width < SIZE_MIN_W || height < SIZE_MIN_H {
-                    console::clear_line_msg(&mut out_lock,
-                                            format_args!("Too small ({}x{}) < ({}x{})",
-                                                         width, height, SIZE_MIN_W, SIZE_MIN_H));
-                    sleep(error_refresh_time);
-                    continue;
+            }
+            if !state.running {
+                break;
+            }
+            if let Some(since) = outro_since {
+                let progress = since.elapsed().as_secs_f32() / OUTRO_TIME.as_secs_f32();
+                if progress >= 1.0 {
+                    state.running = false;
+                    break;
                 }
+                state.city.set_outro(progress);
             }
 
-            city_state.next_tick();
+            if auto_size || center {
+                let term = console::get_term_dimensions();
+                let stable = match pending_term {
+                    _ if term == state.term_wh => {
+                        pending_term = None;
+                        false
+                    }
+                    Some((pending, since)) if pending == term => since.elapsed() >= resize_delay,
+                    _ => {
+                        // coalesce intermediate sizes until the terminal stops changing
+                        pending_term = Some((term, Instant::now()));
+                        resize_delay == zero_d
+                    }
+                };
+
+                if stable {
+                    pending_term = None;
+                    let canvas = auto_size.then(|| console::get_term_size(&user_margins, &title));
+                    apply_command(&mut state, &setup, Command::Resize { term, canvas });
+                    break;
+                }
+            }
 
-            if skip_ticks > 0 {
-                skip_ticks -= 1;
+            let (width, height) = state.size;
+            if width < SIZE_MIN_W || height < SIZE_MIN_H {
+                console::clear_line_msg(&mut out_lock, console::too_small_msg(state.term_wh, &user_margins));
+                full_redraw = true;
+                sleep(error_refresh_time);
                 continue;
             }
 
+            if let Some(secs) = palette_cycle {
+                let shift = (started.elapsed().as_secs_f32() / secs) as usize;
+                state.city.set_palette_shift(shift, opts.palette_cycle_lit);
+            }
+            // frames of the ramp and of power saving are longer and run the ticks of the target
+            // frames they stand for, only the last is drawn so the diff is against the shown frame
+            let ramp_frame = fps_ramp.as_ref().and_then(|ramp| ramp.frame_time(started.elapsed(), state.frame_time));
+            if ramp_frame.is_none() {
+                fps_ramp = None;
+            }
+            let saving_frame = power_save.as_ref()
+                .and_then(|(save, _)| save.frame_time(state.focused, Instant::now(), state.frame_time));
+            let long_frame = ramp_frame.max(saving_frame);
+            let frames_owed = match long_frame {
+                Some(frame) => owed_frames.take(frame, state.frame_time),
+                None => 1,
+            };
+            let frame_time = long_frame.unwrap_or(state.frame_time);
+            let target_ms = long_frame.map_or(state.target_frame_time_ms, |frame| frame.as_millis() as u64);
+            let mut due = 0;
+            for _ in 0..frames_owed {
+                if !state.paused && state.pacer.due(state.frame_time) {
+                    due += 1;
+                }
+                if cycle_ticks == Some(ticks + due) {
+                    break;
+                }
+            }
+            state.city.run_ticks(due);
+            ticks += due;
+
             let before_draw = SystemTime::now();
-            console::draw_to_console(&city_state, &mut console_buf, &mut out_lock);
+            // what's left of the frame in the buffer is written with the status, in one write
+            let canvas_write = if opts.minimap {
+                console::draw_minimap(&state.city, (view_w, view_h), &mut console_buf, &margins, canvas_up);
+                WriteStats::default()
+            } else if sixel {
+                sixel::draw(&state.city, cell_px, &mut console_buf, &mut out_lock, &margins, canvas_up,
+                            write_opts.chunk)
+            } else {
+                let stats = console::draw_to_console(&state.city, &mut console_buf, &mut out_lock, &margins,
+                                                     canvas_up, full_redraw, &write_opts);
+                full_redraw = false;
+                stats
+            };
+            let draw_time = SystemTime::now().duration_since(before_draw).unwrap_or(zero_d);
+            if let Some((save, last)) = power_save.as_mut() {
+                let changed = last.as_ref().is_none_or(|last: &screenshot::Frame| !last.shows(&state.city));
+                save.drawn(changed, Instant::now());
+                if changed {
+                    match last.as_mut() {
+                        Some(last) => last.update(&state.city),
+                        None => *last = Some(screenshot::Frame::of(&state.city)),
+                    }
+                }
+            }
+            state.drawn();
+            frames += 1;
+
+            if cycle_ticks == Some(ticks) || profile.as_ref().is_some_and(|(secs, _)| started.elapsed() >= *secs) {
+                apply_command(&mut state, &setup, Command::Stop);
+            }
 
             let diff = SystemTime::now().duration_since(start).unwrap_or(zero_d);
             let diff_tick = before_draw.duration_since(start).unwrap_or(zero_d);
             let sleep_d = frame_time.checked_sub(diff).unwrap_or(zero_d);
 
-            let real_fps = match sleep_d.as_millis() as u64 {
-                0 => 1000_u64 / diff.as_millis() as u64,
-                _ => fps,
-            };
+            // the end of the frame is written after the values are taken, so it's counted with the next one
+            let format_time = draw_time.saturating_sub(canvas_write.time);
+            let write_time = canvas_write.time + end_write.time;
+            let bytes = canvas_write.bytes + end_write.bytes;
+            phase_totals.0 += diff_tick;
+            phase_totals.1 += format_time;
+            phase_totals.2 += write_time;
+            phase_totals.3 += bytes as u64;
+            if let Some(((_, profile), times)) = profile.as_mut().zip(state.city.take_tick_times()) {
+                profile.add(&times, format_time, write_time, sleep_d);
+            }
+
+            r_times.push_back(diff.as_millis() as u32);
+
+            // average over recent frames for a readable number, frames faster than target are padded by sleep
+            let (smooth_n, smooth_ms) = r_times.iter().rev().take(FPS_SMOOTH_FRAMES)
+                .fold((0, 0), |(n, sum), &t| (n + 1, sum + (t as u64).max(target_ms)));
+            let real_fps = 1000 * smooth_n / smooth_ms.max(1);
+
+            if let Some((scene_i, switches)) = demo.as_mut().and_then(Demo::poll) {
+                let scene_seed = seed.wrapping_add(switches);
+                rng.seed(scene_seed);
+                control::show_scene(&mut state.city, &demo_layers[scene_i], scene_i, sky.clone(), bg_color);
+                if opts.layer_seeds {
+                    state.city.seed_layers(scene_seed);
+                }
+                if let Some(period) = opts.loop_period {
+                    state.city.set_loop_period(period, scene_seed);
+                }
+                state.city.warmup();
+                state.status_msg = Some((format!("demo: {} / seed: {}", demo::scene_name(scene_i), scene_seed),
+                                   Instant::now()));
+            }
 
-            let diff_us = diff.as_micros();
-            let diff_ms = diff_us / 1000;
-            let diff_ms_fract = diff_us / 10 - diff_ms;
+            if state.status_msg.as_ref().is_some_and(|(_, since)| since.elapsed() > STATUS_MSG_TIME) {
+                state.status_msg = None;
+            }
 
-            write!(out_lock, "\r\x1b[0m\x1b[0Jtick: {: >tnw$} / tick time: {: >4}us / real fps: {: >4}\n\
-                                              real frametime: {: >4}.{:0<2}ms / target rametime: {: >4}ms",
-                   city_state.get_tick(), diff_tick.as_micros(), real_fps,
-                   diff_ms, diff_ms_fract, target_frame_time_ms, tnw = tick_num_width).unwrap();
-            out_lock.flush().unwrap();
+            // narrow canvases only get the segments that fit next to them
+            let status_w = if view_w < SIZE_FULL_W { view_w } else { usize::MAX };
+            let values = status::Values {
+                tick: state.city.get_tick(),
+                tick_time: diff_tick,
+                format_time,
+                write_time,
+                bytes,
+                fps: real_fps,
+                frametime: diff,
+                target_ms,
+                frames,
+                elapsed: started.elapsed(),
+                buildings: state.city.building_count(),
+                building_cap: state.city.building_cap(),
+            };
+            let [line1, mut line2] = status.lines(status_w, &values);
+            #[cfg(unix)]
+            if let Some(metrics) = metrics.as_mut() {
+                metrics.send(|| values.to_json());
+            }
+            let canvas_row = state.cursor_row.and_then(|row| row.checked_sub(canvas_up));
+            let inspected = state.pointer.zip(canvas_row)
+                .and_then(|((col, row), top)| Some((col.checked_sub(1 + margins.left)?, row.checked_sub(top)?)))
+                .and_then(|xy| state.city.building_at(xy))
+                .map(|b| format!("layer {} / id {} / {}x{} / spawned at {} / wall {} / seed {}",
+                                 b.layer, b.id, b.size.0, b.size.1, b.spawn_tick, b.color, b.seed));
+            for msg in inspected.iter().chain(state.status_msg.as_ref().map(|(msg, _)| msg)) {
+                if !line2.is_empty() {
+                    line2.push_str(" / ");
+                }
+                line2.push_str(msg);
+            }
+            let line2 = width::truncate(&line2, status_w);
+
+            let fwd = CursorMove::fwd(margins.left);
+            write!(console_buf, "{}\r\x1b[0m{}{}\x1b[K\n{}{}\x1b[K{}",
+                   layout.status_from_canvas(view_h), fwd, line1, fwd, line2,
+                   layout.rest_from_status(view_h, header_lines, &margins)).unwrap();
+            end_write = WriteStats::default();
+            end_write.write(&mut out_lock, console_buf.as_bytes());
+            if state.inspecting && !canvas_row_asked {
+                // the cursor is back where drawing starts, `canvas_up` rows below the canvas top
+                console::request_cursor_pos(&mut out_lock);
+                canvas_row_asked = true;
+            }
 
-            r_times.push_back(diff.as_millis() as u32);
-            sleep(sleep_d);
+            match outro_since {
+                // waits for commands instead, so a second Ctrl-C doesn't wait for the frame to end
+                Some(_) => if wait_for_stop(&commands, Instant::now() + sleep_d, &mut queued) {
+                    state.running = false;
+                    break;
+                },
+                None => sleep(sleep_d),
+            }
         }
 
         console::destroy_console();
     }
 
+    if let Some((_, profile)) = profile {
+        print!("{}", profile.report(state.frame_time));
+    }
+
+    if opts.no_stats {
+        return;
+    }
+
+    if let Some(cycle) = cycle_ticks {
+        println!("one cycle: {} of {} ticks", ticks, cycle);
+    }
+
     let mut r_times = r_times.into_unbounded();
     let r_times = unsafe { deque_raw_slice(&mut r_times) };
 
@@ -267,5 +805,86 @@ fn main() {
     let rtl = r_times.len();
     println!("render time: avg {}, 1th {}, 50th {}, 97th {}",
              r_times.iter().fold(0.0, |b, t| b + *t as f32) / rtl as f32,
-             r_times[rtl / 100 * 1], r_times[rtl / 100 * 50], r_times[rtl / 100 * 97]);
+             r_times[rtl / 100], r_times[rtl / 100 * 50], r_times[rtl / 100 * 97]);
+    let (sim, format, write, bytes) = phase_totals;
+    let per_frame = |d: Duration| d.as_micros() / frames.max(1) as u128;
+    println!("per frame: sim {}us, format {}us, write {}us, {} bytes",
+             per_frame(sim), per_frame(format), per_frame(write), bytes / frames.max(1));
+    println!("reproduce with: {}", cmdline);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn seed_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("city-seed-{}-test-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn outro_wait_keeps_commands() {
+        let (tx, rx) = mpsc::channel();
+        let mut queued = Vec::new();
+        tx.send(Command::Seed(3)).unwrap();
+        tx.send(Command::Pause).unwrap();
+        let until = Instant::now() + Duration::from_millis(30);
+        assert!(!wait_for_stop(&rx, until, &mut queued));
+        assert!(Instant::now() >= until, "the rest of the frame is waited");
+        assert_eq!(queued, [Command::Seed(3), Command::Pause]);
+
+        queued.clear();
+        for command in [Command::Resume, Command::Stop, Command::Seed(4)] {
+            tx.send(command).unwrap();
+        }
+        assert!(wait_for_stop(&rx, Instant::now() + Duration::from_secs(10), &mut queued));
+        assert_eq!(queued, [Command::Resume]);
+
+        drop(tx);
+        let until = Instant::now() + Duration::from_millis(30);
+        assert!(!wait_for_stop(&rx, until, &mut queued));
+        assert!(Instant::now() >= until);
+        assert_eq!(queued, [Command::Resume, Command::Seed(4)]);
+    }
+
+    #[test]
+    fn seed_file_counts_up() {
+        let path = seed_file("stable");
+        fs::write(&path, "42\n").unwrap();
+        assert_eq!(seed_from_file(&path), 42);
+        assert_eq!(seed_from_file(&path), 43);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "44\n");
+
+        // the same file gives the same seed
+        fs::write(&path, "42\n").unwrap();
+        assert_eq!(seed_from_file(&path), 42);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_or_empty_seed_file_starts_from_now() {
+        let path = seed_file("new");
+        for text in [None, Some(""), Some(" \n")] {
+            match text {
+                Some(text) => fs::write(&path, text).unwrap(),
+                None => { let _ = fs::remove_file(&path); }
+            }
+            let before = unix_time();
+            let seed = seed_from_file(&path);
+            assert!((before..=unix_time()).contains(&seed), "{:?}", text);
+            assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", seed + 1), "{:?}", text);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_seed_file_is_left_alone() {
+        let path = seed_file("bad");
+        fs::write(&path, "not a seed\n").unwrap();
+        let before = unix_time();
+        assert!((before..=unix_time()).contains(&seed_from_file(&path)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a seed\n");
+        fs::remove_file(&path).unwrap();
+    }
 }
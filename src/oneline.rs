@@ -0,0 +1,171 @@
+use std::fmt::{self, Write as fmtWrite};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::city::{PaletteColor, Tick};
+use crate::paths;
+
+const FILE_NAME: &str = "oneline.txt";
+const LOCK_NAME: &str = "oneline.lock";
+
+/// Ticks the city moves on per second between runs
+pub const TICKS_PER_SEC: u64 = 1;
+
+/// Loop period used unless one is given, so each run seeks a bounded number of ticks
+pub const LOOP_PERIOD: Tick = 3600;
+
+/// Where the next run continues the city from
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub seed: u64,
+    pub tick: u64, // ticks since the first run, the city loops over them
+    pub time: u64, // unix time of the run that saved it
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seed={} tick={} time={}", self.seed, self.tick, self.time)
+    }
+}
+
+impl FromStr for State {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut seed, mut tick, mut time) = (None, None, None);
+        for pair in s.split_whitespace() {
+            let (key, v) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+            let v = v.parse().map_err(|_| format!("bad {} value '{}'", key, v))?;
+            match key {
+                "seed" => seed = Some(v),
+                "tick" => tick = Some(v),
+                "time" => time = Some(v),
+                _ => {}
+            }
+        }
+
+        match (seed, tick, time) {
+            (Some(seed), Some(tick), Some(time)) => Ok(State { seed, tick, time }),
+            _ => Err("missing seed, tick or time".to_string()),
+        }
+    }
+}
+
+/// Exclusive hold on the state, concurrent runs wait for it until it's dropped
+pub struct Lock {
+    _file: File,
+    dir: PathBuf, // the state is kept in
+}
+
+fn dir() -> io::Result<PathBuf> {
+    let dir = paths::state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "neither XDG_STATE_HOME nor HOME is set"))?;
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn lock() -> io::Result<Lock> {
+    lock_in(&dir()?)
+}
+
+fn lock_in(dir: &Path) -> io::Result<Lock> {
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(LOCK_NAME))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(Lock { _file: file, dir: dir.to_path_buf() })
+}
+
+/// State of the previous run moved on to `now`. A missing or unreadable state,
+/// or one with a different seed than asked for, starts a new city
+pub fn resume(lock: &Lock, seed: Option<u64>, now: u64) -> io::Result<State> {
+    let fresh = State { seed: seed.unwrap_or(now), tick: 0, time: now };
+    let text = match fs::read_to_string(lock.dir.join(FILE_NAME)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(fresh),
+        Err(e) => return Err(e),
+    };
+
+    Ok(match text.trim().parse::<State>() {
+        Ok(s) if seed.is_none_or(|seed| seed == s.seed) =>
+            State { tick: s.tick + now.saturating_sub(s.time) * TICKS_PER_SEC, time: now, ..s },
+        _ => fresh,
+    })
+}
+
+pub fn save(lock: &Lock, state: &State) -> io::Result<()> {
+    fs::write(lock.dir.join(FILE_NAME), format!("{}\n", state))
+}
+
+/// Row of spaces in the colors, with escape codes or as a tmux status string
+pub fn render(colors: &[PaletteColor], tmux: bool) -> String {
+    let mut line = String::new();
+    let mut last = None;
+    for &color in colors {
+        if last != Some(color) {
+            match tmux {
+                true => write!(line, "#[bg={}]", tmux_color(color)),
+                false => write!(line, "\x1b[{}m", color),
+            }.unwrap();
+            last = Some(color);
+        }
+        line.push(' ');
+    }
+    line.push_str(if tmux { "#[default]" } else { "\x1b[0m" });
+    line
+}
+
+/// tmux name of a background color code
+fn tmux_color(color: PaletteColor) -> String {
+    match color {
+        40..=47 => format!("colour{}", color - 40),
+        100..=107 => format!("colour{}", color - 100 + 8),
+        _ => "default".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_round_trip() {
+        let state = State { seed: 42, tick: 7, time: 1_700_000_000 };
+        assert_eq!(state.to_string().parse::<State>(), Ok(state.clone()));
+        assert_eq!("time=3 other=x seed=1 tick=2".parse::<State>().unwrap_err(), "bad other value 'x'");
+        assert_eq!("time=3 seed=1 tick=2 more=4".parse(), Ok(State { seed: 1, tick: 2, time: 3 }));
+        assert!("seed=1 tick=2".parse::<State>().unwrap_err().contains("missing"));
+        assert!("seed 1".parse::<State>().is_err());
+    }
+
+    #[test]
+    fn runs_continue_the_city() {
+        let dir = std::env::temp_dir().join(format!("city-oneline-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lock = lock_in(&dir).unwrap();
+
+        let first = resume(&lock, None, 1000).unwrap();
+        assert_eq!(first, State { seed: 1000, tick: 0, time: 1000 });
+        save(&lock, &first).unwrap();
+
+        // a later run moves on by the time between them and keeps the seed
+        let second = resume(&lock, None, 1030).unwrap();
+        assert_eq!(second, State { seed: 1000, tick: 30 * TICKS_PER_SEC, time: 1030 });
+        save(&lock, &second).unwrap();
+        assert_eq!(resume(&lock, Some(1000), 1040).unwrap().tick, 40 * TICKS_PER_SEC);
+        assert_eq!(resume(&lock, None, 1000).unwrap().tick, 30 * TICKS_PER_SEC);
+
+        // another seed or a broken file start over
+        assert_eq!(resume(&lock, Some(5), 1040).unwrap(), State { seed: 5, tick: 0, time: 1040 });
+        fs::write(dir.join(FILE_NAME), "garbage").unwrap();
+        let broken = resume(&lock, None, 1050);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(broken.unwrap(), State { seed: 1050, tick: 0, time: 1050 });
+    }
+}
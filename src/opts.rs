@@ -0,0 +1,818 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::iter;
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+
+use crate::city::{ColorDepth, Grid, Horizon, LayerColors, LayerNumber, Shard, Sky, Stars, Tick, WallColor};
+use crate::config;
+use crate::console::{Layout, Margins};
+use crate::pbm::{Ink, PixelSize};
+use crate::status::Segments;
+use crate::title::TitleStyle;
+
+#[derive(Debug, Default)]
+pub struct Opts {
+    pub fps: Option<u64>,
+    pub fps_ramp: Option<f32>,
+    pub step: Option<Tick>,
+    pub speed: Option<u32>,
+    pub seed: Option<u64>,
+    pub seed_file: Option<PathBuf>,
+    pub daily: bool,
+    pub daily_offset: Option<i64>,
+    pub layer_seeds: bool,
+    pub auto_size: bool,
+    pub resize_delay: Option<u64>,
+    pub margins: Option<Margins>,
+    pub title: Option<String>,
+    pub title_style: Option<TitleStyle>,
+    pub window_glyph: Option<char>,
+    pub age_fade: Option<f32>,
+    pub face_gradient: Option<f32>,
+    pub palette_cycle: Option<f32>,
+    pub palette_cycle_lit: bool,
+    pub mirror: bool,
+    pub calm: bool,
+    pub motion_blur: Option<f32>,
+    pub rising: bool,
+    pub legacy_spawn: bool,
+    pub horizon: Option<Horizon>,
+    pub stars: Option<Stars>,
+    pub debug_grid: bool,
+    pub grid: Option<Grid>,
+    pub sky: Option<Sky>,
+    pub sky_drift: Option<i32>,
+    pub dither: bool,
+    pub walls: Vec<LayerColors<WallColor>>,
+    pub windows: Vec<LayerColors>,
+    pub spawn_jitter: Option<usize>,
+    pub grow_ticks: Option<Tick>,
+    pub despawn_margin: Option<usize>,
+    pub no_clip: Vec<usize>,
+    pub target_counts: Vec<LayerNumber>,
+    pub sinks: Vec<LayerNumber>,
+    pub stamps: Vec<PathBuf>,
+    pub stamp_chance: Option<f32>,
+    pub sprite: Option<PathBuf>,
+    pub no_center: bool,
+    pub center_vertical: bool,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub world_width: Option<usize>,
+    pub view_x: Option<usize>,
+    pub shard: Option<Shard>,
+    pub shard_seed: Option<u64>,
+    pub start_at: Option<u64>,
+    pub one_cycle: bool,
+    pub profile: Option<f32>,
+    pub power_save: bool,
+    pub show_elapsed: bool,
+    pub status_segments: Option<Segments>,
+    pub loop_period: Option<Tick>,
+    pub minimap: bool,
+    pub sixel: Option<bool>,
+    pub colors: Option<ColorDepth>,
+    pub demo: bool,
+    pub demo_time: Option<u64>,
+    pub scenario: Option<PathBuf>,
+    pub control: Option<PathBuf>,
+    pub control_force: bool,
+    pub metrics_socket: Option<PathBuf>,
+    pub metrics_force: bool,
+    pub no_bce: bool,
+    pub write_chunk: Option<usize>,
+    pub layout: Option<Layout>,
+    pub pbm: Option<PathBuf>,
+    pub pbm_scale: Option<usize>,
+    pub pbm_ink: Option<Ink>,
+    pub screenshot_dir: Option<PathBuf>,
+    pub screenshot_pbm: bool,
+    pub fit: Option<PixelSize>,
+    pub export_ans: Option<PathBuf>,
+    pub check_ans: Option<PathBuf>,
+    pub heatmap: Option<PathBuf>,
+    pub heatmap_ticks: Option<usize>,
+    pub panorama: Option<PathBuf>,
+    pub panorama_width: Option<usize>,
+    pub config: Option<PathBuf>,
+    pub no_config: bool,
+    pub bookmark_list: bool,
+    pub list_colors: bool,
+    pub from_bookmark: Option<usize>,
+    pub selftest: bool,
+    pub print_cmdline: bool,
+    pub no_stats: bool,
+    pub outro: Option<bool>,
+    pub oneline: Option<usize>,
+    pub oneline_tmux: bool,
+}
+
+impl Opts {
+    /// Arguments that set every option given here, config and environment included,
+    /// so they reproduce the same settings with `--no-config`. Actions like --selftest are left out
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec!["--no-config".to_string()];
+        let mut value = |name: &str, v: Option<String>| if let Some(v) = v {
+            args.push(name.to_string());
+            args.push(v);
+        };
+
+        value("--fps", self.fps.map(|v| v.to_string()));
+        value("--fps-ramp", self.fps_ramp.map(|v| v.to_string()));
+        value("--step", self.step.map(|v| v.to_string()));
+        value("--speed", self.speed.map(|v| v.to_string()));
+        value("--seed", self.seed.map(|v| v.to_string()));
+        value("--width", self.width.map(|v| v.to_string()));
+        value("--height", self.height.map(|v| v.to_string()));
+        value("--world-width", self.world_width.map(|v| v.to_string()));
+        value("--view-x", self.view_x.map(|v| v.to_string()));
+        value("--shard", self.shard.map(|v| v.to_string()));
+        value("--shard-seed", self.shard_seed.map(|v| v.to_string()));
+        value("--start-at", self.start_at.map(|v| v.to_string()));
+        value("--resize-delay", self.resize_delay.map(|v| v.to_string()));
+        value("--margin", self.margins.map(|v| v.to_string()));
+        value("--title", self.title.clone());
+        value("--window-glyph", self.window_glyph.map(|v| v.to_string()));
+        value("--age-fade", self.age_fade.map(|v| v.to_string()));
+        value("--face-gradient", self.face_gradient.map(|v| v.to_string()));
+        value("--palette-cycle", self.palette_cycle.map(|v| v.to_string()));
+        value("--motion-blur", self.motion_blur.map(|v| v.to_string()));
+        value("--horizon", self.horizon.map(|v| v.to_string()));
+        value("--stars", self.stars.map(|v| v.to_string()));
+        value("--grid", self.grid.map(|v| v.to_string()));
+        value("--sky", self.sky.as_ref().map(|v| v.to_string()));
+        value("--sky-drift", self.sky_drift.map(|v| v.to_string()));
+        value("--spawn-jitter", self.spawn_jitter.map(|v| v.to_string()));
+        value("--grow-ticks", self.grow_ticks.map(|v| v.to_string()));
+        value("--despawn-margin", self.despawn_margin.map(|v| v.to_string()));
+        for layer in &self.no_clip {
+            value("--no-clip", Some(layer.to_string()));
+        }
+        for target in &self.target_counts {
+            value("--target-count", Some(target.to_string()));
+        }
+        for sink in &self.sinks {
+            value("--sink", Some(sink.to_string()));
+        }
+        for path in &self.stamps {
+            value("--stamp", Some(path.display().to_string()));
+        }
+        for colors in &self.walls {
+            value("--wall", Some(colors.to_string()));
+        }
+        for colors in &self.windows {
+            value("--windows", Some(colors.to_string()));
+        }
+        value("--stamp-chance", self.stamp_chance.map(|v| v.to_string()));
+        value("--sprite", self.sprite.as_ref().map(|path| path.display().to_string()));
+        value("--loop-period", self.loop_period.map(|v| v.to_string()));
+        value("--layout", self.layout.map(|v| v.to_string()));
+        value("--status-segments", self.status_segments.as_ref().map(|v| v.to_string()));
+        value("--write-chunk", self.write_chunk.map(|v| v.to_string()));
+        value("--demo-time", self.demo_time.map(|v| v.to_string()));
+        value("--scenario", self.scenario.as_ref().map(|v| v.display().to_string()));
+        value("--colors", self.colors.map(|v| v.to_string()));
+        value("--pbm", self.pbm.as_ref().map(|v| v.display().to_string()));
+        value("--pbm-scale", self.pbm_scale.map(|v| v.to_string()));
+        value("--pbm-ink", self.pbm_ink.as_ref().map(|v| v.to_string()));
+        value("--from-bookmark", self.from_bookmark.map(|v| v.to_string()));
+
+        let flags = [
+            ("--auto-size", self.auto_size),
+            ("--layer-seeds", self.layer_seeds),
+            ("--no-title", self.title_style == Some(TitleStyle::Hidden)),
+            ("--big-title", self.title_style == Some(TitleStyle::Big)),
+            ("--no-center", self.no_center),
+            ("--center-vertical", self.center_vertical),
+            ("--one-cycle", self.one_cycle),
+            ("--power-save", self.power_save),
+            ("--show-elapsed", self.show_elapsed),
+            ("--minimap", self.minimap),
+            ("--sixel", self.sixel == Some(true)),
+            ("--no-sixel", self.sixel == Some(false)),
+            ("--demo", self.demo),
+            ("--no-bce", self.no_bce),
+            ("--no-stats", self.no_stats),
+            ("--outro", self.outro == Some(true)),
+            ("--no-outro", self.outro == Some(false)),
+            ("--palette-cycle-lit", self.palette_cycle_lit),
+            ("--mirror", self.mirror),
+            ("--calm", self.calm),
+            ("--rising", self.rising),
+            ("--dither", self.dither),
+            ("--legacy-spawn", self.legacy_spawn),
+            ("--debug-grid", self.debug_grid),
+        ];
+        args.extend(flags.iter().filter(|(_, on)| *on).map(|(name, _)| name.to_string()));
+        args
+    }
+
+    /// Shell command running the program with `to_args`
+    pub fn cmdline(&self) -> String {
+        let program = env::args().next().unwrap_or_else(|| "city".to_string());
+        iter::once(program).chain(self.to_args()).map(|a| shell_quote(&a)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./,:=+".contains(c);
+    match !arg.is_empty() && arg.chars().all(plain) {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+const HELP: &str = r#"
+Usage: city [options] [width] [height]
+-f, --fps <n>
+        Set target fps (default: 60)
+--fps-ramp <seconds>
+        Start at 5 fps and ease up to the target fps over this many seconds, the city moves
+        at the same speed meanwhile. 0 starts at the target (default)
+--speed <cells/sec>
+        Scroll the fastest layers this many cells a second, whatever the fps (default: the fps).
+        Takes the place of the step when both are given
+-t, --step <n>
+        Set step per frame (default: 1). Deprecated: the speed it gives changes with the fps,
+        use --speed
+-s, --seed <n>
+        Custom seed
+--seed-from-file <path>
+        Use the seed stored in a file and store the next one for the next run (created from the time if missing)
+--daily
+        Use the seed of the current UTC date, everyone gets the same city on the same day
+--daily-offset <days>
+        Move the date of --daily this many days forward, back if negative
+-a, --auto-size
+        Use terminal size (auto-size)
+--no-auto-size
+        Don't, when the config or CITY_AUTOSIZE asks for it
+--width <n>, --height <n>
+        Same as [width] and [height]
+--world-width <n>
+        Let buildings travel across a world this wide, the canvas shows a part of it
+--view-x <n>
+        World column at the left canvas edge (default: canvas at the right end of the world)
+--shard <i>/<n>
+        Show part i (from 1 at the left) of a city n terminals wide, to span terminals placed
+        side by side. Every part is its own process started with the same --shard-seed, size,
+        step, fps and layer options, and they stay the same city as long as all of them keep
+        up with the fps. Resizing one of them breaks the seams until all have the same size
+        again. Replaces --world-width and --view-x, mirrored and rising cities can't be split
+--shard-seed <n>
+        Seed of the city split with --shard, spawns are drawn from it and the tick only
+--start-at <unix time>
+        Wait until this time after warmup before showing the first frame, so the parts of a
+        city split with --shard start together, e.g. --start-at $(($(date +%s) + 5))
+--calm
+        Reduce motion: layers scroll at most a cell per second whatever the step and fps, stars
+        don't twinkle or shoot and --palette-cycle changes window lights 20 times slower.
+        Also on when CITY_CALM=1 is set
+--motion-blur <0..1>
+        Blend cells that changed with the previous frame by this weight, so moving edges leave
+        a fading trail. Smooths low fps playback, 0 turns it off (default)
+--mirror
+        Simulate the left half of the canvas and mirror it onto the right half
+--sky <colors>
+        Fill the sky with bands of these colors from the top down, e.g. 44,104,103
+--sky-drift <ticks>
+        Move the sky bands down a row every N ticks, up if negative (default: 0, still)
+--dither
+        Blend neighboring sky bands and face gradient shades with an ordered dither instead of
+        changing color at once
+--horizon <row>,<color>
+        Paint a row of the sky this many rows above the ground in a color, e.g. 6,103
+--stars <count>[,<chance>]
+        Scatter stars over the upper sky, with this chance of a shooting star crossing it at
+        each tick (default: 0.001), e.g. 80,0.005
+--debug-grid
+        Draw rulers over the buildings every 10 cells, labeled with canvas columns and rows
+        counted from the ground
+--grid <spacing>,<color>
+        Spacing and color of the --debug-grid rulers, implies it (default: 10,100)
+--rising
+        Let buildings rise from the ground in place, stand for a while and sink, instead of scrolling
+--legacy-spawn
+        Spawn buildings as older versions did, so their seeds show the same city (layers thin out with bigger steps)
+--layer-seeds
+        Give each layer its own random stream derived from the seed
+--resize-delay <ms>
+        Apply auto-size changes only after the size is stable for this long (default: 150)
+--margin <top,right,bottom,left>
+        Keep terminal cells free around the canvas
+--title <text>
+        Custom title text
+--no-title
+        Don't show the title
+--big-title
+        Show the title in big block letters if it fits
+--window-glyph <char>
+        Draw windows as this glyph over the wall instead of colored cells (e.g. ▪)
+--wall <layer>=<colors>
+        Wall colors of a layer by index from the back, e.g. 0=47,100 (repeatable)
+--windows <layer>=<colors>
+        Window colors of a layer, like --wall, also wall, wall-light or wall-dark for shades of
+        the wall of each building. Codes are listed by --list-colors
+--age-fade <0..1>
+        Turn off more windows the older (further left) buildings are
+--face-gradient <-1..1>
+        Shade walls from lighter at the top to darker at the base with 24-bit colors, negative
+        values flip it. Other terminals get the nearest colors (default: 0, flat walls)
+--palette-cycle <seconds>
+        Rotate window colors every N seconds, 0 keeps them as they are (default)
+--palette-cycle-lit
+        Keep window colors that are also wall or background colors out of the cycle
+--spawn-jitter <n>
+        Spawn buildings up to N cells beyond the right edge to stagger their entry
+--grow-ticks <n>
+        Let new buildings rise from the ground over N ticks, 0 shows them at full height (default)
+--despawn-margin <n>
+        Keep buildings until they're N cells past the left edge instead of dropping them as
+        they leave it, 0 by default
+--no-clip <layer>
+        Keep buildings of a layer (by index from the back) within the screen height instead of
+        letting them clip off the top (repeatable)
+--target-count <layer>=<n>
+        Spawn buildings on a layer as often as it takes to keep about N of them, instead of by
+        its density (repeatable)
+--sink <layer>=<n>
+        Stand the buildings of a layer N rows below the bottom, so only their tops show
+        (repeatable)
+--stamp <path>
+        Occasionally spawn this ASCII art building on the front layer ('#' wall, 'o' window,
+        space or '.' empty), can be given several times
+--stamp-chance <0..1>
+        Chance for a front layer building to be a stamp (default: 0.05)
+--sprite <path>
+        Scroll this text art across the sky over and over: settings like 'anchor top 2',
+        'layer front', 'speed 3' and 'color B 100', then the art under an 'art' line. See
+        sprites/blimp.txt
+--no-center
+        Don't center fixed size canvas in the terminal
+--center-vertical
+        Also center fixed size canvas vertically
+--one-cycle
+        Exit after the slowest layer scrolled one screen width past the first frame
+--power-save
+        Drop to 5 fps while the terminal isn't focused or the picture stays the same for
+        2 seconds, the city keeps its speed
+--profile <seconds>
+        Exit after this many seconds and print how long each phase of a frame took: mean,
+        median and 97th percentile, and the share of the frame time at the target fps
+--show-elapsed
+        Show frames drawn and time since start in the status line
+--status-segments <segments>
+        Comma separated status line segments, ';' starts the second line. Segments: tick,
+        tick-time, fps, frametime, target, frames, elapsed, clock (utc), buildings, phases
+        (sim, format and write time), bytes (written per frame)
+        (default: tick,tick-time,fps;frametime,target,phases)
+--loop-period <ticks>
+        Spawn buildings as a pure function of seed and tick modulo this period, so the city
+        repeats exactly every N ticks. Looks different from the normal mode for the same seed.
+        Must be a multiple of every layer speed (12 for the default layers)
+--layout <title-top|status-top|title-bottom>
+        Put the status above the canvas or the title below it (default: title-top)
+--no-bce
+        Print blank cells instead of erasing them, for terminals that erase to the default
+        background (detected for TERM=screen*)
+--demo
+        Switch through a fixed sequence of effects with a new seed each time
+--demo-time <seconds>
+        Time each demo scene is shown (default: 8)
+--scenario <path>
+        Run commands from a file at set times, one per line as <seconds> <command>, e.g.
+        '20 scene dusk'. Commands: scene <demo scene name>, sky <colors>, fps <n>, pause,
+        resume, layer <n> (hides or shows it), screenshot [path] (as the 's' key does, or a PBM
+        like --pbm at the path), bookmark, inspector, stop
+--control <path>
+        Take commands on a Unix socket, one per line as --scenario names them without the time,
+        or quit. Each line is answered with 'ok' or 'error: <why>'
+--control-force
+        Replace the file at the --control path instead of refusing to start
+--metrics-socket <path>
+        Send a line of JSON with the tick, frame times, fps and building counts every frame
+        to everyone connected to this Unix socket, e.g. with 'nc -U <path>'. Lines a client
+        doesn't keep up with are dropped
+--metrics-force
+        Replace the file at the --metrics-socket path instead of refusing to start
+--colors <16|256|truecolor>
+        Colors of the terminal, RGB colors are shown as the nearest ones it has (default: from
+        COLORTERM and TERM)
+--sixel, --no-sixel
+        Draw the canvas as a sixel image instead of colored cells, the default is on for
+        terminals known to support it (foot, mlterm)
+--write-chunk <bytes>
+        Write frames out in pieces of about this size, 0 writes each frame at once (default: 65536)
+--minimap
+        Show a small silhouette of the skyline instead of the full canvas
+--pbm <path>
+        Write one frame as a black and white PBM image and exit
+--pbm-scale <n>
+        Pixels per cell side in the PBM (default: 1)
+--screenshot-dir <path>
+        Directory the 's' key saves the frame on screen to, as a timestamped .ans file to print
+        with cat (default: current directory)
+--screenshot-pbm
+        Save screenshots as a PBM too, with --pbm-scale and --pbm-ink
+--export-ans <path>
+        Write one frame as ANSI art to print with cat and exit
+--check-ans <path>
+        Read back a file written by --export-ans or a screenshot, print its size and exit
+--heatmap <path>
+        Run the city without drawing it and write a gray PNG with a pixel per cell, brighter
+        the more often buildings covered the cell, then exit. For tuning layer options
+--heatmap-ticks <n>
+        Ticks --heatmap counts over (default: 1000)
+--panorama <path>
+        Let the city fill a world --panorama-width cells wide, write all of it as one wide PNG
+        with cells two pixels tall and exit
+--panorama-width <n>
+        Width of the --panorama world in cells (default: 1000)
+--fit <w>x<h>
+        Pick the width and height so the PBM is this many pixels at --pbm-scale
+--pbm-ink <colors>
+        Comma separated color codes drawn black in the PBM (default: all but the background)
+--oneline <width>
+        Print the colors of the skyline as one row this wide and exit. Each run continues
+        the city of the previous one, saved in the state directory, for status bars
+--oneline-tmux
+        Print --oneline colors as a tmux status string instead of escape codes
+--config <path>
+        Load options from this file instead of the default config
+--no-config
+        Don't load the default config
+--bookmark-list
+        Print bookmarks saved with 'b' key and exit
+--from-bookmark <n>
+        Relaunch bookmark number N from --bookmark-list
+--list-colors
+        Print the color codes usable for walls and windows and exit
+--selftest
+        Run internal checks without touching the terminal and exit
+--print-cmdline
+        Print a command reproducing the settings at start, it's also printed on exit
+--no-stats
+        Don't print render times and the reproducing command on exit
+--outro, --no-outro
+        Wind the city down for a moment on quit, turning lights off and fading the buildings
+        out. Quitting again skips it. Off by default, never shown with --calm, while paused
+        or when not writing to a terminal
+
+Options are also read from config.toml in the config directory
+($XDG_CONFIG_HOME/city on linux) as `name = value` lines, where name is
+a long option name without dashes and switches take true/false.
+
+Environment variables override the config: CITY_FPS, CITY_SEED,
+CITY_SIZE (WxH) and CITY_AUTOSIZE (1 or 0).
+Command line options override both.
+
+CITY_WIDTH and CITY_HEIGHT only stand in for a size that isn't given
+otherwise, before the defaults, also when -a finds no terminal.
+"#;
+
+fn parse<T: FromStr>(arg: Option<String>, name: &str) -> Result<Option<T>, String>
+where <T as FromStr>::Err: fmt::Debug {
+    match arg.map(|a| a.parse()) {
+        Some(Ok(value)) => Ok(Some(value)),
+        Some(Err(e)) => Err(format!("Can't parse {} value: {:?}", name, e)),
+        None => Err(format!("Expected value for {}", name)),
+    }
+}
+
+/// Like `parse`, for counts that can't be 0
+fn parse_positive(arg: Option<String>, name: &str) -> Result<Option<u32>, String> {
+    match parse(arg, name)? {
+        Some(0) => Err(format!("{} must be at least 1", name)),
+        v => Ok(v),
+    }
+}
+
+/// Applies options in order, so later ones override earlier ones
+fn parse_into(opts: &mut Opts, args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let pos_names = ["[width]", "[height]"];
+    let mut pos_i = 0;
+    let mut args = args.into_iter();
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-h" | "--help" => {
+                println!("{}", HELP);
+                exit(0);
+            }
+            "-f" | "--fps" => opts.fps = parse(args.next(), &a)?,
+            "--fps-ramp" => opts.fps_ramp = parse(args.next(), &a)?,
+            "-t" | "--step" => opts.step = parse_positive(args.next(), &a)?,
+            "--speed" => opts.speed = parse_positive(args.next(), &a)?,
+            "-s" | "--seed" => opts.seed = parse(args.next(), &a)?,
+            "--seed-from-file" => opts.seed_file = parse(args.next(), &a)?,
+            "--daily" => opts.daily = true,
+            "--daily-offset" => opts.daily_offset = parse(args.next(), &a)?,
+            "-a" | "--auto-size" => opts.auto_size = true,
+            "--no-auto-size" => opts.auto_size = false,
+            "--width" => opts.width = parse(args.next(), &a)?,
+            "--height" => opts.height = parse(args.next(), &a)?,
+            "--world-width" => opts.world_width = parse(args.next(), &a)?,
+            "--view-x" => opts.view_x = parse(args.next(), &a)?,
+            "--shard" => opts.shard = parse(args.next(), &a)?,
+            "--shard-seed" => opts.shard_seed = parse(args.next(), &a)?,
+            "--start-at" => opts.start_at = parse(args.next(), &a)?,
+            "--layer-seeds" => opts.layer_seeds = true,
+            "--resize-delay" => opts.resize_delay = parse(args.next(), &a)?,
+            "--margin" => opts.margins = parse(args.next(), &a)?,
+            "--title" => opts.title = parse(args.next(), &a)?,
+            "--no-title" => opts.title_style = Some(TitleStyle::Hidden),
+            "--big-title" => opts.title_style = Some(TitleStyle::Big),
+            "--window-glyph" => opts.window_glyph = parse(args.next(), &a)?,
+            "--age-fade" => opts.age_fade = parse(args.next(), &a)?,
+            "--face-gradient" => opts.face_gradient = parse(args.next(), &a)?,
+            "--palette-cycle" => opts.palette_cycle = parse(args.next(), &a)?,
+            "--palette-cycle-lit" => opts.palette_cycle_lit = true,
+            "--mirror" => opts.mirror = true,
+            "--calm" => opts.calm = true,
+            "--motion-blur" => opts.motion_blur = parse(args.next(), &a)?,
+            "--rising" => opts.rising = true,
+            "--legacy-spawn" => opts.legacy_spawn = true,
+            "--horizon" => opts.horizon = parse(args.next(), &a)?,
+            "--stars" => opts.stars = parse(args.next(), &a)?,
+            "--debug-grid" => opts.debug_grid = true,
+            "--grid" => opts.grid = parse(args.next(), &a)?,
+            "--sky" => opts.sky = parse(args.next(), &a)?,
+            "--sky-drift" => opts.sky_drift = parse(args.next(), &a)?,
+            "--dither" => opts.dither = true,
+            "--wall" => opts.walls.extend(parse(args.next(), &a)?),
+            "--windows" => opts.windows.extend(parse(args.next(), &a)?),
+            "--spawn-jitter" => opts.spawn_jitter = parse(args.next(), &a)?,
+            "--grow-ticks" => opts.grow_ticks = parse(args.next(), &a)?,
+            "--despawn-margin" => opts.despawn_margin = parse(args.next(), &a)?,
+            "--no-clip" => opts.no_clip.extend(parse::<usize>(args.next(), &a)?),
+            "--target-count" => opts.target_counts.extend(parse::<LayerNumber>(args.next(), &a)?),
+            "--sink" => opts.sinks.extend(parse::<LayerNumber>(args.next(), &a)?),
+            "--stamp" => opts.stamps.extend(parse(args.next(), &a)?),
+            "--stamp-chance" => opts.stamp_chance = parse(args.next(), &a)?,
+            "--sprite" => opts.sprite = parse(args.next(), &a)?,
+            "--no-center" => opts.no_center = true,
+            "--center-vertical" => opts.center_vertical = true,
+            "--one-cycle" => opts.one_cycle = true,
+            "--profile" => opts.profile = parse(args.next(), &a)?,
+            "--power-save" => opts.power_save = true,
+            "--show-elapsed" => opts.show_elapsed = true,
+            "--status-segments" => opts.status_segments = parse(args.next(), &a)?,
+            "--minimap" => opts.minimap = true,
+            "--colors" => opts.colors = parse(args.next(), &a)?,
+            "--sixel" => opts.sixel = Some(true),
+            "--no-sixel" => opts.sixel = Some(false),
+            "--demo" => opts.demo = true,
+            "--demo-time" => opts.demo_time = parse(args.next(), &a)?,
+            "--scenario" => opts.scenario = parse(args.next(), &a)?,
+            "--control" => opts.control = parse(args.next(), &a)?,
+            "--control-force" => opts.control_force = true,
+            "--metrics-socket" => opts.metrics_socket = parse(args.next(), &a)?,
+            "--metrics-force" => opts.metrics_force = true,
+            "--no-bce" => opts.no_bce = true,
+            "--write-chunk" => opts.write_chunk = parse(args.next(), &a)?,
+            "--layout" => opts.layout = parse(args.next(), &a)?,
+            "--pbm" => opts.pbm = parse(args.next(), &a)?,
+            "--pbm-scale" => opts.pbm_scale = parse(args.next(), &a)?,
+            "--pbm-ink" => opts.pbm_ink = parse(args.next(), &a)?,
+            "--screenshot-dir" => opts.screenshot_dir = parse(args.next(), &a)?,
+            "--screenshot-pbm" => opts.screenshot_pbm = true,
+            "--export-ans" => opts.export_ans = parse(args.next(), &a)?,
+            "--check-ans" => opts.check_ans = parse(args.next(), &a)?,
+            "--heatmap" => opts.heatmap = parse(args.next(), &a)?,
+            "--heatmap-ticks" => opts.heatmap_ticks = parse(args.next(), &a)?,
+            "--panorama" => opts.panorama = parse(args.next(), &a)?,
+            "--panorama-width" => opts.panorama_width = parse(args.next(), &a)?,
+            "--fit" => opts.fit = parse(args.next(), &a)?,
+            "--loop-period" => opts.loop_period = parse(args.next(), &a)?,
+            "--config" => opts.config = parse(args.next(), &a)?,
+            "--no-config" => opts.no_config = true,
+            "--bookmark-list" => opts.bookmark_list = true,
+            "--list-colors" => opts.list_colors = true,
+            "--from-bookmark" => opts.from_bookmark = parse(args.next(), &a)?,
+            "--selftest" => opts.selftest = true,
+            "--print-cmdline" => opts.print_cmdline = true,
+            "--no-stats" => opts.no_stats = true,
+            "--outro" => opts.outro = Some(true),
+            "--no-outro" => opts.outro = Some(false),
+            "--oneline" => opts.oneline = parse(args.next(), &a)?,
+            "--oneline-tmux" => opts.oneline_tmux = true,
+            u if u.starts_with('-') => return Err(format!("Unknown arg {}", u)),
+            pos => {
+                match pos_names.get(pos_i) {
+                    Some(&name) if pos_i == 0 => opts.width = parse(Some(pos.to_string()), name)?,
+                    Some(&name) => opts.height = parse(Some(pos.to_string()), name)?,
+                    None => return Err(format!("Unknown arg at position {}", pos_i + 1)),
+                }
+                pos_i += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Option arguments from `CITY_*` environment variables, paired with the variable name
+fn env_args(env: &dyn Fn(&str) -> Option<String>) -> Result<Vec<(&'static str, Vec<String>)>, String> {
+    let mut args = Vec::new();
+    let value_opts = [("CITY_FPS", "--fps"), ("CITY_SEED", "--seed")];
+
+    for &(var, option) in value_opts.iter() {
+        if let Some(v) = env(var) {
+            args.push((var, vec![option.to_string(), v]));
+        }
+    }
+
+    if let Some(v) = env("CITY_SIZE") {
+        let mut wh = v.splitn(2, 'x');
+        match (wh.next(), wh.next()) {
+            (Some(w), Some(h)) => args.push(("CITY_SIZE", vec![
+                "--width".to_string(), w.to_string(), "--height".to_string(), h.to_string()])),
+            _ => return Err(format!("CITY_SIZE: expected WxH, got '{}'", v)),
+        }
+    }
+
+    if let Some(v) = env("CITY_AUTOSIZE") {
+        match v.as_str() {
+            "1" | "true" | "yes" => args.push(("CITY_AUTOSIZE", vec!["--auto-size".to_string()])),
+            "0" | "false" | "no" => args.push(("CITY_AUTOSIZE", vec!["--no-auto-size".to_string()])),
+            "" => {}
+            _ => return Err(format!("CITY_AUTOSIZE: expected 1 or 0, got '{}'", v)),
+        }
+    }
+
+    Ok(args)
+}
+
+/// Canvas width and height from `CITY_WIDTH` and `CITY_HEIGHT`, used by `main` where no
+/// size is given and none can be taken from a terminal
+pub fn env_size(env: &dyn Fn(&str) -> Option<String>) -> Result<(Option<usize>, Option<usize>), String> {
+    let var = |name| env(name).filter(|v| !v.is_empty()).map(|v| {
+        v.trim().parse().map_err(|e| format!("{}: expected a number of cells, got '{}': {}", name, v, e))
+    }).transpose();
+    Ok((var("CITY_WIDTH")?, var("CITY_HEIGHT")?))
+}
+
+/// Builds options from defaults, then the config file, then environment, then command line
+fn resolve(args: Vec<String>, env: &dyn Fn(&str) -> Option<String>) -> Result<Opts, String> {
+    // config selection has to be known before everything else
+    let mut cli = Opts::default();
+    parse_into(&mut cli, args.iter().cloned())?;
+
+    let mut opts = Opts::default();
+    let config_path = match (cli.config, cli.no_config) {
+        (Some(path), _) => Some((path, true)),
+        (None, false) => config::default_path().map(|path| (path, false)),
+        (None, true) => None,
+    };
+
+    if let Some((path, explicit)) = config_path {
+        let lines = match config::load(&path) {
+            Ok(lines) => lines,
+            Err(e) if !explicit && e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(format!("Can't read config {}: {}", path.display(), e)),
+        };
+
+        for (line_no, tokens) in lines {
+            parse_into(&mut opts, tokens)
+                .map_err(|e| format!("Invalid config {}:{}: {}", path.display(), line_no, e))?;
+        }
+    }
+
+    for (var, tokens) in env_args(env)? {
+        parse_into(&mut opts, tokens).map_err(|e| format!("{}: {}", var, e))?;
+    }
+
+    parse_into(&mut opts, args)?;
+    Ok(opts)
+}
+
+pub fn parse_args() -> Opts {
+    let args = env::args().skip(1).collect();
+    resolve(args, &|var| env::var(var).ok()).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn resolve_with(args: &[&str], env: &[(&str, &str)]) -> Result<Opts, String> {
+        let env: HashMap<String, String> = env.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        if !args.iter().any(|a| a == "--config") {
+            args.push("--no-config".to_string());
+        }
+        resolve(args, &|var| env.get(var).cloned())
+    }
+
+    #[test]
+    fn env_overrides_config() {
+        let path = env::temp_dir().join(format!("city-test-config-{}.toml", std::process::id()));
+        fs::write(&path, "fps = 30\nseed = 5\n").unwrap();
+        let config = path.to_str().unwrap();
+
+        let opts = resolve_with(&["--config", config], &[("CITY_FPS", "40")]);
+        fs::remove_file(&path).unwrap();
+
+        let opts = opts.unwrap();
+        assert_eq!(opts.fps, Some(40));
+        assert_eq!(opts.seed, Some(5));
+    }
+
+    #[test]
+    fn cli_overrides_env() {
+        let env = [("CITY_FPS", "40"), ("CITY_SEED", "7"), ("CITY_SIZE", "100x30")];
+        let opts = resolve_with(&["-f", "50", "120"], &env).unwrap();
+
+        assert_eq!(opts.fps, Some(50));
+        assert_eq!(opts.seed, Some(7));
+        assert_eq!(opts.width, Some(120));
+        assert_eq!(opts.height, Some(30));
+    }
+
+    #[test]
+    fn env_size_is_a_fallback() {
+        let env = [("CITY_WIDTH", "90"), ("CITY_HEIGHT", " 25 ")];
+        let size = |env: &HashMap<&str, &str>| env_size(&|var| env.get(var).map(|v| v.to_string()));
+        assert_eq!(size(&HashMap::from(env)), Ok((Some(90), Some(25))));
+        assert_eq!(size(&HashMap::from([("CITY_HEIGHT", "")])), Ok((None, None)));
+        assert!(size(&HashMap::from([("CITY_WIDTH", "wide")])).unwrap_err().contains("CITY_WIDTH"));
+
+        // options don't see them, main only falls back to them
+        assert_eq!(resolve_with(&[], &env).unwrap().width, None);
+    }
+
+    #[test]
+    fn env_autosize() {
+        assert!(resolve_with(&[], &[("CITY_AUTOSIZE", "1")]).unwrap().auto_size);
+        assert!(!resolve_with(&[], &[("CITY_AUTOSIZE", "0")]).unwrap().auto_size);
+        assert!(!resolve_with(&[], &[]).unwrap().auto_size);
+    }
+
+    #[test]
+    fn autosize_can_be_turned_off() {
+        let path = env::temp_dir().join(format!("city-test-autosize-{}.toml", std::process::id()));
+        fs::write(&path, "auto-size = true\n").unwrap();
+        let config = path.to_str().unwrap();
+
+        let from_config = resolve_with(&["--config", config], &[]);
+        let env_off = resolve_with(&["--config", config], &[("CITY_AUTOSIZE", "0")]);
+        let cli_off = resolve_with(&["--config", config, "--no-auto-size"], &[]);
+        let cli_on = resolve_with(&["--config", config, "-a"], &[("CITY_AUTOSIZE", "no")]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(from_config.unwrap().auto_size);
+        assert!(!env_off.unwrap().auto_size);
+        assert!(!cli_off.unwrap().auto_size);
+        assert!(cli_on.unwrap().auto_size);
+    }
+
+    #[test]
+    fn args_reproduce_opts() {
+        let env = [("CITY_SEED", "7"), ("CITY_SIZE", "100x30")];
+        let opts = resolve_with(&["-f", "50", "--title", "it's a city", "--big-title", "--stamp", "a.txt",
+                                  "--stamp", "b.txt", "--margin", "1,2,3,4", "--layout", "status-top",
+                                  "--pbm-ink", "40,47", "--no-sixel", "--age-fade", "0.25", "--motion-blur", "0.5",
+                                  "--wall", "0=47, 100", "--windows", "2=40,107"], &env).unwrap();
+        let args = opts.to_args();
+        let again = resolve_with(&args.iter().map(|a| a.as_str()).collect::<Vec<_>>(), &[]).unwrap();
+
+        assert_eq!(again.to_args(), args);
+        assert_eq!((again.seed, again.width, again.height), (Some(7), Some(100), Some(30)));
+        assert_eq!(again.stamps.len(), 2);
+        assert_eq!((&again.walls, &again.windows), (&opts.walls, &opts.windows));
+        assert!(opts.cmdline().contains(" --title 'it'\\''s a city' "));
+    }
+
+    #[test]
+    fn layer_colors_errors() {
+        for arg in ["47,100", "x=47", "0=47,", "0=47,30", "0=256"] {
+            assert!(resolve_with(&["--wall", arg], &[]).is_err(), "{} accepted", arg);
+        }
+        let shade = resolve_with(&["--wall", "0=47,wall-light"], &[]).unwrap_err();
+        assert!(shade.contains("walls can't be colored wall-light"), "{}", shade);
+        assert!(resolve_with(&["--windows", "0=47,wall-light"], &[]).is_ok());
+    }
+
+    #[test]
+    fn zero_step_and_speed_rejected() {
+        assert_eq!(resolve_with(&["-t", "0"], &[]).unwrap_err(), "-t must be at least 1");
+        assert_eq!(resolve_with(&["--speed", "0"], &[]).unwrap_err(), "--speed must be at least 1");
+        assert_eq!(resolve_with(&["--step", "2"], &[]).unwrap().step, Some(2));
+    }
+
+    #[test]
+    fn env_errors_name_variable() {
+        let cases = [("CITY_SEED", "abc"), ("CITY_FPS", "-1"), ("CITY_SIZE", "100"),
+                     ("CITY_SIZE", "100xabc"), ("CITY_AUTOSIZE", "maybe")];
+
+        for &(var, value) in cases.iter() {
+            let err = resolve_with(&[], &[(var, value)]).unwrap_err();
+            assert!(err.starts_with(var), "{} not named in '{}'", var, err);
+        }
+    }
+}
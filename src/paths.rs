@@ -0,0 +1,71 @@
+use std::env;
+use std::path::PathBuf;
+
+fn env_dir(var: &str) -> Option<PathBuf> {
+    match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    env_dir(var).or_else(|| env_dir("HOME").map(|home| home.join(home_fallback)))
+}
+
+/// `$XDG_DATA_HOME/city`, `~/.local/share/city` if unset
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share").map(|d| d.join("city"))
+}
+
+/// `$XDG_CONFIG_HOME/city`, `~/.config/city` if unset
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|d| d.join("city"))
+}
+
+/// `$XDG_STATE_HOME/city`, `~/.local/state/city` if unset
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state").map(|d| d.join("city"))
+}
+
+/// `~/Library/Application Support/city`, XDG variables are still honored if set
+#[cfg(target_os = "macos")]
+pub fn data_dir() -> Option<PathBuf> {
+    env_dir("XDG_DATA_HOME")
+        .or_else(|| env_dir("HOME").map(|home| home.join("Library/Application Support")))
+        .map(|d| d.join("city"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn config_dir() -> Option<PathBuf> {
+    env_dir("XDG_CONFIG_HOME")
+        .or_else(|| env_dir("HOME").map(|home| home.join("Library/Application Support")))
+        .map(|d| d.join("city"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn state_dir() -> Option<PathBuf> {
+    env_dir("XDG_STATE_HOME")
+        .or_else(|| env_dir("HOME").map(|home| home.join("Library/Application Support")))
+        .map(|d| d.join("city"))
+}
+
+/// `%APPDATA%\city`
+#[cfg(windows)]
+pub fn data_dir() -> Option<PathBuf> {
+    env_dir("APPDATA").map(|d| d.join("city"))
+}
+
+#[cfg(windows)]
+pub fn config_dir() -> Option<PathBuf> {
+    env_dir("APPDATA").map(|d| d.join("city"))
+}
+
+/// `%LOCALAPPDATA%\city`
+#[cfg(windows)]
+pub fn state_dir() -> Option<PathBuf> {
+    env_dir("LOCALAPPDATA").map(|d| d.join("city"))
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::city::{Cell, PaletteColor};
+use crate::vec2d::Vec2D;
+
+/// Which cells are black in the bitmap
+#[derive(Debug, Clone, Default)]
+pub enum Ink {
+    #[default]
+    NotBackground,
+    Colors(Vec<PaletteColor>),
+}
+
+impl Ink {
+    fn is_ink(&self, cell: &Cell, background: PaletteColor) -> bool {
+        match self {
+            Ink::NotBackground => cell.bg != background,
+            Ink::Colors(colors) => colors.contains(&cell.bg),
+        }
+    }
+}
+
+impl FromStr for Ink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|v| v.trim().parse::<PaletteColor>().map_err(|e| format!("{}: {}", v, e)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Ink::Colors)
+    }
+}
+
+/// Comma list of colors as parsed by FromStr, empty for NotBackground
+impl fmt::Display for Ink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ink::NotBackground => Ok(()),
+            Ink::Colors(colors) => {
+                let list: Vec<String> = colors.iter().map(|c| c.to_string()).collect();
+                f.write_str(&list.join(","))
+            }
+        }
+    }
+}
+
+/// Image size in pixels, parsed from and shown as WxH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelSize {
+    pub w: usize,
+    pub h: usize,
+}
+
+impl PixelSize {
+    /// Canvas size in cells giving exactly this image with `scale` pixels per cell side
+    pub fn cells(self, scale: usize) -> Result<(usize, usize), String> {
+        if scale == 0 {
+            return Err("scale must be at least 1".to_string());
+        }
+        if !self.w.is_multiple_of(scale) || !self.h.is_multiple_of(scale) {
+            return Err(format!("{} isn't made of whole {}x{} cells, try {}x{}", self, scale, scale,
+                               self.w / scale * scale, self.h / scale * scale));
+        }
+        Ok((self.w / scale, self.h / scale))
+    }
+}
+
+impl FromStr for PixelSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (w, h) = s.split_once('x').ok_or_else(|| format!("expected WxH, got '{}'", s))?;
+        let side = |v: &str| match v.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("expected a positive number of pixels, got '{}'", v)),
+            Ok(v) => Ok(v),
+        };
+        Ok(PixelSize { w: side(w)?, h: side(h)? })
+    }
+}
+
+impl fmt::Display for PixelSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.w, self.h)
+    }
+}
+
+/// Writes a canvas as a binary (P4) PBM, `scale` pixels per cell side
+pub fn write(canvas: &Vec2D<Cell>, background: PaletteColor, path: &Path, scale: usize, ink: &Ink)
+             -> io::Result<()> {
+    let (_, (w, h)) = canvas.as_flat_slice();
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P4\n{} {}\n", w * scale, h * scale)?;
+
+    let mut bits = vec![0u8; (w * scale).div_ceil(8)];
+    for row in canvas.row_iter() {
+        bits.iter_mut().for_each(|b| *b = 0);
+        for (x, cell) in row.iter().enumerate() {
+            if ink.is_ink(cell, background) {
+                for px in x * scale..(x + 1) * scale {
+                    bits[px / 8] |= 0x80 >> (px % 8);
+                }
+            }
+        }
+        for _ in 0..scale {
+            out.write_all(&bits)?;
+        }
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_pixel_size() {
+        let full_hd: PixelSize = "1920x1080".parse().unwrap();
+        assert_eq!(full_hd.to_string(), "1920x1080");
+        assert_eq!(full_hd.cells(1), Ok((1920, 1080)));
+        assert_eq!(full_hd.cells(8), Ok((240, 135)));
+        assert_eq!(full_hd.cells(7).unwrap_err(), "1920x1080 isn't made of whole 7x7 cells, try 1918x1078");
+        assert!(full_hd.cells(0).is_err());
+
+        for bad in ["1920", "0x1080", "1920x", "axb", "-1x2"] {
+            assert!(bad.parse::<PixelSize>().is_err(), "{} accepted", bad);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const STORED_BLOCK_MAX: usize = 65535;
+
+/// Writes 8-bit gray `pixels`, rows from the top, as a PNG of `w` x `h`. Image data is
+/// stored without compression, which keeps the encoder small
+pub fn write_gray(path: &Path, size: (usize, usize), pixels: &[u8]) -> io::Result<()> {
+    write(path, size, (0, 1), pixels)
+}
+
+/// Writes RGB `pixels` like `write_gray`
+pub fn write_rgb(path: &Path, size: (usize, usize), pixels: &[(u8, u8, u8)]) -> io::Result<()> {
+    let bytes: Vec<u8> = pixels.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+    write(path, size, (2, 3), &bytes)
+}
+
+/// `color` is the PNG color type and its bytes per pixel
+fn write(path: &Path, (w, h): (usize, usize), (color, px_bytes): (u8, usize), data: &[u8]) -> io::Result<()> {
+    assert!(w > 0 && h > 0 && data.len() == w * h * px_bytes, "Pixels don't match a {}x{} image", w, h);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(w as u32).to_be_bytes());
+    header.extend_from_slice(&(h as u32).to_be_bytes());
+    header.extend_from_slice(&[8, color, 0, 0, 0]); // bit depth, color type, deflate, filters, no interlace
+
+    // each row starts with its filter type, 0 for none
+    let mut rows = Vec::with_capacity((w * px_bytes + 1) * h);
+    for row in data.chunks_exact(w * px_bytes) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(SIGNATURE)?;
+    write_chunk(&mut out, b"IHDR", &header)?;
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&rows))?;
+    write_chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+/// Zlib stream of `data` in stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut z = vec![0x78, 0x01];
+    let mut blocks: Vec<&[u8]> = data.chunks(STORED_BLOCK_MAX).collect();
+    if blocks.is_empty() {
+        blocks.push(&[]);
+    }
+    let last = blocks.len() - 1;
+    for (i, block) in blocks.into_iter().enumerate() {
+        let len = block.len() as u16;
+        z.push((i == last) as u8); // final block flag, stored type
+        z.extend_from_slice(&len.to_le_bytes());
+        z.extend_from_slice(&(!len).to_le_bytes());
+        z.extend_from_slice(block);
+    }
+    z.extend_from_slice(&adler32(data).to_be_bytes());
+    z
+}
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in parts.iter().flat_map(|p| p.iter()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_checksums_and_blocks() {
+        assert_eq!(crc32(&[b"IEND"]), 0xae426082);
+        assert_eq!(crc32(&[b"IE", b"ND"]), 0xae426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+
+        let data = vec![7u8; STORED_BLOCK_MAX + 10];
+        let z = zlib_stored(&data);
+        assert_eq!(z.len(), 2 + 2 * 5 + data.len() + 4);
+        assert_eq!(z[2], 0);
+        assert_eq!(z[2 + 5 + STORED_BLOCK_MAX], 1);
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+
+        let path = std::env::temp_dir().join(format!("city-png-test-{}.png", std::process::id()));
+        write_gray(&path, (3, 2), &[0, 128, 255, 255, 128, 0]).unwrap();
+        let png = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let png = png.unwrap();
+        assert!(png.starts_with(SIGNATURE));
+        assert_eq!(png[12..16], *b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 3, 0, 0, 0, 2]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+
+        write_rgb(&path, (1, 1), &[(1, 2, 3)]).unwrap();
+        let png = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(png.unwrap()[24..26], [8, 2]);
+    }
+}
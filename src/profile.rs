@@ -0,0 +1,102 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Time the phases of drawn ticks took, summed over the ticks of a frame.
+/// Only taken while profiling, see `City::set_profiling`
+#[derive(Debug, Clone, Default)]
+pub struct TickTimes {
+    pub wipe: Duration,
+    pub effects: Duration, // and passes over the whole canvas after the layers, like mirroring
+    pub spawn: Duration,
+    pub layers: Vec<Duration>, // of drawing the buildings of each layer
+}
+
+/// Adds the time since the last lap to a phase of `TickTimes`, without times it does nothing.
+/// Whether it's timing is decided once when it's started
+pub struct PhaseClock<'t>(Option<(&'t mut TickTimes, Instant)>);
+
+impl<'t> PhaseClock<'t> {
+    pub fn start(times: Option<&'t mut TickTimes>, layers: usize) -> PhaseClock<'t> {
+        PhaseClock(times.map(|times| {
+            if times.layers.len() < layers {
+                times.layers.resize(layers, Duration::ZERO);
+            }
+            (times, Instant::now())
+        }))
+    }
+
+    pub fn lap(&mut self, phase: impl FnOnce(&mut TickTimes) -> &mut Duration) {
+        if let Some((times, last)) = self.0.as_mut() {
+            let now = Instant::now();
+            *phase(times) += now - *last;
+            *last = now;
+        }
+    }
+}
+
+/// Phase times of every frame of a --profile run, in microseconds
+#[derive(Debug, Default)]
+pub struct Profile {
+    phases: Vec<(String, Vec<u32>)>, // in the order they were first seen
+}
+
+impl Profile {
+    /// Adds a frame with the tick phases of `ticks` and the rest of the frame after them
+    pub fn add(&mut self, ticks: &TickTimes, format: Duration, write: Duration, sleep: Duration) {
+        let mut phases = vec![("wipe".to_string(), ticks.wipe), ("effects".to_string(), ticks.effects),
+                              ("spawn".to_string(), ticks.spawn)];
+        phases.extend(ticks.layers.iter().enumerate().map(|(i, &d)| (format!("layer {}", i), d)));
+        phases.extend(vec![("format".to_string(), format), ("write".to_string(), write), ("sleep".to_string(), sleep)]);
+        for (name, d) in phases {
+            let micros = d.as_micros().min(u32::MAX as u128) as u32;
+            match self.phases.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, samples)) => samples.push(micros),
+                None => self.phases.push((name, vec![micros])),
+            }
+        }
+    }
+
+    /// Table of the mean, median and 97th percentile of each phase, and the share of `budget`,
+    /// the target frame time, it takes on average
+    pub fn report(&self, budget: Duration) -> String {
+        let mut out = format!("{:<10}{:>10}{:>10}{:>10}{:>9}\n", "phase", "mean", "50th", "97th", "budget");
+        for (name, samples) in &self.phases {
+            let mut sorted = samples.clone();
+            sorted.sort_unstable();
+            let mean = sorted.iter().map(|&us| us as f64).sum::<f64>() / sorted.len().max(1) as f64;
+            let nth = |pct: usize| sorted.get(sorted.len() * pct / 100).copied().unwrap_or(0);
+            let share = mean / budget.as_micros().max(1) as f64 * 100.0;
+            writeln!(out, "{:<10}{:>8.0}us{:>8}us{:>8}us{:>8.1}%", name, mean, nth(50), nth(97), share).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_report_per_phase() {
+        let mut times = TickTimes::default();
+        let mut clock = PhaseClock::start(Some(&mut times), 2);
+        clock.lap(|t| &mut t.wipe);
+        clock.lap(|t| &mut t.layers[1]);
+        assert_eq!(times.layers.len(), 2);
+        PhaseClock::start(None, 2).lap(|t| &mut t.wipe);
+
+        let mut profile = Profile::default();
+        let ms = Duration::from_millis;
+        for i in 0..100 {
+            let ticks = TickTimes { spawn: ms(i % 2), layers: vec![ms(4)], ..TickTimes::default() };
+            profile.add(&ticks, ms(1), ms(2), ms(10));
+        }
+        let report = profile.report(ms(20));
+        let rows: Vec<Vec<&str>> = report.lines().map(|l| l.split_whitespace().collect()).collect();
+        let names: Vec<&str> = rows[1..].iter().map(|r| r[0]).collect();
+        assert_eq!(names, ["wipe", "effects", "spawn", "layer", "format", "write", "sleep"]);
+        assert_eq!(rows[3], ["spawn", "500us", "1000us", "1000us", "2.5%"]);
+        assert_eq!(rows[4], ["layer", "0", "4000us", "4000us", "4000us", "20.0%"]);
+        assert_eq!(rows[7], ["sleep", "10000us", "10000us", "10000us", "50.0%"]);
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::control::Command;
+
+/// Commands to run at set times, one per line as `<seconds> <command>`, e.g. `20 scene dusk`.
+/// Blank lines and ones starting with '#' are skipped
+#[derive(Debug)]
+pub struct Scenario {
+    actions: Vec<(Duration, Command)>,
+    next: usize,
+    clock: Duration, // target frame times summed, so actions land on the same frame however slow drawing is
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Scenario, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Scenario::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut actions = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |msg: String| format!("line {} '{}': {}", i + 1, line, msg);
+            let (at, command) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("expected <seconds> <command>".to_string()))?;
+            let at = match at.parse::<f64>() {
+                Ok(secs) if secs >= 0.0 && secs.is_finite() => Duration::from_secs_f64(secs),
+                _ => return Err(invalid(format!("bad time '{}'", at))),
+            };
+            actions.push((at, command.parse().map_err(invalid)?));
+        }
+
+        // stable, so commands given the same time run in file order
+        actions.sort_by_key(|&(at, _)| at);
+        Ok(Scenario { actions, next: 0, clock: Duration::ZERO })
+    }
+
+    /// Commands due on the frame about to be shown, then moves the clock past it
+    pub fn poll(&mut self, frame_time: Duration) -> Vec<Command> {
+        let due = self.actions[self.next..].iter().take_while(|(at, _)| *at <= self.clock).count();
+        let commands = self.actions[self.next..self.next + due].iter().map(|(_, c)| c.clone()).collect();
+        self.next += due;
+        self.clock += frame_time;
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_run_on_their_frame() {
+        let text = "# night, then dusk\n0 scene late night\n\n20 fps 30\n1.5 sky 44,104\n20 stop\n";
+        let mut s = Scenario::parse(text).unwrap();
+        let frame = Duration::from_millis(500);
+
+        assert_eq!(s.poll(frame), [Command::Scene(2)]);
+        assert_eq!(s.poll(frame), []);
+        assert_eq!(s.poll(frame), []);
+        assert_eq!(s.poll(frame), [Command::Sky(vec![44, 104])]);
+        let rest: Vec<_> = (0..40).flat_map(|_| s.poll(frame)).collect();
+        assert_eq!(rest, [Command::Fps(30), Command::Stop]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = |text: &str| Scenario::parse(text).unwrap_err();
+        assert_eq!(err("1 stop\n2 fireworks"), "line 2 '2 fireworks': unknown command 'fireworks'");
+        assert!(err("soon stop").starts_with("line 1 'soon stop': bad time"));
+        assert!(err("-1 stop").contains("bad time"));
+        assert!(err("3").contains("expected <seconds> <command>"));
+        assert!(err("3 fps 0").contains("at least 1"));
+        assert!(err("3 scene sunny").contains("unknown scene 'sunny'"));
+        assert!(err("3 pause now").contains("takes no argument"));
+        assert!(err("3 sky 12").contains("isn't a color code"));
+    }
+}
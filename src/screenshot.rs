@@ -0,0 +1,216 @@
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::city::{self, Cell, City, ColorDepth, PaletteColor, FG_DEFAULT};
+use crate::daily::Date;
+use crate::pbm::{self, Ink};
+use crate::png;
+use crate::vec2d::Vec2D;
+use crate::width;
+
+/// Copy of the canvas as it was last drawn. Screenshots are taken of it instead of the city,
+/// which commands applied since may have changed
+pub struct Frame {
+    canvas: Vec2D<Cell>,
+    background: PaletteColor,
+}
+
+impl Frame {
+    pub fn of(c: &City) -> Frame {
+        Frame::with_background(c, c.get_background())
+    }
+
+    /// Canvas of `c` drawn with `background`, which the city may have changed since
+    pub fn with_background(c: &City, background: PaletteColor) -> Frame {
+        Frame { canvas: c.get_canvas().clone(), background }
+    }
+
+    /// Copies the canvas of `c`, reusing the memory of the last copy
+    pub fn update(&mut self, c: &City) {
+        self.canvas.clone_from(c.get_canvas());
+        self.background = c.get_background();
+    }
+
+    /// The frame as lines of colored cells, `cat` prints it back in a terminal. RGB colors
+    /// are kept whatever the terminal it was taken on shows
+    pub fn to_ans(&self) -> String {
+        let mut out = String::new();
+        for row in self.canvas.row_iter() {
+            let (mut last_bg, mut last_fg) = (None, FG_DEFAULT);
+            for cell in row {
+                if last_bg != Some(cell.bg) {
+                    last_bg = Some(cell.bg);
+                    write!(out, "\x1b[{}m", Cell::sgr(cell.bg, ColorDepth::TrueColor)).unwrap();
+                }
+                if last_fg != cell.fg && cell.glyph != ' ' {
+                    last_fg = cell.fg;
+                    write!(out, "\x1b[{}m", cell.fg).unwrap();
+                }
+                out.push(cell.glyph);
+            }
+            // styles end with the line, so resized terminals don't fill the rest with them
+            out.push_str("\x1b[0m\n");
+        }
+        out
+    }
+
+    /// Whether the canvas and background of `c` are the ones of the frame
+    pub fn shows(&self, c: &City) -> bool {
+        self.background == c.get_background() && self.canvas.as_flat_slice() == c.get_canvas().as_flat_slice()
+    }
+
+    pub fn write_pbm(&self, path: &Path, scale: usize, ink: &Ink) -> io::Result<()> {
+        pbm::write(&self.canvas, self.background, path, scale, ink)
+    }
+
+    /// The frame as an RGB PNG with cells two pixels tall, about the shape they have in a
+    /// terminal. Cells with a glyph take its color
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let (_, (w, h)) = self.canvas.as_flat_slice();
+        let mut pixels = Vec::with_capacity(w * h * 2);
+        for row in self.canvas.row_iter() {
+            let colors: Vec<_> = row.iter().map(|c| Cell::rgb_of(if c.glyph == ' ' { c.bg } else { c.fg })).collect();
+            pixels.extend_from_slice(&colors);
+            pixels.extend_from_slice(&colors);
+        }
+        png::write_rgb(path, (w, h * 2), &pixels)
+    }
+}
+
+/// Background colors of the cells of a frame written by `Frame::to_ans`. Only the SGR codes
+/// it writes are understood: resets, foreground and background codes and RGB backgrounds,
+/// also as 256 color palette indexes, which are read as their RGB
+pub fn parse_ans(text: &str) -> Result<Vec2D<PaletteColor>, String> {
+    let mut rows: Vec<Vec<PaletteColor>> = Vec::new();
+    for (y, line) in text.split_terminator('\n').enumerate() {
+        let mut row = Vec::new();
+        let mut bg = None;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                match (width::char_width(c), bg) {
+                    (Some(1), Some(bg)) => row.push(bg),
+                    (Some(1), None) => return Err(format!("line {}: cell before any background", y + 1)),
+                    _ => return Err(format!("line {}: {:?} isn't a cell", y + 1, c)),
+                }
+                continue;
+            }
+
+            let params: String = chars.by_ref().skip(1).take_while(|&c| c != 'm').collect();
+            let codes = params.split(';').map(|v| v.parse::<PaletteColor>()).collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("line {}: unknown escape '{}'", y + 1, params))?;
+            match codes[..] {
+                [0] => bg = None,
+                [30..=39] | [90..=97] => {}
+                [code @ 40..=47] | [code @ 100..=107] => bg = Some(code),
+                [48, 2, r, g, b] if r < 256 && g < 256 && b < 256 => bg = Some(Cell::rgb(r as u8, g as u8, b as u8)),
+                [48, 5, i] if i < 256 => {
+                    let (r, g, b) = city::xterm_256_rgb(i as u8);
+                    bg = Some(Cell::rgb(r, g, b));
+                }
+                _ => return Err(format!("line {}: unknown SGR code '{}'", y + 1, params)),
+            }
+        }
+        if bg.is_some() {
+            return Err(format!("line {}: colors aren't reset at the end", y + 1));
+        }
+        if rows.first().is_some_and(|first| first.len() != row.len()) {
+            return Err(format!("line {} is {} cells wide, the first is {}", y + 1, row.len(), rows[0].len()));
+        }
+        rows.push(row);
+    }
+
+    let (w, h) = (rows.first().map_or(0, Vec::len), rows.len());
+    let mut colors = Vec2D::new(w, h, || 0);
+    for (y, row) in rows.iter().enumerate() {
+        colors.get_row_mut(y).copy_from_slice(row);
+    }
+    Ok(colors)
+}
+
+/// Path without extension for screenshots taken at unix time `secs`, `city-<date>-<hhmmss>`
+/// in `dir` followed by a counter if a file with one of `exts` is there already
+pub fn free_stem(dir: &Path, secs: u64, exts: &[&str]) -> PathBuf {
+    let time = secs % 86400;
+    let name = format!("city-{}-{:02}{:02}{:02}", Date::at(secs, 0), time / 3600, time / 60 % 60, time % 60);
+    let taken = |stem: &PathBuf| exts.iter().any(|ext| stem.with_extension(ext).exists());
+
+    let mut stem = dir.join(&name);
+    let mut n = 1;
+    while taken(&stem) {
+        n += 1;
+        stem = dir.join(format!("{}-{}", name, n));
+    }
+    stem
+}
+
+/// Writes the frame as `.ans` and, if `pbm` has a scale and ink, as `.pbm` too, into `dir`
+/// (made if missing) with names from `free_stem`. Returns the path of the `.ans`
+pub fn save(frame: &Frame, dir: &Path, secs: u64, pbm: Option<(usize, &Ink)>) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let exts: &[&str] = if pbm.is_some() { &["ans", "pbm"] } else { &["ans"] };
+    let stem = free_stem(dir, secs, exts);
+
+    let ans = stem.with_extension("ans");
+    // a screenshot taken at the same time elsewhere isn't replaced
+    OpenOptions::new().write(true).create_new(true).open(&ans)?.write_all(frame.to_ans().as_bytes())?;
+    if let Some((scale, ink)) = pbm {
+        frame.write_pbm(&stem.with_extension("pbm"), scale, ink)?;
+    }
+    Ok(ans)
+}
+
+#[cfg(test)]
+mod tests {
+    use fastrand::Rng;
+
+    use super::*;
+    use crate::city::Stars;
+    use crate::{default_layers, BG_COLOR};
+
+    #[test]
+    fn screenshot_names_dont_collide() {
+        let dir = std::env::temp_dir().join(format!("city-screenshot-test-{}", std::process::id()));
+        let (rng, layers) = (Rng::with_seed(1), default_layers());
+        let frame = Frame::of(&City::new(30, 12, 1, &rng, BG_COLOR, &layers));
+        let ink = Ink::default();
+        let secs = 19875 * 86400 + 3723;
+
+        let first = save(&frame, &dir, secs, None).unwrap();
+        let second = save(&frame, &dir, secs, Some((2, &ink))).unwrap();
+        let third = save(&frame, &dir, secs, None).unwrap();
+        let names: Vec<_> = [&first, &second, &third].iter().map(|p| p.file_name().unwrap().to_owned()).collect();
+        let text = fs::read_to_string(&first);
+        let pbm_exists = second.with_extension("pbm").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names, ["city-2024-06-01-010203.ans", "city-2024-06-01-010203-2.ans",
+                           "city-2024-06-01-010203-3.ans"]);
+        assert!(pbm_exists);
+        let text = text.unwrap();
+        assert_eq!(text.lines().count(), 12);
+        assert!(text.lines().all(|l| l.ends_with("\x1b[0m")));
+    }
+
+    #[test]
+    fn ans_round_trip() {
+        let (rng, layers) = (Rng::with_seed(3), default_layers());
+        let mut c = City::new(40, 15, 1, &rng, BG_COLOR, &layers);
+        c.set_background_gradient(vec![Cell::rgb(10, 20, 30), 44]);
+        c.set_stars(Some(Stars { count: 20, shooting_chance: 0.0 }), 3);
+        c.warmup();
+        c.next_tick();
+
+        let colors = parse_ans(&Frame::of(&c).to_ans()).unwrap();
+        let (cells, size) = c.get_canvas().as_flat_slice();
+        assert_eq!(colors.as_flat_slice(), (&cells.iter().map(|c| c.bg).collect::<Vec<_>>()[..], size));
+
+        assert!(parse_ans("\x1b[44m  \x1b[0m\n\x1b[44m \x1b[0m\n").unwrap_err().contains("line 2"));
+        assert!(parse_ans("\x1b[44m \x1b[1m \x1b[0m\n").unwrap_err().contains("unknown SGR"));
+        assert!(parse_ans("\x1b[44m \n").unwrap_err().contains("reset"));
+        let indexed = parse_ans("\x1b[48;5;196m \x1b[48;5;244m \x1b[0m\n").unwrap();
+        assert_eq!(indexed.get_row(0), [Cell::rgb(255, 0, 0), Cell::rgb(128, 128, 128)]);
+    }
+}
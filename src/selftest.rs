@@ -0,0 +1,267 @@
+use std::any::Any;
+use std::panic;
+
+use fastrand::Rng;
+
+use crate::city::{Cell, City, LayerDesc, PaletteColor, Tick, TICK_WRAP};
+use crate::console::{SIZE_DEFAULT_H, SIZE_DEFAULT_W, SIZE_FULL_W, SIZE_MIN_H, SIZE_MIN_W};
+use crate::demo;
+use crate::{default_layers, BG_COLOR};
+
+const SEED: u64 = 0x5eed;
+const TICKS: usize = 3000;
+const CHECK_EVERY: usize = 10;
+
+type CheckResult = Result<(), String>;
+type Check = fn() -> CheckResult;
+
+/// Runs every check, printing PASS/FAIL per check. Returns false if any check failed
+pub fn run() -> bool {
+    let checks: [(&str, Check); 7] = [
+        ("sizes", check_sizes),
+        ("resize", check_resize),
+        ("window glyph", check_window_glyph),
+        ("determinism", check_determinism),
+        ("tick wrap", check_tick_wrap),
+        ("double buffer", check_double_buffer),
+        ("demo scenes", check_demo_scenes),
+    ];
+
+    // panics are reported as check failures, keep the default hook quiet meanwhile
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut passed = true;
+    for (name, check) in checks.iter() {
+        let result = panic::catch_unwind(check).unwrap_or_else(|e| Err(panic_msg(e)));
+        match result {
+            Ok(()) => println!("PASS  {}", name),
+            Err(e) => {
+                passed = false;
+                println!("FAIL  {}: {}", name, e);
+            }
+        }
+    }
+
+    panic::set_hook(default_hook);
+    passed
+}
+
+fn panic_msg(e: Box<dyn Any + Send>) -> String {
+    let msg = match e.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => e.downcast_ref::<String>().cloned().unwrap_or_default(),
+    };
+    format!("panicked: {}", msg)
+}
+
+fn known_colors(bg: PaletteColor, layers: &[LayerDesc]) -> Vec<PaletteColor> {
+    let mut colors = vec![bg];
+    for d in layers {
+        colors.extend(d.wall_color.iter());
+        for &wall in &d.wall_color {
+            colors.extend(d.window_colors.iter().map(|c| c.resolve(wall)));
+        }
+    }
+    colors
+}
+
+fn check_canvas(c: &City, known: &[PaletteColor]) -> CheckResult {
+    let (w, h) = c.get_size();
+    let canvas = c.get_canvas();
+    let known_cell = |cell: &Cell| known.contains(&cell.bg)
+        && (cell.glyph == ' ' || known.iter().any(|&k| Cell::fg_of(k) == cell.fg));
+
+    if canvas.row_iter().count() != h {
+        return Err(format!("canvas has wrong row count at {}x{}", w, h));
+    }
+
+    let blank = Cell::blank(BG_COLOR);
+    if c.skyline() != &canvas.column_heights(|cell| *cell != blank)[..] {
+        return Err(format!("skyline doesn't match canvas at {}x{}, tick {}", w, h, c.get_tick()));
+    }
+
+    let (flat, flat_wh) = c.canvas_raw();
+    if flat_wh != (w, h) || flat.len() != w * h {
+        return Err(format!("flat canvas is {} cells of {:?} at {}x{}", flat.len(), flat_wh, w, h));
+    }
+
+    for (y, row) in canvas.row_iter().enumerate() {
+        if row.len() != w {
+            return Err(format!("row {} has length {} at {}x{}", y, row.len(), w, h));
+        }
+        if let Some(x) = row.iter().position(|cell| !known_cell(cell)) {
+            return Err(format!("unknown color {:?} at ({}, {}), size {}x{}, tick {}",
+                               row[x], x, y, w, h, c.get_tick()));
+        }
+    }
+
+    Ok(())
+}
+
+fn run_ticks(c: &mut City, ticks: usize, known: &[PaletteColor]) -> CheckResult {
+    for i in 0..ticks {
+        c.next_tick();
+        if i % CHECK_EVERY == 0 {
+            check_canvas(c, known)?;
+        }
+    }
+    Ok(())
+}
+
+fn same_canvas(a: &City, b: &City) -> bool {
+    a.get_canvas().row_iter().eq(b.get_canvas().row_iter())
+}
+
+fn check_sizes() -> CheckResult {
+    let layers = default_layers();
+    let known = known_colors(BG_COLOR, &layers);
+    let sizes = [
+        (SIZE_MIN_W, SIZE_MIN_H),
+        (SIZE_FULL_W - 1, SIZE_MIN_H),
+        (SIZE_DEFAULT_W, SIZE_DEFAULT_H),
+        (SIZE_MIN_W, SIZE_DEFAULT_H * 2),
+        (SIZE_DEFAULT_W * 2, SIZE_MIN_H),
+        (SIZE_DEFAULT_W * 2, SIZE_DEFAULT_H * 2),
+    ];
+
+    for &(w, h) in sizes.iter() {
+        for &step in [1, 3].iter() {
+            let rng = Rng::with_seed(SEED);
+            let mut c = City::new(w, h, step, &rng, BG_COLOR, &layers);
+            run_ticks(&mut c, TICKS, &known)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn check_resize() -> CheckResult {
+    let layers = default_layers();
+    let known = known_colors(BG_COLOR, &layers);
+    let sizes = [
+        (SIZE_DEFAULT_W, SIZE_DEFAULT_H),
+        (SIZE_FULL_W - 1, SIZE_DEFAULT_H),
+        (SIZE_MIN_W, SIZE_MIN_H),
+        (SIZE_DEFAULT_W * 2, SIZE_DEFAULT_H),
+        (SIZE_MIN_W, SIZE_DEFAULT_H * 2),
+    ];
+
+    let rng = Rng::with_seed(SEED);
+    let mut c = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng, BG_COLOR, &layers);
+
+    for &(w, h) in sizes.iter() {
+        c.set_wh(w, h);
+        run_ticks(&mut c, TICKS / sizes.len(), &known)?;
+    }
+
+    Ok(())
+}
+
+fn check_demo_scenes() -> CheckResult {
+    let base = default_layers();
+    let scenes = demo::scene_layers(&base);
+
+    let rng = Rng::with_seed(SEED);
+    let mut c = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng, BG_COLOR, &base);
+    for (i, layers) in scenes.iter().enumerate() {
+        c.set_layers(layers);
+        c.warmup();
+        run_ticks(&mut c, TICKS / scenes.len(), &known_colors(BG_COLOR, layers))
+            .map_err(|e| format!("{}: {}", demo::scene_name(i), e))?;
+    }
+
+    Ok(())
+}
+
+fn check_window_glyph() -> CheckResult {
+    let mut layers = default_layers();
+    layers.iter_mut().for_each(|d| d.window_glyph = Some('▪'));
+    let known = known_colors(BG_COLOR, &layers);
+
+    let rng = Rng::with_seed(SEED);
+    let mut c = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng, BG_COLOR, &layers);
+    run_ticks(&mut c, TICKS, &known)?;
+
+    let glyphs = c.get_canvas().row_iter().flatten().filter(|cell| cell.glyph == '▪').count();
+    match glyphs {
+        0 => Err("no window glyphs drawn".to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn check_determinism() -> CheckResult {
+    let layers = default_layers();
+    let (rng_a, rng_b) = (Rng::with_seed(SEED), Rng::with_seed(SEED));
+    let mut a = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng_a, BG_COLOR, &layers);
+    let mut b = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng_b, BG_COLOR, &layers);
+
+    for _ in 0..TICKS {
+        a.next_tick();
+        b.next_tick();
+        if !same_canvas(&a, &b) {
+            return Err(format!("canvases diverged at tick {}", a.get_tick()));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_double_buffer() -> CheckResult {
+    let layers = default_layers();
+    let known = known_colors(BG_COLOR, &layers);
+    let (rng_a, rng_b) = (Rng::with_seed(SEED), Rng::with_seed(SEED));
+    let mut a = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng_a, BG_COLOR, &layers);
+    let mut b = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng_b, BG_COLOR, &layers);
+    b.enable_double_buffer();
+
+    let mut prev = a.canvas_raw().0.to_vec();
+    for _ in 0..TICKS {
+        a.next_tick();
+        b.next_tick();
+        if !same_canvas(&a, &b) {
+            return Err(format!("double buffered canvas diverged at tick {}", a.get_tick()));
+        }
+        if b.get_prev_canvas().map(|c| c.as_flat_slice().0) != Some(&prev[..]) {
+            return Err(format!("previous canvas isn't the last frame at tick {}", b.get_tick()));
+        }
+        check_canvas(&b, &known)?;
+        prev.copy_from_slice(a.canvas_raw().0);
+    }
+
+    Ok(())
+}
+
+fn check_tick_wrap() -> CheckResult {
+    // with layer speeds dividing TICK_WRAP, spawn ticks keep their phase across the wrap,
+    // so a city crossing the wrap must render exactly like one starting from tick 1
+    let speeds: [Tick; 3] = [9, 3, 1];
+    let mut layers = default_layers();
+    for (d, &speed) in layers.iter_mut().zip(speeds.iter()) {
+        assert_eq!(TICK_WRAP % speed, 0);
+        d.speed = speed;
+    }
+
+    let period = speeds[0] as usize;
+    let start = TICK_WRAP + 1 - (TICKS / 2 / period * period) as Tick;
+
+    let (rng_a, rng_b) = (Rng::with_seed(SEED), Rng::with_seed(SEED));
+    let mut a = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng_a, BG_COLOR, &layers);
+    let mut b = City::new(SIZE_DEFAULT_W, SIZE_DEFAULT_H, 1, &rng_b, BG_COLOR, &layers);
+    b.set_tick(start);
+
+    for _ in 0..TICKS {
+        a.next_tick();
+        b.next_tick();
+        if !same_canvas(&a, &b) {
+            return Err(format!("canvas mismatch at tick {} (wrapped tick {})",
+                               a.get_tick(), b.get_tick()));
+        }
+    }
+
+    if b.get_tick() >= start {
+        return Err("tick didn't wrap".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as fmtWrite;
+use std::io::StdoutLock;
+
+use crate::city::{self, Cell, City, PaletteColor, WHSize};
+use crate::console::{self, CursorMove, Margins, WriteStats};
+use crate::vec2d::Vec2D;
+
+/// Cell size in pixels when the terminal doesn't report it
+pub const CELL_PX_DEFAULT: WHSize = (8, 16);
+/// Color registers of an image, terminals have at least this many
+const REGISTERS: usize = 256;
+
+/// Whether TERM names a terminal known to show sixel images without extra setup
+pub fn term_supports() -> bool {
+    match std::env::var("TERM") {
+        Ok(term) => term.starts_with("foot") || term.starts_with("mlterm") || term == "yaft-256color",
+        Err(_) => false,
+    }
+}
+
+/// Draws the canvas as one sixel image over the cells the text canvas would take,
+/// leaving the cursor and the rest of the frame where `console::draw_to_console` leaves them
+pub fn draw(c: &City, cell_px: WHSize, buf: &mut String, out: &mut StdoutLock, margins: &Margins, up: usize,
+            chunk: usize) -> WriteStats {
+    buf.clear();
+    let mut stats = WriteStats::default();
+
+    // image starts at the cursor, save it so the image height in rows doesn't matter
+    write!(buf, "\x1b[0m{}\r{}\x1b7", CursorMove::up(up), CursorMove::fwd(margins.left)).unwrap();
+    encode(c.get_canvas(), cell_px, buf, &mut |buf| console::write_chunk(buf, out, chunk, &mut stats));
+    write!(buf, "\x1b8{}", CursorMove::down(c.get_size().1 - 1)).unwrap();
+    stats
+}
+
+/// Appends the canvas as a sixel image, each cell `cell_px` pixels big.
+/// Cells with a glyph get a box of the foreground color in their middle. Canvases with more
+/// colors than `REGISTERS` (gradients, blur, fades) are drawn in the nearest of xterm's 256.
+/// `flush` gets the buffer after every band of six pixel rows
+fn encode(canvas: &Vec2D<Cell>, (cw, ch): WHSize, buf: &mut String, flush: &mut dyn FnMut(&mut String)) {
+    let (_, (w, h)) = canvas.as_flat_slice();
+    let (pw, ph) = (w * cw, h * ch);
+
+    let used: HashSet<PaletteColor> = canvas.row_iter().flatten()
+        .flat_map(|cell| std::iter::once(cell.bg).chain(Some(cell.fg).filter(|_| cell.glyph != ' ')))
+        .collect();
+    let quantize = used.len() > REGISTERS;
+
+    // pixels hold indexes into colors, which become sixel color registers
+    let mut colors: Vec<PaletteColor> = Vec::new();
+    let mut shown_registers: HashMap<PaletteColor, u8> = HashMap::new();
+    let mut registers: HashMap<PaletteColor, u8> = HashMap::new();
+    let mut register = |color: PaletteColor| *registers.entry(color).or_insert_with(|| {
+        let shown = match quantize {
+            true => {
+                let (r, g, b) = city::xterm_256_rgb(city::nearest_256(Cell::rgb_of(color)));
+                Cell::rgb(r, g, b)
+            }
+            false => color,
+        };
+        *shown_registers.entry(shown).or_insert_with(|| {
+            colors.push(shown);
+            (colors.len() - 1) as u8
+        })
+    });
+
+    let mut pixels = vec![0u8; pw * ph];
+    for (y, row) in canvas.row_iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let bg = register(cell.bg);
+            let fg = if cell.glyph != ' ' { register(cell.fg) } else { bg };
+            for dy in 0..ch {
+                let inner_y = (ch / 4..ch - ch / 4).contains(&dy);
+                let line = &mut pixels[(y * ch + dy) * pw + x * cw..][..cw];
+                for (dx, p) in line.iter_mut().enumerate() {
+                    *p = if inner_y && (cw / 4..cw - cw / 4).contains(&dx) { fg } else { bg };
+                }
+            }
+        }
+    }
+
+    write!(buf, "\x1bP0;1;0q\"1;1;{};{}", pw, ph).unwrap();
+    for (i, &color) in colors.iter().enumerate() {
+        let (r, g, b) = Cell::rgb_of(color);
+        let pct = |v: u8| v as usize * 100 / 255;
+        write!(buf, "#{};2;{};{};{}", i, pct(r), pct(g), pct(b)).unwrap();
+    }
+
+    for band in (0..ph).step_by(6) {
+        let rows = &pixels[band * pw..(band + 6).min(ph) * pw];
+        for i in (0..colors.len()).map(|i| i as u8) {
+            if !rows.contains(&i) {
+                continue;
+            }
+
+            write!(buf, "#{}", i).unwrap();
+            let mut run = (0, '?');
+            for x in 0..pw {
+                let bits = rows.chunks(pw).enumerate()
+                    .fold(0, |bits, (k, row)| if row[x] == i { bits | 1 << k } else { bits });
+                let sixel = (63 + bits) as u8 as char;
+                if sixel != run.1 {
+                    push_run(buf, run);
+                    run = (0, sixel);
+                }
+                run.0 += 1;
+            }
+            // the rest of the band keeps its other colors
+            if run.1 != '?' {
+                push_run(buf, run);
+            }
+            buf.push('$');
+        }
+        buf.push('-');
+        flush(buf);
+    }
+
+    buf.push_str("\x1b\\");
+}
+
+fn push_run(buf: &mut String, (len, sixel): (usize, char)) {
+    match len {
+        0..=3 => (0..len).for_each(|_| buf.push(sixel)),
+        _ => write!(buf, "!{}{}", len, sixel).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colors defined and colors drawn in the first band of a one pixel high image of `colors`
+    fn registers_used(colors: &[PaletteColor]) -> (usize, usize) {
+        let mut canvas = Vec2D::new(colors.len(), 1, || Cell::blank(40));
+        for (x, &color) in colors.iter().enumerate() {
+            canvas[(x, 0)] = Cell::blank(color);
+        }
+        let mut buf = String::new();
+        encode(&canvas, (1, 1), &mut buf, &mut |_| {});
+
+        let defined = buf.matches(";2;").count();
+        let body = &buf[buf.rfind(";2;").unwrap()..];
+        (defined, body.matches('$').count())
+    }
+
+    #[test]
+    fn colors_fit_the_registers() {
+        let gradient = |n: usize| (0..n).map(|i| Cell::rgb((i % 256) as u8, (i / 256) as u8, 50)).collect::<Vec<_>>();
+        assert_eq!(registers_used(&[40, 107, 40]), (2, 2));
+        assert_eq!(registers_used(&gradient(256)), (256, 256));
+
+        // more colors than registers are drawn in fewer, every one of them used
+        let (defined, drawn) = registers_used(&gradient(600));
+        assert!((2..=REGISTERS).contains(&defined), "{} registers", defined);
+        assert_eq!(drawn, defined);
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use crate::control::Command;
+
+const LINE_MAX: usize = 4096; // clients sending longer lines are dropped
+
+/// Unix socket taking commands one per line, as a scenario names them (plus `quit`).
+/// Each line is answered with `ok` or `error: <why>`. Never blocks, the socket file is removed on drop
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+}
+
+struct Client {
+    stream: UnixStream,
+    buf: Vec<u8>, // start of a line that hasn't ended yet
+}
+
+/// Unix socket sending a line per frame to everyone connected. Lines a client isn't reading
+/// fast enough are dropped whole, so the frames never wait. The socket file is removed on drop
+pub struct MetricsSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<(UnixStream, Vec<u8>)>, // with the rest of a line the socket took only part of
+}
+
+/// Non-blocking listener on `path`, which mustn't exist unless `force` is set to replace it.
+/// `force_flag` names the option setting it, for the error
+fn listen(path: &Path, force: bool, force_flag: &str) -> io::Result<UnixListener> {
+    if force {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    // bind fails on an existing path too, this just explains it
+    if path.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("path exists, see {}", force_flag)));
+    }
+
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+impl ControlSocket {
+    /// Listens on `path`, which mustn't exist unless `force` is set to replace it
+    pub fn bind(path: &Path, force: bool) -> io::Result<ControlSocket> {
+        let listener = listen(path, force, "--control-force")?;
+        Ok(ControlSocket { listener, path: path.to_path_buf(), clients: Vec::new() })
+    }
+
+    /// Accepts new clients and returns the commands of lines they sent since the last poll
+    pub fn poll(&mut self) -> Vec<Command> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client { stream, buf: Vec::new() });
+            }
+        }
+
+        let mut commands = Vec::new();
+        self.clients.retain_mut(|client| client.read(&mut commands));
+        commands
+    }
+}
+
+impl Client {
+    /// Reads what's there and answers complete lines, false once the client is gone
+    fn read(&mut self, commands: &mut Vec<Command>) -> bool {
+        let mut chunk = [0; 512];
+        let open = loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => break false,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break false,
+            }
+        };
+
+        let mut answers = String::new();
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(command) => {
+                    commands.push(command);
+                    answers.push_str("ok\n");
+                }
+                Err(e) => answers.push_str(&format!("error: {}\n", e)),
+            }
+        }
+
+        // a client that doesn't read its answers isn't waited for
+        let answered = answers.is_empty() || self.stream.write_all(answers.as_bytes()).is_ok();
+        open && answered && self.buf.len() <= LINE_MAX
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl MetricsSocket {
+    /// Listens on `path`, which mustn't exist unless `force` is set to replace it
+    pub fn bind(path: &Path, force: bool) -> io::Result<MetricsSocket> {
+        let listener = listen(path, force, "--metrics-force")?;
+        Ok(MetricsSocket { listener, path: path.to_path_buf(), clients: Vec::new() })
+    }
+
+    /// Accepts new clients and sends them the line `line` makes, which isn't called
+    /// while nobody is connected. It's dropped for clients still taking an earlier one
+    pub fn send(&mut self, line: impl FnOnce() -> String) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push((stream, Vec::new()));
+            }
+        }
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let mut line = line();
+        line.push('\n');
+        self.clients.retain_mut(|(stream, pending)| {
+            if pending.is_empty() {
+                pending.extend_from_slice(line.as_bytes());
+            }
+            while !pending.is_empty() {
+                match stream.write(pending) {
+                    Ok(0) => return false,
+                    Ok(n) => {
+                        pending.drain(..n);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return false,
+                }
+            }
+            true
+        });
+    }
+}
+
+impl Drop for MetricsSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn poll_for(socket: &mut ControlSocket, count: usize) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for _ in 0..200 {
+            commands.extend(socket.poll());
+            if commands.len() >= count {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        commands
+    }
+
+    #[test]
+    fn commands_are_answered() {
+        let path = std::env::temp_dir().join(format!("city-socket-test-{}", std::process::id()));
+        let mut socket = ControlSocket::bind(&path, true).unwrap();
+        assert!(ControlSocket::bind(&path, false).is_err());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"pause\nfps 30\nseed many\nqu").unwrap();
+        assert_eq!(poll_for(&mut socket, 2), [Command::Pause, Command::Fps(30)]);
+        client.write_all(b"it\n").unwrap();
+        assert_eq!(poll_for(&mut socket, 1), [Command::Stop]);
+
+        let answers: Vec<String> = BufReader::new(client).lines().take(4).map(Result::unwrap).collect();
+        assert_eq!(answers[..2], ["ok", "ok"]);
+        assert!(answers[2].starts_with("error: seed 'many'"));
+        assert_eq!(answers[3], "ok");
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn metrics_dont_wait_for_clients() {
+        let path = std::env::temp_dir().join(format!("city-metrics-test-{}", std::process::id()));
+        let mut socket = MetricsSocket::bind(&path, true).unwrap();
+        socket.send(|| panic!("line made without clients"));
+
+        // a client that doesn't read gets whole lines until its socket is full, then none
+        let client = UnixStream::connect(&path).unwrap();
+        let line = format!("{{\"pad\":\"{}\"}}", "x".repeat(1000));
+        for _ in 0..1000 {
+            socket.send(|| line.clone());
+        }
+        drop(socket);
+
+        // the line taken in part when the socket closed is left cut
+        let mut text = String::new();
+        BufReader::new(client).read_to_string(&mut text).unwrap();
+        let lines: Vec<&str> = text.rsplit_once('\n').map_or(vec![], |(whole, _)| whole.split('\n').collect());
+        assert!(!lines.is_empty() && lines.len() < 1000, "{} lines", lines.len());
+        assert!(lines.iter().all(|&l| l == line));
+    }
+}
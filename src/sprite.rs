@@ -0,0 +1,280 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::city::{self, Cell, LayerDesc, PaletteColor, Tick, WHSize};
+use crate::effect::{Effect, EffectCanvas, EffectCtx, EffectPhase};
+use crate::vec2d::Vec2D;
+
+/// Edge of the canvas a sprite's rows are counted from, and how many rows away from it it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Top(usize),
+    Bottom(usize), // 0 stands it on the ground
+}
+
+/// Decoration scrolling across the sky, parsed from `name value` settings, an `art` line and
+/// the art under it. Settings are `anchor top|bottom <rows>` (default top 1, bottom 0 stands
+/// on the ground), `layer behind|front` of the buildings (default behind), `speed <ticks per
+/// cell>` (default 2), `transparent <character>` (default space, short lines are padded with
+/// it) and `color <character> <color>` for each character of the art, where the color is
+/// a code or a `SpriteColor` role. Blank lines and ones starting with '#' before the art are
+/// skipped:
+///
+/// ```text
+/// anchor top 2
+/// color B 100
+/// color o window
+/// art
+///  BBBB
+/// BoBoBB
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    cells: Vec2D<Option<SpriteColor>>,
+    anchor: Anchor,
+    phase: EffectPhase,
+    speed: Tick,
+}
+
+impl Sprite {
+    pub fn load(path: &Path) -> Result<Sprite, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        text.parse()
+    }
+
+    #[inline]
+    pub fn size(&self) -> WHSize {
+        self.cells.as_flat_slice().1
+    }
+
+    #[inline]
+    pub fn phase(&self) -> EffectPhase {
+        self.phase
+    }
+}
+
+impl FromStr for Sprite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut anchor, mut phase, mut speed, mut transparent) = (Anchor::Top(1), EffectPhase::Behind, 2, ' ');
+        let mut colors = Vec::new();
+        let mut lines = s.lines().enumerate();
+
+        for (i, line) in lines.by_ref() {
+            let invalid = |msg: String| format!("line {}: {}", i + 1, msg);
+            let setting = line.trim();
+            if setting.is_empty() || setting.starts_with('#') {
+                continue;
+            }
+            if setting == "art" {
+                break;
+            }
+
+            let (name, value) = setting.split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(format!("expected <name> <value> or art, got '{}'", setting)))?;
+            let value = value.trim();
+            match name {
+                "anchor" => {
+                    let rows = |rows: &str| rows.trim().parse().map_err(|e| invalid(format!("rows: {}", e)));
+                    anchor = match value.split_once(char::is_whitespace) {
+                        Some(("top", r)) => Anchor::Top(rows(r)?),
+                        Some(("bottom", r)) => Anchor::Bottom(rows(r)?),
+                        _ => return Err(invalid(format!("expected top or bottom and rows, got '{}'", value))),
+                    }
+                }
+                "layer" => phase = match value {
+                    "behind" => EffectPhase::Behind,
+                    "front" => EffectPhase::InFront,
+                    _ => return Err(invalid(format!("expected behind or front, got '{}'", value))),
+                },
+                "speed" => speed = match value.parse() {
+                    Ok(speed) if speed > 0 => speed,
+                    _ => return Err(invalid(format!("speed '{}' isn't a number of ticks above 0", value))),
+                },
+                "transparent" => transparent = single_char(value).ok_or_else(|| invalid("expected a character".into()))?,
+                "color" => {
+                    let (c, color) = value.split_once(char::is_whitespace)
+                        .and_then(|(c, color)| Some((single_char(c)?, color)))
+                        .ok_or_else(|| invalid(format!("expected <character> <color>, got '{}'", value)))?;
+                    colors.push((c, color.parse::<SpriteColor>().map_err(invalid)?));
+                }
+                _ => return Err(invalid(format!("unknown setting '{}'", name))),
+            }
+        }
+
+        let art: Vec<(usize, &str)> = lines.collect();
+        let h = art.iter().rposition(|(_, l)| !l.trim().is_empty()).map_or(0, |last| last + 1);
+        let art = &art[..h];
+        let w = art.iter().map(|(_, l)| l.chars().count()).max().unwrap_or(0);
+        if w == 0 {
+            return Err("sprite has no art, it goes under an 'art' line".to_string());
+        }
+
+        let mut cells = Vec2D::new(w, h, || None);
+        for (y, &(i, line)) in art.iter().enumerate() {
+            for (x, c) in line.chars().enumerate().filter(|&(_, c)| c != transparent) {
+                let color = colors.iter().find(|&&(known, _)| known == c)
+                    .ok_or_else(|| format!("line {}, column {}: no color for {:?}", i + 1, x + 1, c))?;
+                cells[(x, y)] = Some(color.1);
+            }
+        }
+
+        Ok(Sprite { cells, anchor, phase, speed })
+    }
+}
+
+/// Color of sprite cells, a code or a role taking the color of the city's front layer when the
+/// sprite is put in it: `wall` is its first wall color and `window` its first window color that
+/// stands out from that wall. Sprites with roles match the city whatever colors it's given
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpriteColor {
+    Fixed(PaletteColor),
+    Wall,
+    Window,
+}
+
+impl SpriteColor {
+    fn resolve(self, front: &LayerDesc) -> PaletteColor {
+        let wall = front.wall_color[0];
+        match self {
+            SpriteColor::Fixed(color) => color,
+            SpriteColor::Wall => wall,
+            SpriteColor::Window => front.window_colors.iter().map(|c| c.resolve(wall))
+                .find(|&color| color != wall)
+                .unwrap_or(wall),
+        }
+    }
+}
+
+impl FromStr for SpriteColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "wall" => Ok(SpriteColor::Wall),
+            "window" => Ok(SpriteColor::Window),
+            s => city::parse_color(s).map(SpriteColor::Fixed),
+        }
+    }
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    chars.next().filter(|_| chars.next().is_none())
+}
+
+/// Sprite crossing the canvas from the right edge to the left one over and over, where it is
+/// depends only on the tick
+#[derive(Debug)]
+pub struct SpriteEffect {
+    sprite: Sprite,
+    colors: Vec2D<Option<PaletteColor>>, // of the sprite's cells, roles taken from the front layer
+}
+
+impl SpriteEffect {
+    /// Sprite in a city with `front` as its front layer. Fails for sprites wider or taller
+    /// than a canvas of `size`
+    pub fn new(sprite: Sprite, (w, h): WHSize, front: &LayerDesc) -> Result<SpriteEffect, String> {
+        let (sw, sh) = sprite.size();
+        if sw > w || sh > h {
+            return Err(format!("sprite is {}x{}, bigger than the {}x{} canvas", sw, sh, w, h));
+        }
+        let mut colors = Vec2D::new(sw, sh, || None);
+        for (y, row) in sprite.cells.row_iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                colors[(x, y)] = color.map(|color| color.resolve(front));
+            }
+        }
+        Ok(SpriteEffect { sprite, colors })
+    }
+
+    /// Canvas cell of the top left corner at `tick`, it may be outside the canvas
+    fn pos(&self, tick: Tick, (w, h): WHSize) -> (i64, i64) {
+        let (sw, sh) = self.sprite.size();
+        let travelled = (tick / self.sprite.speed) as usize % (w + sw);
+        let y = match self.sprite.anchor {
+            Anchor::Top(rows) => rows as i64,
+            Anchor::Bottom(rows) => h as i64 - (rows + sh) as i64,
+        };
+        (w as i64 - travelled as i64, y)
+    }
+}
+
+impl Effect for SpriteEffect {
+    fn tick(&mut self, _: &EffectCtx) {}
+
+    fn draw(&self, canvas: &mut EffectCanvas, ctx: &EffectCtx) {
+        let (w, h) = ctx.size;
+        let (px, py) = self.pos(ctx.tick, ctx.size);
+        for (y, row) in self.colors.row_iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let (cx, cy) = (px + x as i64, py + y as i64);
+                let inside = (0..w as i64).contains(&cx) && (0..h as i64).contains(&cy);
+                if let Some(color) = color.filter(|_| inside) {
+                    canvas.set((cx as usize, cy as usize), Cell::blank(color));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastrand::Rng;
+
+    use super::*;
+    use crate::city::{MotionProfile, WindowColor};
+    use crate::default_layers;
+
+    #[test]
+    fn blimp_scrolls_in_from_the_right() {
+        let sprite: Sprite = include_str!("../sprites/blimp.txt").parse().unwrap();
+        assert_eq!(sprite.size(), (20, 6));
+        let effect = SpriteEffect::new(sprite, (60, 20), &default_layers()[2]).unwrap();
+
+        // 10 cells in at speed 3, rows from 2
+        let ctx = EffectCtx { tick: 30, size: (60, 20), motion: MotionProfile::Full, rng: Rng::with_seed(0) };
+        let mut cells = Vec2D::new(60, 20, || Cell::blank(107));
+        let mut dirty = vec![(60, 0); 20];
+        effect.draw(&mut EffectCanvas::new(&mut cells, &mut dirty), &ctx);
+        let colors = |y: usize| cells.get_row(y).iter().map(|c| c.bg).collect::<Vec<_>>();
+        assert_eq!(colors(4)[48..], [107, 107, 107, 100, 100, 100, 100, 100, 100, 100, 100, 100]);
+        assert_eq!(colors(7)[50..], [107, 107, 107, 107, 107, 107, 40, 103, 40, 103]);
+        assert!((0..20).filter(|y| !(2..8).contains(y)).all(|y| colors(y).iter().all(|&c| c == 107)));
+        assert_eq!(dirty[4], (51, 60));
+    }
+
+    #[test]
+    fn sprite_errors_point_at_the_line() {
+        let err = |text: &str| text.parse::<Sprite>().unwrap_err();
+        assert_eq!(err("color x 100\nart\n xx\n xy"), "line 4, column 3: no color for 'y'");
+        assert_eq!(err("speed 0\nart\nx"), "line 1: speed '0' isn't a number of ticks above 0");
+        assert_eq!(err("\nlayer over\nart\nx"), "line 2: expected behind or front, got 'over'");
+        assert!(err("color x 100\n").contains("no art"));
+
+        let wide: Sprite = "color x 100\nart\nxxxxxxxxxx".parse().unwrap();
+        assert_eq!(SpriteEffect::new(wide, (8, 20), &default_layers()[2]).unwrap_err(),
+                   "sprite is 10x1, bigger than the 8x20 canvas");
+        assert_eq!(err("color x door\nart\nx"), "line 1: door: invalid digit found in string");
+    }
+
+    #[test]
+    fn roles_take_front_layer_colors() {
+        let sprite: Sprite = "color w wall\ncolor o window\ncolor x 41\nart\nwox".parse().unwrap();
+        let row = |front: &LayerDesc| {
+            let effect = SpriteEffect::new(sprite.clone(), (10, 5), front).unwrap();
+            effect.colors.get_row(0).to_vec()
+        };
+
+        // the default front layer's first window color is the wall's own, 107 is the next
+        let mut front = default_layers()[2].clone();
+        assert_eq!(row(&front), [Some(40), Some(107), Some(41)]);
+        front.wall_color = std::iter::once(44).collect();
+        front.window_colors = std::iter::once(WindowColor::Fixed(103)).collect();
+        assert_eq!(row(&front), [Some(44), Some(103), Some(41)]);
+        front.window_colors.clear();
+        assert_eq!(row(&front), [Some(44), Some(44), Some(41)]);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::city::WHSize;
+use crate::vec2d::Vec2D;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StampCell {
+    Empty,
+    Wall,
+    Window,
+}
+
+/// Fixed building shape drawn instead of a random rectangle, parsed from ASCII art:
+/// `#` is wall, `o` is window, space or `.` is empty. Short lines are padded with empty cells
+#[derive(Debug, Clone)]
+pub struct Stamp {
+    cells: Vec2D<StampCell>,
+    size: WHSize,
+}
+
+impl Stamp {
+    pub fn load(path: &Path) -> Result<Stamp, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        text.parse()
+    }
+
+    #[inline]
+    pub fn size(&self) -> WHSize {
+        self.size
+    }
+
+    #[inline]
+    pub fn cell(&self, xy: (usize, usize)) -> StampCell {
+        self.cells[xy]
+    }
+}
+
+impl FromStr for Stamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines: Vec<&str> = s.trim_end_matches(['\n', '\r']).lines().collect();
+        let w = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let h = lines.len();
+        if w == 0 {
+            return Err("stamp is empty".to_string());
+        }
+
+        let mut cells = Vec2D::new(w, h, || StampCell::Empty);
+        for (y, line) in lines.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                cells[(x, y)] = match c {
+                    '#' => StampCell::Wall,
+                    'o' => StampCell::Window,
+                    ' ' | '.' => StampCell::Empty,
+                    _ => return Err(format!("unknown character {:?} at line {}", c, y + 1)),
+                };
+            }
+        }
+
+        Ok(Stamp { cells, size: (w, h) })
+    }
+}
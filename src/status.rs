@@ -0,0 +1,273 @@
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::city::{Tick, TICK_WRAP};
+use crate::width::str_width;
+
+/// Piece of the status lines, padded to a fixed width so the ones after it don't move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment {
+    Tick,
+    TickTime,
+    Fps,
+    Frametime,
+    Target,
+    Frames,
+    Elapsed,
+    Clock,
+    Buildings,
+    Phases,
+    Bytes,
+}
+
+const NAMES: [(&str, Segment); 11] = [
+    ("tick", Segment::Tick),
+    ("tick-time", Segment::TickTime),
+    ("fps", Segment::Fps),
+    ("frametime", Segment::Frametime),
+    ("target", Segment::Target),
+    ("frames", Segment::Frames),
+    ("elapsed", Segment::Elapsed),
+    ("clock", Segment::Clock),
+    ("buildings", Segment::Buildings),
+    ("phases", Segment::Phases),
+    ("bytes", Segment::Bytes),
+];
+
+/// What the segments show, gathered once per frame
+#[derive(Debug, Clone, Copy)]
+pub struct Values {
+    pub tick: Tick,
+    pub tick_time: Duration,
+    pub format_time: Duration,
+    pub write_time: Duration, // writes of the canvas and the previous frame's status
+    pub bytes: usize, // written for the canvas and the previous frame's status
+    pub fps: u64,
+    pub frametime: Duration,
+    pub target_ms: u64,
+    pub frames: u64,
+    pub elapsed: Duration,
+    pub buildings: usize,
+    pub building_cap: usize,
+}
+
+impl Values {
+    /// One line JSON object, times in milliseconds, for --metrics-socket
+    pub fn to_json(self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        format!("{{\"tick\":{},\"render_ms\":{:.3},\"tick_ms\":{:.3},\"format_ms\":{:.3},\"write_ms\":{:.3},\
+                 \"bytes\":{},\"fps\":{},\"target_ms\":{},\"frames\":{},\"elapsed_s\":{:.3},\
+                 \"buildings\":{},\"building_cap\":{}}}",
+                self.tick, ms(self.frametime), ms(self.tick_time), ms(self.format_time), ms(self.write_time),
+                self.bytes, self.fps, self.target_ms, self.frames, self.elapsed.as_secs_f64(),
+                self.buildings, self.building_cap)
+    }
+}
+
+impl Segment {
+    /// How often the segment is formatted again, zero for every frame
+    fn refresh(self) -> Duration {
+        match self {
+            Segment::Elapsed | Segment::Clock => Duration::from_secs(1),
+            Segment::Buildings => Duration::from_millis(250),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn format(self, v: &Values, out: &mut String) {
+        let tick_w = (TICK_WRAP as f32).log10() as usize + 1;
+        let hms = |secs: u64| (secs / 3600, secs / 60 % 60, secs % 60);
+
+        match self {
+            Segment::Tick => write!(out, "tick: {: >w$}", v.tick, w = tick_w),
+            Segment::TickTime => write!(out, "tick time: {: >4}us", v.tick_time.as_micros()),
+            Segment::Fps => write!(out, "real fps: {: >4}", v.fps),
+            Segment::Frametime => {
+                let us = v.frametime.as_micros();
+                write!(out, "real frametime: {: >4}.{:02}ms", us / 1000, us % 1000 / 10)
+            }
+            Segment::Target => write!(out, "target rametime: {: >4}ms", v.target_ms),
+            Segment::Frames => write!(out, "frames: {: >8}", v.frames),
+            Segment::Elapsed => {
+                let (h, m, s) = hms(v.elapsed.as_secs());
+                write!(out, "elapsed: {: >3}:{:02}:{:02}", h, m, s)
+            }
+            Segment::Clock => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                let (h, m, s) = hms(now.as_secs() % 86400);
+                write!(out, "utc: {:02}:{:02}:{:02}", h, m, s)
+            }
+            Segment::Buildings => write!(out, "buildings: {: >4}/{: <4}", v.buildings, v.building_cap),
+            Segment::Phases => write!(out, "sim/fmt/out: {: >4}/{: >4}/{: >5}us", v.tick_time.as_micros(),
+                                      v.format_time.as_micros(), v.write_time.as_micros()),
+            Segment::Bytes => write!(out, "bytes: {: >7}", v.bytes),
+        }.unwrap()
+    }
+}
+
+impl FromStr for Segment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match NAMES.iter().find(|(name, _)| *name == s) {
+            Some(&(_, segment)) => Ok(segment),
+            None => {
+                let names: Vec<&str> = NAMES.iter().map(|(name, _)| *name).collect();
+                Err(format!("unknown segment '{}', expected one of {}", s, names.join(", ")))
+            }
+        }
+    }
+}
+
+/// Segments of both status lines, parsed from comma lists with `;` between the lines
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segments(pub [Vec<Segment>; 2]);
+
+impl Default for Segments {
+    fn default() -> Self {
+        Segments([
+            vec![Segment::Tick, Segment::TickTime, Segment::Fps],
+            vec![Segment::Frametime, Segment::Target, Segment::Phases],
+        ])
+    }
+}
+
+impl Segments {
+    /// Adds frame count and elapsed time to the first line unless they're shown already
+    pub fn with_elapsed(mut self) -> Segments {
+        for segment in [Segment::Frames, Segment::Elapsed] {
+            if !self.0.iter().flatten().any(|&s| s == segment) {
+                self.0[0].push(segment);
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for Segments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |s: &Segment| NAMES.iter().find(|(_, n)| n == s).map_or("", |(name, _)| name);
+        let [first, second] = &self.0;
+        let line = |segments: &[Segment]| segments.iter().map(name).collect::<Vec<_>>().join(",");
+        write!(f, "{};{}", line(first), line(second))
+    }
+}
+
+impl FromStr for Segments {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = [Vec::new(), Vec::new()];
+        for (i, line) in s.split(';').enumerate() {
+            let segments = lines.get_mut(i).ok_or_else(|| "at most 2 lines".to_string())?;
+            for name in line.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                segments.push(name.parse()?);
+            }
+        }
+        Ok(Segments(lines))
+    }
+}
+
+/// Formats the status lines, keeping each segment's text until it's due for a refresh
+pub struct Status {
+    segments: Segments,
+    cache: Vec<Option<(Instant, String)>>,
+}
+
+impl Status {
+    pub fn new(segments: Segments) -> Status {
+        let count = segments.0.iter().map(Vec::len).sum();
+        Status { segments, cache: vec![None; count] }
+    }
+
+    /// Both lines with segments separated by " / ", a line ends before the first segment
+    /// that would make it longer than `width`
+    pub fn lines(&mut self, width: usize, v: &Values) -> [String; 2] {
+        let now = Instant::now();
+        let mut cached = self.cache.iter_mut();
+        let mut lines = [String::new(), String::new()];
+
+        for (line, segments) in lines.iter_mut().zip(self.segments.0.iter()) {
+            let mut full = false;
+            for (i, &segment) in segments.iter().enumerate() {
+                let slot = cached.next().unwrap();
+                let stale = slot.as_ref().is_none_or(|(at, _)| now.duration_since(*at) >= segment.refresh());
+                if stale {
+                    let mut text = String::new();
+                    segment.format(v, &mut text);
+                    *slot = Some((now, text));
+                }
+
+                let text = &slot.as_ref().unwrap().1;
+                let sep = if i > 0 { " / " } else { "" };
+                full |= str_width(line) + sep.len() + str_width(text) > width;
+                if !full {
+                    line.push_str(sep);
+                    line.push_str(text);
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_round_trip() {
+        let segments: Segments = "fps, clock;buildings".parse().unwrap();
+        assert_eq!(segments.0, [vec![Segment::Fps, Segment::Clock], vec![Segment::Buildings]]);
+        assert_eq!(segments.to_string().parse::<Segments>().unwrap(), segments);
+        assert_eq!(Segments::default().to_string(), "tick,tick-time,fps;frametime,target,phases");
+
+        assert!("fps;clock;tick".parse::<Segments>().is_err());
+        assert!("fps,weather".parse::<Segments>().is_err());
+    }
+
+    #[test]
+    fn narrow_lines_drop_segments() {
+        let values = Values {
+            tick: 1, tick_time: Duration::ZERO, format_time: Duration::ZERO, write_time: Duration::ZERO,
+            bytes: 0, fps: 60, frametime: Duration::ZERO, target_ms: 16, frames: 1, elapsed: Duration::ZERO,
+            buildings: 1, building_cap: 1,
+        };
+        let mut status = Status::new(Segments::default());
+        let [wide, _] = status.lines(usize::MAX, &values);
+        let [narrow, second] = status.lines(30, &values);
+
+        assert!(wide.starts_with(&narrow) && narrow.len() < wide.len());
+        assert!(narrow.starts_with("tick:") && !narrow.ends_with(' '));
+        assert!(narrow.len() <= 30 && second.len() <= 30);
+    }
+
+    #[test]
+    fn metrics_json() {
+        let values = Values {
+            tick: 7, tick_time: Duration::from_micros(1500), format_time: Duration::ZERO, write_time: Duration::ZERO,
+            bytes: 10, fps: 60, frametime: Duration::from_micros(16667), target_ms: 16, frames: 3,
+            elapsed: Duration::from_secs(2), buildings: 12, building_cap: 40,
+        };
+        assert_eq!(values.to_json(), "{\"tick\":7,\"render_ms\":16.667,\"tick_ms\":1.500,\"format_ms\":0.000,\
+                                      \"write_ms\":0.000,\"bytes\":10,\"fps\":60,\"target_ms\":16,\"frames\":3,\
+                                      \"elapsed_s\":2.000,\"buildings\":12,\"building_cap\":40}");
+    }
+
+    #[test]
+    fn widths_dont_change() {
+        let values = |n| Values {
+            tick: n as Tick, tick_time: Duration::from_micros(n), format_time: Duration::from_micros(n),
+            write_time: Duration::from_micros(n), bytes: n as usize, fps: n, frametime: Duration::from_micros(n),
+            target_ms: n, frames: n, elapsed: Duration::from_secs(n), buildings: n as usize,
+            building_cap: n as usize,
+        };
+        for &(_, segment) in NAMES.iter() {
+            let (mut small, mut large) = (String::new(), String::new());
+            segment.format(&values(1), &mut small);
+            segment.format(&values(9999), &mut large);
+            assert_eq!(small.len(), large.len(), "{:?}: '{}' vs '{}'", segment, small, large);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::city::{Cell, PaletteColor};
+use crate::console::{CursorMove, Margins};
+use crate::width;
+use crate::INFO_LINEFEEDS;
+
+const BIG_ROWS: usize = 3;
+const BIG_GLYPH_W: usize = 3;
+const BIG_GLYPH_SPC: usize = 1;
+
+// 3x5 pixel font, printed with half blocks as 3 terminal rows
+const FONT: &[(char, [&str; 5])] = &[
+    ('A', [".#.", "#.#", "###", "#.#", "#.#"]),
+    ('B', ["##.", "#.#", "##.", "#.#", "##."]),
+    ('C', [".##", "#..", "#..", "#..", ".##"]),
+    ('D', ["##.", "#.#", "#.#", "#.#", "##."]),
+    ('E', ["###", "#..", "##.", "#..", "###"]),
+    ('F', ["###", "#..", "##.", "#..", "#.."]),
+    ('G', [".##", "#..", "#.#", "#.#", ".##"]),
+    ('H', ["#.#", "#.#", "###", "#.#", "#.#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..#", "..#", "..#", "#.#", ".#."]),
+    ('K', ["#.#", "#.#", "##.", "#.#", "#.#"]),
+    ('L', ["#..", "#..", "#..", "#..", "###"]),
+    ('M', ["#.#", "###", "###", "#.#", "#.#"]),
+    ('N', ["##.", "#.#", "#.#", "#.#", "#.#"]),
+    ('O', [".#.", "#.#", "#.#", "#.#", ".#."]),
+    ('P', ["##.", "#.#", "##.", "#..", "#.."]),
+    ('Q', [".#.", "#.#", "#.#", "##.", ".##"]),
+    ('R', ["##.", "#.#", "##.", "#.#", "#.#"]),
+    ('S', [".##", "#..", ".#.", "..#", "##."]),
+    ('T', ["###", ".#.", ".#.", ".#.", ".#."]),
+    ('U', ["#.#", "#.#", "#.#", "#.#", "###"]),
+    ('V', ["#.#", "#.#", "#.#", "#.#", ".#."]),
+    ('W', ["#.#", "#.#", "###", "###", "#.#"]),
+    ('X', ["#.#", "#.#", ".#.", "#.#", "#.#"]),
+    ('Y', ["#.#", "#.#", ".#.", ".#.", ".#."]),
+    ('Z', ["###", "..#", ".#.", "#..", "###"]),
+    ('0', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('1', [".#.", "##.", ".#.", ".#.", "###"]),
+    ('2', ["##.", "..#", ".#.", "#..", "###"]),
+    ('3', ["##.", "..#", ".#.", "..#", "##."]),
+    ('4', ["#.#", "#.#", "###", "..#", "..#"]),
+    ('5', ["###", "#..", "##.", "..#", "##."]),
+    ('6', [".##", "#..", "###", "#.#", "###"]),
+    ('7', ["###", "..#", ".#.", ".#.", ".#."]),
+    ('8', ["###", "#.#", "###", "#.#", "###"]),
+    ('9', ["###", "#.#", "###", "..#", "##."]),
+    (' ', ["...", "...", "...", "...", "..."]),
+    ('-', ["...", "...", "###", "...", "..."]),
+    ('.', ["...", "...", "...", "...", ".#."]),
+    (':', ["...", ".#.", "...", ".#.", "..."]),
+    ('!', [".#.", ".#.", ".#.", "...", ".#."]),
+    ('?', ["##.", "..#", ".#.", "...", ".#."]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TitleStyle {
+    Hidden,
+    Text,
+    Big,
+}
+
+#[derive(Debug)]
+pub struct Title {
+    text: String,
+    style: TitleStyle,
+    color: PaletteColor,
+}
+
+impl Title {
+    pub fn new(text: String, style: TitleStyle, color: PaletteColor) -> Title {
+        Title { text, style, color }
+    }
+
+    /// Big title falls back to plain text when it doesn't fit into width
+    fn style_at(&self, width: usize) -> TitleStyle {
+        match self.style {
+            TitleStyle::Big if big_width(self.text.chars().count()) > width => TitleStyle::Text,
+            style => style,
+        }
+    }
+
+    /// Rows taken by the banner itself
+    pub fn banner_lines(&self, width: usize) -> usize {
+        match self.style_at(width) {
+            TitleStyle::Hidden => 0,
+            TitleStyle::Text => 1,
+            TitleStyle::Big => BIG_ROWS,
+        }
+    }
+
+    /// Rows taken by the banner, info line and spacing above the canvas
+    pub fn header_lines(&self, width: usize) -> usize {
+        self.banner_lines(width) + INFO_LINEFEEDS
+    }
+
+    pub fn print(&self, width: usize, margins: &Margins) {
+        let fwd = CursorMove::fwd(margins.left);
+
+        match self.style_at(width) {
+            TitleStyle::Hidden => {}
+            TitleStyle::Text => println!("{}{}", fwd, width::center(&self.text, width)),
+            TitleStyle::Big => {
+                let text_w = big_width(self.text.chars().count());
+                let pad = CursorMove::fwd(margins.left + (width - text_w) / 2);
+                for row in render_big(&self.text) {
+                    println!("{}\x1b[{}m{}\x1b[0m", pad, Cell::fg_of(self.color), row);
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn big_width(chars: usize) -> usize {
+    (chars * (BIG_GLYPH_W + BIG_GLYPH_SPC)).saturating_sub(BIG_GLYPH_SPC)
+}
+
+fn glyph(c: char) -> &'static [&'static str; 5] {
+    let c = c.to_ascii_uppercase();
+    let unknown = &FONT[FONT.len() - 1].1;
+    FONT.iter().find(|(g, _)| *g == c).map(|(_, rows)| rows).unwrap_or(unknown)
+}
+
+fn render_big(text: &str) -> Vec<String> {
+    let mut rows = vec![String::new(); BIG_ROWS];
+    let pixel = |g: &[&str; 5], x: usize, y: usize| g.get(y).is_some_and(|r| r.as_bytes()[x] == b'#');
+
+    for (i, c) in text.chars().enumerate() {
+        let g = glyph(c);
+        for (row_i, row) in rows.iter_mut().enumerate() {
+            if i > 0 {
+                row.push_str(&" ".repeat(BIG_GLYPH_SPC));
+            }
+            for x in 0..BIG_GLYPH_W {
+                let (y_top, y_bottom) = (row_i * 2, row_i * 2 + 1);
+                row.push(match (pixel(g, x, y_top), pixel(g, x, y_bottom)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+        }
+    }
+
+    rows
+}
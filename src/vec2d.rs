@@ -3,6 +3,8 @@ use std::ops::{Index, IndexMut};
 
 pub type IndexXY = (usize, usize);
 
+/// Row-major grid: cell (x, y) is at `y * size_x + x` of one contiguous buffer
+/// holding exactly `size_x * size_y` cells
 #[derive(Debug)]
 pub struct Vec2D<T> {
     data: Vec<T>,
@@ -10,19 +12,43 @@ pub struct Vec2D<T> {
     size_y: usize,
 }
 
+impl<T: Clone> Clone for Vec2D<T> {
+    fn clone(&self) -> Self {
+        Vec2D { data: self.data.clone(), size_x: self.size_x, size_y: self.size_y }
+    }
+
+    // keeps the buffer, for copies taken every frame
+    fn clone_from(&mut self, source: &Self) {
+        self.data.clone_from(&source.data);
+        (self.size_x, self.size_y) = (source.size_x, source.size_y);
+        debug_assert!(self.data.len() == self.size_x * self.size_y);
+    }
+}
+
 impl<T> Vec2D<T> {
     pub fn new(size_x: usize, size_y: usize, init: impl Fn() -> T) -> Vec2D<T> {
         let data = Vec::with_capacity(size_x * size_y);
         let mut vec2d = Vec2D { data, size_x, size_y };
 
         vec2d.fill_with_fn(init);
+        debug_assert!(vec2d.data.len() == size_x * size_y);
         vec2d
     }
 
     pub fn fill_with_fn(&mut self, f: impl Fn() -> T) {
-        let cap = self.data.capacity();
+        // capacity may be larger than requested, length has to match the size
         self.data.clear();
-        self.data.extend(iter::repeat_with(f).take(cap));
+        self.data.extend(iter::repeat_with(f).take(self.size_x * self.size_y));
+        debug_assert!(self.data.len() == self.size_x * self.size_y);
+    }
+
+    /// Whole buffer in row-major order with its (size_x, size_y)
+    pub fn as_flat_slice(&self) -> (&[T], IndexXY) {
+        (&self.data, (self.size_x, self.size_y))
+    }
+
+    pub fn as_flat_slice_mut(&mut self) -> (&mut [T], IndexXY) {
+        (&mut self.data, (self.size_x, self.size_y))
     }
 
     pub fn get_row(&self, y: usize) -> &[T] {
@@ -36,13 +62,23 @@ impl<T> Vec2D<T> {
     pub fn row_iter(&self) -> impl Iterator<Item=&[T]> {
         (0..self.size_y).map(move |y| self.get_row(y))
     }
-}
 
-impl<T: Copy> Vec2D<T> {
-    pub fn fill_with(&mut self, f: T) {
-        let cap = self.data.capacity();
-        self.data.clear();
-        self.data.extend(iter::repeat(f).take(cap));
+    /// Cells of column x from top to bottom
+    pub fn col_iter(&self, x: usize) -> impl Iterator<Item=&T> {
+        assert!(x < self.size_x, "Column out of range");
+        self.data.chunks_exact(self.size_x).map(move |row| &row[x])
+    }
+
+    pub fn col_iter_mut(&mut self, x: usize) -> impl Iterator<Item=&mut T> {
+        assert!(x < self.size_x, "Column out of range");
+        self.data.chunks_exact_mut(self.size_x).map(move |row| &mut row[x])
+    }
+
+    /// First row matching `pred` in each column, size_y if none does
+    pub fn column_heights(&self, pred: impl Fn(&T) -> bool) -> Vec<usize> {
+        (0..self.size_x)
+            .map(|x| self.col_iter(x).position(&pred).unwrap_or(self.size_y))
+            .collect()
     }
 }
 
@@ -64,3 +100,141 @@ impl<T> IndexMut<IndexXY> for Vec2D<T> {
         &mut self.data[raw_idx(self.size_x, idx)]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_slice_is_row_major() {
+        let mut v = Vec2D::new(7, 3, || (0, 0));
+        for y in 0..3 {
+            for x in 0..7 {
+                v[(x, y)] = (x, y);
+            }
+        }
+
+        let (flat, (sx, sy)) = v.as_flat_slice();
+        assert_eq!((sx, sy), (7, 3));
+        assert_eq!(flat.len(), sx * sy);
+        assert!(flat.iter().enumerate().all(|(i, &(x, y))| i == y * sx + x));
+        assert!(v.row_iter().flatten().eq(flat.iter()));
+    }
+
+    #[test]
+    fn columns() {
+        let mut v = Vec2D::new(4, 3, || 0);
+        v.col_iter_mut(2).enumerate().for_each(|(y, c)| *c = y + 1);
+        v[(0, 1)] = 9;
+
+        assert!(v.col_iter(2).copied().eq(1..=3));
+        assert!(v.col_iter(3).all(|&c| c == 0));
+        assert_eq!(v.column_heights(|&c| c > 0), vec![1, 3, 0, 3]);
+    }
+
+    #[test]
+    fn new_calls_init_per_cell() {
+        let calls = std::cell::Cell::new(0);
+        let v = Vec2D::new(3, 2, || {
+            calls.set(calls.get() + 1);
+            calls.get()
+        });
+        assert_eq!(calls.get(), 6);
+        assert_eq!(v.as_flat_slice().0, &[1, 2, 3, 4, 5, 6][..]);
+    }
+
+    #[test]
+    fn index_corners_and_center() {
+        let mut v = Vec2D::new(5, 3, || '.');
+        for &(x, y) in &[(0, 0), (4, 0), (2, 1), (0, 2), (4, 2)] {
+            v[(x, y)] = '#';
+        }
+
+        assert_eq!((v[(0, 0)], v[(4, 2)], v[(2, 1)], v[(1, 1)]), ('#', '#', '#', '.'));
+        let rows: Vec<String> = v.row_iter().map(|r| r.iter().collect()).collect();
+        assert_eq!(rows, ["#...#", "..#..", "#...#"]);
+    }
+
+    #[test]
+    fn rows() {
+        let mut v = Vec2D::new(4, 3, || 0);
+        v.get_row_mut(1).copy_from_slice(&[1, 2, 3, 4]);
+        v.get_row_mut(2)[3] = 5;
+
+        assert_eq!(v.get_row(0), &[0; 4]);
+        assert_eq!(v.get_row(1), &[1, 2, 3, 4]);
+        assert_eq!(v[(3, 2)], 5);
+        assert!(v.row_iter().map(|r| r.len()).eq([4, 4, 4]));
+        assert!(v.row_iter().enumerate().all(|(y, r)| r == v.get_row(y)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_past_last_row_panics() {
+        let v = Vec2D::new(4, 3, || 0);
+        let _ = v[(0, 3)];
+    }
+
+    #[test]
+    #[should_panic]
+    fn row_past_last_panics() {
+        let mut v = Vec2D::new(4, 3, || 0);
+        v.get_row_mut(3);
+    }
+
+    #[test]
+    fn fills_keep_length() {
+        let mut v = Vec2D::new(5, 4, || 1u8);
+        v[(4, 3)] = 2;
+        v.fill_with_fn(|| 3);
+        assert_eq!(v.as_flat_slice().0, &[3; 20][..]);
+
+        let empty = Vec2D::new(0, 4, || 1u8);
+        assert!(empty.as_flat_slice().0.is_empty());
+    }
+
+    #[test]
+    fn refill_after_shrinking() {
+        // a buffer that held a bigger grid keeps its capacity
+        let mut v = Vec2D::new(9, 7, || 0u8);
+        (v.size_x, v.size_y) = (2, 3);
+        v.fill_with_fn(|| 1);
+        assert!(v.data.capacity() > 6);
+        assert_eq!(v.as_flat_slice(), (&[1; 6][..], (2, 3)));
+        v[(1, 2)] = 2;
+        assert_eq!(v.get_row(2), &[1, 2]);
+    }
+
+    #[test]
+    fn clone_from_other_size() {
+        let mut v = Vec2D::new(9, 7, || 0u8);
+        v.clone_from(&Vec2D::new(2, 3, || 1));
+        assert_eq!(v.as_flat_slice(), (&[1; 6][..], (2, 3)));
+        v.clone_from(&Vec2D::new(4, 5, || 2));
+        assert_eq!(v.get_row(4), &[2; 4]);
+    }
+
+    #[test]
+    fn single_row_and_column() {
+        let mut row = Vec2D::new(5, 1, || 0);
+        row[(4, 0)] = 1;
+        assert_eq!(row.get_row(0), &[0, 0, 0, 0, 1]);
+        assert_eq!(row.row_iter().count(), 1);
+
+        let mut col = Vec2D::new(1, 5, || 0);
+        col[(0, 4)] = 1;
+        assert_eq!(col.get_row(4), &[1]);
+        assert!(col.row_iter().map(|r| r[0]).eq([0, 0, 0, 0, 1].iter().copied()));
+    }
+
+    #[test]
+    fn last_row_of_odd_sizes() {
+        for &(w, h) in &[(3, 7), (7, 3), (13, 11)] {
+            let mut v = Vec2D::new(w, h, || 0);
+            v.get_row_mut(h - 1).iter_mut().enumerate().for_each(|(x, c)| *c = x + 1);
+            assert!(v.get_row(h - 1).iter().copied().eq(1..=w));
+            assert_eq!(v[(w - 1, h - 1)], w);
+            assert!(v.get_row(h - 2).iter().all(|&c| c == 0));
+        }
+    }
+}
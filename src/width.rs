@@ -0,0 +1,163 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// Characters printed instead of control characters, which would move the cursor
+pub const REPLACEMENT: char = '\u{fffd}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+
+/// Terminal cells a character takes: 2 for wide East Asian characters and emoji, 0 for ones
+/// drawn over the character before them, None for control characters
+pub fn char_width(c: char) -> Option<usize> {
+    match c {
+        _ if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => None,
+        _ => c.width(),
+    }
+}
+
+/// Cells a grapheme cluster (a character and the ones drawn with it) takes: those of its first
+/// character, 2 for ones shown as emoji by a variation selector and for flags, which are pairs
+/// of regional indicators. None for control characters, they're clusters of their own
+pub fn grapheme_width(g: &str) -> Option<usize> {
+    let mut chars = g.chars();
+    let first = char_width(chars.next()?)?;
+    let flag = g.chars().count() == 2 && g.chars().all(|c| ('\u{1f1e6}'..='\u{1f1ff}').contains(&c));
+    match flag || (first > 0 && chars.any(|c| c == EMOJI_PRESENTATION)) {
+        true => Some(2),
+        false => Some(first),
+    }
+}
+
+/// Cells a string takes, control characters are counted as the replacement they're printed as
+pub fn str_width(s: &str) -> usize {
+    s.graphemes(true).map(|g| grapheme_width(g).unwrap_or_else(|| g.chars().count())).sum()
+}
+
+/// Longest start of `s` taking at most `width` cells, with control characters replaced.
+/// Clusters are kept whole, a wide one that would only fit halfway is left out
+pub fn truncate(s: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for g in s.graphemes(true) {
+        let w = grapheme_width(g);
+        let cells = w.unwrap_or_else(|| g.chars().count());
+        if used + cells > width {
+            break;
+        }
+        match w {
+            Some(_) => out.push_str(g),
+            None => out.extend(g.chars().map(|_| REPLACEMENT)),
+        }
+        used += cells;
+    }
+    out
+}
+
+/// `s` if it fits into `width` cells, else its start ending with '…'
+pub fn ellipsize(s: &str, width: usize) -> String {
+    match str_width(s) <= width {
+        true => truncate(s, width),
+        false if width == 0 => String::new(),
+        false => truncate(s, width - 1) + "…",
+    }
+}
+
+/// Text fitted into `width` cells and padded with spaces on both sides, the extra one goes right
+pub fn center(s: &str, width: usize) -> String {
+    let s = ellipsize(s, width);
+    let free = width - str_width(&s);
+    format!("{:l$}{}{:r$}", "", s, "", l = free / 2, r = free - free / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::CursorMove;
+
+    #[test]
+    fn cells_of_mixed_text() {
+        assert_eq!(str_width("city"), 4);
+        assert_eq!(str_width("東京タワー"), 10);
+        assert_eq!(str_width("🌃 night"), 8);
+        assert_eq!(str_width("cafe\u{301}"), 4);
+        assert_eq!(char_width('\x1b'), None);
+        assert_eq!(char_width('\u{200d}'), Some(0));
+        assert_eq!(char_width('ｃ'), Some(2));
+
+        // clusters take the cells of how they're drawn, not the sum of their characters
+        assert_eq!(str_width("👨\u{200d}👩\u{200d}👧 family"), 9);
+        assert_eq!(str_width("🇯🇵🇫🇷"), 4);
+        assert_eq!(str_width("\u{2764}\u{fe0f}"), 2);
+        assert_eq!(str_width("\u{2764}"), 1);
+        assert_eq!(str_width("a\r\nb"), 4);
+        assert_eq!(grapheme_width("\u{301}"), Some(0));
+    }
+
+    #[test]
+    fn wide_characters_arent_split() {
+        assert_eq!(truncate("ab東京", 3), "ab");
+        assert_eq!(truncate("a東b", 3), "a東");
+        assert_eq!(truncate("e\u{301}x", 1), "e\u{301}");
+        assert_eq!(truncate("a\tb\x1b[2J", 5), "a\u{fffd}b\u{fffd}[");
+        assert_eq!(truncate("👨\u{200d}👩\u{200d}👧x", 2), "👨\u{200d}👩\u{200d}👧");
+        assert_eq!(truncate("a👨\u{200d}👩", 2), "a");
+        assert_eq!(truncate("🇯🇵🇫🇷", 3), "🇯🇵");
+
+        assert_eq!(ellipsize("東京タワー", 5), "東京…");
+        assert_eq!(ellipsize("東京タワー", 6), "東京…");
+        assert_eq!(ellipsize("東京", 4), "東京");
+        assert_eq!(ellipsize("東京", 0), "");
+    }
+
+    #[test]
+    fn centered_text_fills_the_width() {
+        for text in ["CiTY", "東京の夜景", "🌃🏙 skyline 🌆", "mixed 漢字 and ascii", "tab\there"] {
+            for width in 0..24 {
+                let line = center(text, width);
+                assert_eq!(str_width(&line), width, "{:?} in {}", text, width);
+                assert!(line.chars().all(|c| char_width(c).is_some()), "{:?}", line);
+            }
+        }
+        assert_eq!(center("東京", 7), " 東京  ");
+    }
+
+    /// Column the cursor ends at after `line`, moved by cursor forward escapes, carriage returns
+    /// and by text the width of each cluster, like a terminal draws it. Other escapes don't move it
+    fn cursor_col(line: &str) -> usize {
+        let mut col = 0;
+        let mut rest = line;
+        while !rest.is_empty() {
+            if let Some(esc) = rest.strip_prefix("\x1b[") {
+                let end = esc.find(|c: char| c.is_ascii_alphabetic()).unwrap();
+                if esc.as_bytes()[end] == b'C' {
+                    col += esc[..end].parse::<usize>().unwrap_or(1);
+                }
+                rest = &esc[end + 1..];
+                continue;
+            }
+            if let Some(after) = rest.strip_prefix('\r') {
+                col = 0;
+                rest = after;
+                continue;
+            }
+            let g = rest.graphemes(true).next().unwrap();
+            col += grapheme_width(g).expect("control characters are replaced");
+            rest = &rest[g.len()..];
+        }
+        col
+    }
+
+    #[test]
+    fn ansi_lines_stay_aligned() {
+        let texts = ["CiTY", "東京 night 🌃", "👨\u{200d}👩\u{200d}👧 at 🇯🇵 cafe\u{301}", "漢字\tand\x1b[2J"];
+        for text in texts {
+            for width in 0..30 {
+                // centered like the title and truncated like the status line, after the margin
+                let title = format!("{}\x1b[1m{}\x1b[0m", CursorMove::fwd(3), center(text, width));
+                assert_eq!(cursor_col(&title), 3 + width, "{:?} in {}", text, width);
+                let status = format!("\r\x1b[0m{}{}\x1b[K", CursorMove::fwd(3), truncate(text, width));
+                let cells = cursor_col(&status) - 3;
+                assert!(cells <= width && cells + 1 >= width.min(str_width(text)), "{:?} in {}", text, width);
+            }
+        }
+    }
+}